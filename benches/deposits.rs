@@ -0,0 +1,142 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use transaction_processor::config::Config;
+use transaction_processor::process_transaction::process_transaction;
+use transaction_processor::store::{AccountStore, DenseAccounts};
+use transaction_processor::types::{Account, Accounts, Deposit, Money, Transactions, TX};
+use transaction_processor::Engine;
+
+const DEPOSITS: u32 = 100_000;
+const CLIENTS: u32 = 1_000;
+
+/// Deposits spread over a small set of clients, so nearly every deposit hits
+/// an existing account.
+fn deposit_stream() -> Vec<TX> {
+    (0..DEPOSITS)
+        .map(|tx| {
+            TX::Deposit(Deposit {
+                client: (tx % CLIENTS) as u16,
+                tx,
+                amount: Money::from_raw(15_000),
+            })
+        })
+        .collect()
+}
+
+fn bench_deposits(c: &mut Criterion) {
+    let stream = deposit_stream();
+    let mut group = c.benchmark_group("deposits");
+    group.throughput(Throughput::Elements(DEPOSITS as u64));
+    group.bench_function("existing_accounts", |b| {
+        b.iter(|| {
+            let mut engine = Engine::with_capacity(Default::default(), DEPOSITS as usize);
+            for tx in stream.iter().cloned() {
+                engine.process(tx).unwrap();
+            }
+            black_box(engine.accounts().len())
+        })
+    });
+    group.bench_function("or_insert_baseline", |b| {
+        b.iter(|| {
+            black_box(credit(&stream, |accounts, client| {
+                accounts.entry(client).or_insert(Account::default())
+            }))
+        })
+    });
+    group.bench_function("or_insert_with", |b| {
+        b.iter(|| {
+            black_box(credit(&stream, |accounts, client| {
+                accounts.entry(client).or_default()
+            }))
+        })
+    });
+    group.finish();
+}
+
+/// Credits every deposit to the account `entry` looks up. The baseline builds
+/// a default `Account` for every deposit, as the deposit path did before
+/// switching to a lazily built one.
+fn credit(stream: &[TX], entry: fn(&mut Accounts, u16) -> &mut Account) -> usize {
+    let mut accounts = Accounts::new();
+    for tx in stream {
+        if let TX::Deposit(deposit) = tx {
+            let account = entry(&mut accounts, deposit.client);
+            account.available = account.available.checked_add(deposit.amount).unwrap();
+            account.total = account.total.checked_add(deposit.amount).unwrap();
+        }
+    }
+    accounts.len()
+}
+
+/// Every deposit of a single client, the case where the transaction map
+/// grows the most for the accounts it holds.
+fn single_client_stream() -> Vec<TX> {
+    (0..DEPOSITS)
+        .map(|tx| {
+            TX::Deposit(Deposit {
+                client: 1,
+                tx,
+                amount: Money::from_raw(15_000),
+            })
+        })
+        .collect()
+}
+
+fn run(mut engine: Engine, stream: &[TX]) -> usize {
+    for tx in stream.iter().cloned() {
+        engine.process(tx).unwrap();
+    }
+    engine.transactions().len()
+}
+
+/// An engine pre-sized with `Engine::with_capacity` against one whose
+/// transaction map grows as it goes.
+fn bench_capacity_hint(c: &mut Criterion) {
+    let stream = single_client_stream();
+    let mut group = c.benchmark_group("capacity_hint");
+    group.throughput(Throughput::Elements(DEPOSITS as u64));
+    group.bench_function("with_capacity", |b| {
+        b.iter(|| {
+            let engine = Engine::with_capacity(Config::default(), DEPOSITS as usize);
+            black_box(run(engine, &stream))
+        })
+    });
+    group.bench_function("no_hint", |b| {
+        b.iter(|| black_box(run(Engine::new(Config::default()), &stream)))
+    });
+    group.finish();
+}
+
+fn process_into<A: AccountStore>(accounts: &mut A, stream: &[TX]) -> usize {
+    let config = Config::default();
+    let mut transactions = Transactions::with_capacity(stream.len());
+    for tx in stream.iter().cloned() {
+        process_transaction(tx, accounts, &mut transactions, &config).unwrap();
+    }
+    accounts.account_count()
+}
+
+/// The same dense client ids kept in an `Accounts` map and in a
+/// `DenseAccounts` vector.
+fn bench_account_store(c: &mut Criterion) {
+    let stream = deposit_stream();
+    let mut group = c.benchmark_group("account_store");
+    group.throughput(Throughput::Elements(DEPOSITS as u64));
+    group.bench_function("map", |b| {
+        b.iter(|| black_box(process_into(&mut Accounts::new(), &stream)))
+    });
+    group.bench_function("dense", |b| {
+        b.iter(|| {
+            let mut accounts = DenseAccounts::with_range(CLIENTS as usize);
+            black_box(process_into(&mut accounts, &stream))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_deposits,
+    bench_capacity_hint,
+    bench_account_store
+);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use transaction_processor::types::{Deposit, Dispute, Money, Withdrawal, TX};
+use transaction_processor::Engine;
+
+const ROWS: u32 = 2_000_000;
+const CLIENTS: u32 = 10_000;
+
+/// Deposits with a withdrawal and a dispute mixed in every few rows.
+fn stream() -> Vec<TX> {
+    (0..ROWS)
+        .map(|tx| {
+            let client = (tx % CLIENTS) as u16;
+            match tx % 10 {
+                7 => TX::Withdrawal(Withdrawal {
+                    client,
+                    tx,
+                    amount: Money::from_raw(5_000),
+                    destination: None,
+                }),
+                9 => TX::Dispute(Dispute {
+                    client,
+                    tx: tx - 9,
+                    amount: None,
+                }),
+                _ => TX::Deposit(Deposit {
+                    client,
+                    tx,
+                    amount: Money::from_raw(15_000),
+                }),
+            }
+        })
+        .collect()
+}
+
+fn bench_parallel(c: &mut Criterion) {
+    let stream = stream();
+    let mut group = c.benchmark_group("parallel");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            let mut engine = Engine::default();
+            black_box(engine.process_batch(stream.iter().cloned()).processed)
+        })
+    });
+    for threads in [2, 4, 8] {
+        group.bench_function(format!("threads_{}", threads), |b| {
+            b.iter(|| {
+                let mut engine = Engine::default();
+                black_box(
+                    engine
+                        .process_parallel(stream.iter().cloned(), threads)
+                        .processed,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parallel);
+criterion_main!(benches);
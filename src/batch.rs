@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::store::AccountStore;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatchMarker {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+impl BatchMarker {
+    pub fn parse(field: &[u8]) -> Option<Self> {
+        let field = String::from_utf8_lossy(field);
+        match field.trim().to_lowercase().as_str() {
+            "begin" => Some(BatchMarker::Begin),
+            "commit" => Some(BatchMarker::Commit),
+            "rollback" => Some(BatchMarker::Rollback),
+            _ => None,
+        }
+    }
+}
+
+/// Pre-batch images of every account and transaction touched inside a batch,
+/// recorded the first time each is touched so a rollback can restore them.
+#[derive(Debug, Default)]
+pub struct Journal {
+    accounts: HashMap<u16, Option<Account>>,
+    transactions: HashMap<TxKey, Option<TXState>>,
+    /// Length of the engine's chargeback log when the batch started.
+    chargebacks: Option<usize>,
+}
+
+impl Journal {
+    pub fn record<A: AccountStore>(
+        &mut self,
+        tx: &TX,
+        accounts: &A,
+        transactions: &Transactions,
+        config: &Config,
+    ) {
+        let key = config.tx_key(tx.client(), tx.tx());
+        self.accounts
+            .entry(tx.client())
+            .or_insert_with(|| accounts.account(tx.client()).cloned());
+        self.transactions
+            .entry(key)
+            .or_insert_with(|| transactions.get(&key).cloned());
+    }
+
+    /// Notes the chargeback log length the first time it's called, so a
+    /// rollback can drop the entries the batch added.
+    pub fn record_chargebacks(&mut self, logged: usize) {
+        self.chargebacks.get_or_insert(logged);
+    }
+
+    pub fn chargebacks(&self) -> Option<usize> {
+        self.chargebacks
+    }
+
+    pub fn rollback<A: AccountStore>(self, accounts: &mut A, transactions: &mut Transactions) {
+        for (client, account) in self.accounts {
+            match account {
+                Some(account) => accounts.insert_account(client, account),
+                None => {
+                    accounts.remove_account(client);
+                }
+            }
+        }
+        for (key, state) in self.transactions {
+            match state {
+                Some(state) => transactions.insert(key, state),
+                None => transactions.remove(&key),
+            };
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub enum Batch {
+    #[default]
+    Idle,
+    Open(Journal),
+    /// An error rolled the batch back; rows are skipped until its end marker.
+    Aborted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_marker_parse() {
+        assert_eq!(BatchMarker::parse(b" BEGIN "), Some(BatchMarker::Begin));
+        assert_eq!(BatchMarker::parse(b"commit"), Some(BatchMarker::Commit));
+        assert_eq!(BatchMarker::parse(b"Rollback"), Some(BatchMarker::Rollback));
+        assert_eq!(BatchMarker::parse(b"deposit"), None);
+    }
+
+    #[test]
+    fn test_journal_rollback() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.0),
+                held: Money::ZERO,
+                total: Money::from(1.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+        let mut transactions = Transactions::new();
+
+        let mut journal = Journal::default();
+        let deposit = TX::Deposit(Deposit {
+            client: 1,
+            tx: 2,
+            amount: Money::from(1.0),
+        });
+        journal.record(&deposit, &accounts, &transactions, &Config::default());
+        accounts.get_mut(&1).unwrap().available = Money::from(2.0);
+        transactions.insert(
+            2.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 1,
+                amount: Money::from(1.0),
+                disputed: false,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+        let deposit = TX::Deposit(Deposit {
+            client: 2,
+            tx: 3,
+            amount: Money::from(1.0),
+        });
+        journal.record(&deposit, &accounts, &transactions, &Config::default());
+        accounts.insert(
+            2,
+            Account {
+                available: Money::from(1.0),
+                held: Money::ZERO,
+                total: Money::from(1.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        journal.rollback(&mut accounts, &mut transactions);
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts.get(&1).unwrap().available, Money::from(1.0));
+        assert!(transactions.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::types::{Account, Money, TXType, TxKey};
+
+/// What to do when disputing a deposit whose funds were already withdrawn,
+/// i.e. when `available` is lower than the disputed amount.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DisputeOverdrawPolicy {
+    /// Reject the dispute with `TXError::NotEnoughFunds`.
+    #[default]
+    Strict,
+    /// Hold the full amount and let `available` go negative.
+    Permissive,
+}
+
+impl FromStr for DisputeOverdrawPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "strict" => Ok(DisputeOverdrawPolicy::Strict),
+            "permissive" => Ok(DisputeOverdrawPolicy::Permissive),
+            _ => Err(format!("Invalid dispute overdraw policy: {}", s)),
+        }
+    }
+}
+
+/// What to do when a chargeback would take `total` below `Config::total_floor`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TotalFloorPolicy {
+    /// Reject the chargeback with `TXError::TotalBelowFloor`.
+    #[default]
+    Reject,
+    /// Apply the chargeback but write off the shortfall, leaving `total` at
+    /// the floor.
+    Clamp,
+}
+
+impl FromStr for TotalFloorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "reject" => Ok(TotalFloorPolicy::Reject),
+            "clamp" => Ok(TotalFloorPolicy::Clamp),
+            _ => Err(format!("Invalid total floor policy: {}", s)),
+        }
+    }
+}
+
+/// Which transactions a locked account still accepts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LockedPolicy {
+    /// Reject every transaction with `TXError::AccountLocked`.
+    #[default]
+    BlockAll,
+    /// Reject deposits and withdrawals but keep honoring disputes, resolves
+    /// and chargebacks.
+    AllowDisputeFlow,
+}
+
+impl FromStr for LockedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "block-all" => Ok(LockedPolicy::BlockAll),
+            "allow-dispute-flow" => Ok(LockedPolicy::AllowDisputeFlow),
+            _ => Err(format!("Invalid locked policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub dispute_overdraw_policy: DisputeOverdrawPolicy,
+    /// Maximum cumulative amount a client may deposit.
+    pub deposit_limit: Option<Money>,
+    /// Largest `available`, `held` or `total` a deposit or dispute may
+    /// leave on an account.
+    pub max_balance: Option<Money>,
+    /// Lowest `total` a chargeback may leave behind.
+    pub total_floor: Option<Money>,
+    pub total_floor_policy: TotalFloorPolicy,
+    /// Lets locked accounts resolve their outstanding disputes, releasing
+    /// the held funds back to available.
+    pub resolve_when_locked: bool,
+    pub locked_policy: LockedPolicy,
+    /// Transaction ids are only unique per client, so transactions are
+    /// stored under `(client, tx)` instead of `tx`.
+    pub per_client_tx_ids: bool,
+    /// Transaction types skipped entirely.
+    pub disabled: HashSet<TXType>,
+    /// Clients under a regulatory hold who can't open new disputes.
+    pub disputes_frozen: HashSet<u16>,
+    /// Maximum number of transactions a client may have under dispute at
+    /// once.
+    pub max_open_disputes: Option<u32>,
+    /// Unlocking an account also resolves its open disputes, releasing the
+    /// held funds back to available.
+    pub unlock_resolves_disputes: bool,
+    /// Records on each account the ids of the transactions contributing to
+    /// its balance, see `Account::tx_ids`.
+    pub track_provenance: bool,
+    /// Resolved transactions stay stored, undisputed, so they can be
+    /// disputed again. By default a resolve forgets the transaction.
+    pub redispute_resolved: bool,
+    /// Rejects chargebacks whose transaction wasn't disputed earlier in the
+    /// engine's stream, including disputes only flagged by a loaded
+    /// snapshot.
+    pub validate_chargeback_order: bool,
+    /// Removes an unlocked account once its available, held and total are
+    /// all zero. Its stored transactions are kept, so their ids stay taken.
+    /// A later deposit creates it again, with its counters and
+    /// `deposit_limit` usage starting over.
+    pub remove_zero_accounts: bool,
+    /// Called once per client, when a deposit creates its account.
+    pub on_account_created: Option<AccountCreated>,
+}
+
+/// Receives the client id and its new account, with the first deposit
+/// applied.
+pub type AccountCreated = fn(u16, &Account);
+
+impl Config {
+    /// Key under which `client`'s transaction `tx` is stored.
+    pub fn tx_key(&self, client: u16, tx: u32) -> TxKey {
+        TxKey {
+            client: self.per_client_tx_ids.then_some(client),
+            tx,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispute_overdraw_policy_from_str() {
+        assert_eq!(
+            "strict".parse::<DisputeOverdrawPolicy>(),
+            Ok(DisputeOverdrawPolicy::Strict)
+        );
+        assert_eq!(
+            " Permissive ".parse::<DisputeOverdrawPolicy>(),
+            Ok(DisputeOverdrawPolicy::Permissive)
+        );
+        assert!("lenient".parse::<DisputeOverdrawPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_locked_policy_from_str() {
+        assert_eq!(
+            "block-all".parse::<LockedPolicy>(),
+            Ok(LockedPolicy::BlockAll)
+        );
+        assert_eq!(
+            "Allow-Dispute-Flow".parse::<LockedPolicy>(),
+            Ok(LockedPolicy::AllowDisputeFlow)
+        );
+        assert!("allow".parse::<LockedPolicy>().is_err());
+    }
+}
@@ -0,0 +1,779 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::thread;
+
+use crate::batch::Journal;
+use crate::config::Config;
+use crate::process_transaction::process_transaction;
+use crate::report::ChargebackRecord;
+use crate::store::AccountStore;
+use crate::transactions::unlock;
+use crate::types::*;
+
+/// A transaction of a batch that failed, with its position in the batch.
+#[derive(Debug, PartialEq)]
+pub struct ProcessError {
+    pub index: usize,
+    pub error: TXError,
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "transaction {}: {}", self.index, self.error)
+    }
+}
+
+impl std::error::Error for ProcessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchReport {
+    pub processed: usize,
+    pub errors: Vec<ProcessError>,
+}
+
+/// Applies transactions to the accounts kept in `A`, a map by default.
+#[derive(Debug)]
+pub struct Engine<A = Accounts> {
+    accounts: A,
+    transactions: Transactions,
+    config: Config,
+    chargebacks: Vec<ChargebackRecord>,
+    /// Transactions passed to `process` so far, the position of the next
+    /// one in the stream.
+    sequence: u64,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl Engine {
+    pub fn new(config: Config) -> Self {
+        Self::with_state(config, Accounts::new(), Transactions::new())
+    }
+
+    /// Pre-sizes the transaction map for `expected_transactions` entries so
+    /// long runs don't stall on repeated rehashing.
+    pub fn with_capacity(config: Config, expected_transactions: usize) -> Self {
+        Self::with_state(
+            config,
+            Accounts::new(),
+            Transactions::with_capacity(expected_transactions),
+        )
+    }
+}
+
+impl<A: AccountStore> Engine<A> {
+    /// Resumes from previously computed state, e.g. a loaded snapshot, or
+    /// starts from an empty store of another kind.
+    pub fn with_state(config: Config, accounts: A, transactions: Transactions) -> Self {
+        Self {
+            accounts,
+            transactions,
+            config,
+            chargebacks: Vec::new(),
+            sequence: 0,
+        }
+    }
+
+    pub fn into_state(self) -> (A, Transactions) {
+        (self.accounts, self.transactions)
+    }
+
+    /// Grows the transaction map to hold at least `expected_transactions`.
+    pub fn reserve(&mut self, expected_transactions: usize) {
+        self.transactions
+            .reserve(expected_transactions.saturating_sub(self.transactions.len()));
+    }
+
+    pub fn process(&mut self, tx: TX) -> Result<(), TXError> {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        let key = self.config.tx_key(tx.client(), tx.tx());
+        if let (true, TX::Chargeback(op)) = (self.config.validate_chargeback_order, &tx) {
+            // Unknown transactions and other clients' are left for the
+            // chargeback itself to report.
+            let undisputed = self
+                .transactions
+                .get(&key)
+                .is_some_and(|state| state.client == op.client && state.disputed_at.is_none());
+            if undisputed {
+                return Err(TXError::ChargebackOutOfOrder(tx));
+            }
+        }
+        // The chargeback removes the stored transaction, read it first.
+        let charged_back = match &tx {
+            TX::Chargeback(op) => self
+                .transactions
+                .get(&key)
+                .map(|state| (op.client, op.tx, state.kind, state.held_amount())),
+            _ => None,
+        };
+        let disputed = matches!(tx, TX::Dispute(_));
+        process_transaction(tx, &mut self.accounts, &mut self.transactions, &self.config)?;
+        if let (true, Some(state)) = (disputed, self.transactions.get_mut(&key)) {
+            state.disputed_at = Some(sequence);
+        }
+        if let Some((client, tx, kind, amount)) = charged_back {
+            self.chargebacks.push(ChargebackRecord {
+                client,
+                tx,
+                kind,
+                amount,
+                locked: self.accounts.account(client).is_some_and(|acc| acc.locked),
+            });
+        }
+        Ok(())
+    }
+
+    /// Computes what `tx` would turn its client's account into, without
+    /// applying it. Only the client's account and the referenced
+    /// transaction are cloned.
+    pub fn dry_apply(&self, tx: TX) -> Result<Account, TXError> {
+        let client = tx.client();
+        let key = self.config.tx_key(client, tx.tx());
+        let mut accounts: Accounts = self
+            .accounts
+            .account(client)
+            .map(|account| (client, account.clone()))
+            .into_iter()
+            .collect();
+        let mut transactions: Transactions = self
+            .transactions
+            .get_key_value(&key)
+            .map(|(key, state)| (*key, state.clone()))
+            .into_iter()
+            .collect();
+        // Nothing is created for real, so don't announce it.
+        let quiet;
+        let config = match self.config.on_account_created {
+            Some(_) => {
+                quiet = Config {
+                    on_account_created: None,
+                    ..self.config.clone()
+                };
+                &quiet
+            }
+            None => &self.config,
+        };
+        process_transaction(tx, &mut accounts, &mut transactions, config)?;
+        Ok(accounts.remove(&client).unwrap_or_default())
+    }
+
+    /// Whether `tx` is a deposit or withdrawal whose id is already in the
+    /// transaction store.
+    pub fn is_recorded(&self, tx: &TX) -> bool {
+        !tx.is_dispute_flow()
+            && self
+                .transactions
+                .contains_key(&self.config.tx_key(tx.client(), tx.tx()))
+    }
+
+    /// Records in `journal` the state `tx` is about to touch, so the batch
+    /// it belongs to can be rolled back.
+    pub fn journal(&self, journal: &mut Journal, tx: &TX) {
+        journal.record(tx, &self.accounts, &self.transactions, &self.config);
+        journal.record_chargebacks(self.chargebacks.len());
+    }
+
+    pub fn rollback(&mut self, journal: Journal) {
+        if let Some(logged) = journal.chargebacks() {
+            self.chargebacks.truncate(logged);
+        }
+        journal.rollback(&mut self.accounts, &mut self.transactions);
+    }
+
+    /// Processes every transaction, collecting the failures in the report.
+    pub fn process_batch(&mut self, txs: impl IntoIterator<Item = TX>) -> BatchReport {
+        let errors = RefCell::new(Vec::new());
+        let processed = self.process_batch_with(txs, |err| {
+            errors.borrow_mut().push(ProcessError {
+                index: err.index,
+                error: err.error.clone(),
+            })
+        });
+        BatchReport {
+            processed,
+            errors: errors.into_inner(),
+        }
+    }
+
+    /// Processes every transaction, handing each failure to `on_error` as it
+    /// happens instead of collecting it. Returns how many succeeded.
+    pub fn process_batch_with<F>(&mut self, txs: impl IntoIterator<Item = TX>, on_error: F) -> usize
+    where
+        F: Fn(&ProcessError),
+    {
+        let mut processed = 0;
+        for (index, tx) in txs.into_iter().enumerate() {
+            match self.process(tx) {
+                Ok(()) => processed += 1,
+                Err(error) => on_error(&ProcessError { index, error }),
+            }
+        }
+        processed
+    }
+
+    /// Processes `txs` on `threads` worker threads. Transactions are routed
+    /// by client, each worker owning the accounts and stored transactions
+    /// of its clients, so every client's transactions still apply in input
+    /// order and the balances match the serial path.
+    ///
+    /// Workers only see their own clients' transaction ids. When two
+    /// clients name the same id, in `txs` or through a stored transaction,
+    /// the whole of `txs` is processed serially instead, so the first
+    /// successful use of an id still wins.
+    pub fn process_parallel(
+        &mut self,
+        txs: impl IntoIterator<Item = TX>,
+        threads: usize,
+    ) -> BatchReport {
+        let txs: Vec<TX> = txs.into_iter().collect();
+        if self.shares_ids_across_clients(&txs) {
+            return self.process_batch(txs);
+        }
+        let threads = threads.max(1);
+        let shard_of = |client: u16| client as usize % threads;
+        let mut errors = Vec::new();
+        let mut shards: Vec<Vec<(usize, TX)>> = (0..threads).map(|_| Vec::new()).collect();
+        // Workers number transactions by their position in `txs`, as the
+        // serial path would.
+        let base = self.sequence;
+        for (index, tx) in txs.into_iter().enumerate() {
+            self.sequence += 1;
+            shards[shard_of(tx.client())].push((index, tx));
+        }
+
+        let mut engines: Vec<Engine> = (0..threads)
+            .map(|_| Engine::new(self.config.clone()))
+            .collect();
+        for (client, account) in self.accounts.take_accounts() {
+            engines[shard_of(client)].accounts.insert(client, account);
+        }
+        for (key, state) in std::mem::take(&mut self.transactions) {
+            engines[shard_of(state.client)]
+                .transactions
+                .insert(key, state);
+        }
+
+        let workers: Vec<_> = engines
+            .into_iter()
+            .zip(shards)
+            .map(|(mut engine, shard)| {
+                thread::spawn(move || {
+                    let mut errors = Vec::new();
+                    let mut processed = 0;
+                    let mut chargebacks = Vec::new();
+                    for (index, tx) in shard {
+                        engine.sequence = base + index as u64;
+                        match engine.process(tx) {
+                            Ok(()) => processed += 1,
+                            Err(error) => errors.push(ProcessError { index, error }),
+                        }
+                        chargebacks.extend(engine.chargebacks.pop().map(|record| (index, record)));
+                    }
+                    (engine, processed, errors, chargebacks)
+                })
+            })
+            .collect();
+        let mut processed = 0;
+        let mut chargebacks = Vec::new();
+        for worker in workers {
+            let (engine, shard_processed, shard_errors, shard_chargebacks) =
+                worker.join().expect("worker thread panicked");
+            for (client, account) in engine.accounts {
+                self.accounts.insert_account(client, account);
+            }
+            self.transactions.extend(engine.transactions);
+            processed += shard_processed;
+            errors.extend(shard_errors);
+            chargebacks.extend(shard_chargebacks);
+        }
+        errors.sort_by_key(|err| err.index);
+        chargebacks.sort_by_key(|(index, _)| *index);
+        self.chargebacks
+            .extend(chargebacks.into_iter().map(|(_, record)| record));
+        BatchReport { processed, errors }
+    }
+
+    /// Whether a transaction id of `txs` belongs to more than one client,
+    /// counting the clients of the stored transactions.
+    fn shares_ids_across_clients(&self, txs: &[TX]) -> bool {
+        let mut owners = HashMap::new();
+        txs.iter().any(|tx| {
+            let key = self.config.tx_key(tx.client(), tx.tx());
+            let owner = *owners.entry(key).or_insert_with(|| {
+                self.transactions
+                    .get(&key)
+                    .map_or(tx.client(), |state| state.client)
+            });
+            owner != tx.client()
+        })
+    }
+
+    /// Admin unlock of a client's account, see `transactions::unlock`.
+    pub fn unlock(&mut self, client: u16) -> bool {
+        unlock(
+            client,
+            &mut self.accounts,
+            &mut self.transactions,
+            &self.config,
+        )
+    }
+
+    pub fn accounts(&self) -> &A {
+        &self.accounts
+    }
+
+    /// Mutable access to the accounts, e.g. to flag seeded accounts.
+    pub fn accounts_mut(&mut self) -> &mut A {
+        &mut self.accounts
+    }
+
+    pub fn transactions(&self) -> &Transactions {
+        &self.transactions
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Every chargeback applied so far, in order.
+    pub fn chargebacks(&self) -> &[ChargebackRecord] {
+        &self.chargebacks
+    }
+
+    /// Ids of the client's deposits and withdrawals that can still be
+    /// disputed: not under dispute and not yet resolved or charged back, in
+    /// ascending order.
+    pub fn disputable_transactions(&self, client: u16) -> Vec<u32> {
+        let mut txs: Vec<u32> = self
+            .transactions
+            .iter()
+            .filter(|(_, state)| state.client == client && !state.disputed)
+            .map(|(key, _)| key.tx)
+            .collect();
+        txs.sort_unstable();
+        txs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(client: u16, tx: u32, amount: f64) -> TX {
+        TX::Deposit(Deposit {
+            client,
+            tx,
+            amount: amount.into(),
+        })
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut hinted = Engine::with_capacity(Config::default(), 10_000);
+        let mut engine = Engine::default();
+        assert!(hinted.transactions().capacity() >= 10_000);
+
+        for tx in 0..1_000 {
+            hinted.process(deposit(1, tx, 1.5)).unwrap();
+            engine.process(deposit(1, tx, 1.5)).unwrap();
+        }
+        hinted
+            .process(TX::Dispute(Dispute {
+                client: 1,
+                tx: 7,
+                amount: None,
+            }))
+            .unwrap();
+        engine
+            .process(TX::Dispute(Dispute {
+                client: 1,
+                tx: 7,
+                amount: None,
+            }))
+            .unwrap();
+
+        assert!(hinted.transactions().capacity() >= 10_000);
+        assert_eq!(hinted.transactions(), engine.transactions());
+        let (hinted, engine) = (&hinted.accounts()[&1], &engine.accounts()[&1]);
+        assert_eq!(
+            (hinted.available, hinted.held, hinted.total),
+            (engine.available, engine.held, engine.total)
+        );
+    }
+
+    #[test]
+    fn test_process_batch_with() {
+        let batch = || {
+            vec![
+                deposit(1, 1, 5.0),
+                TX::Dispute(Dispute {
+                    client: 1,
+                    tx: 9,
+                    amount: None,
+                }),
+                deposit(1, 2, 5.0),
+                TX::Dispute(Dispute {
+                    client: 2,
+                    tx: 1,
+                    amount: None,
+                }),
+            ]
+        };
+
+        let mut engine = Engine::default();
+        let errors = RefCell::new(Vec::new());
+        let processed = engine.process_batch_with(batch(), |err| {
+            errors.borrow_mut().push((err.index, err.error.kind()))
+        });
+        assert_eq!(processed, 2);
+        assert_eq!(
+            errors.into_inner(),
+            vec![(1, "parent_tx_not_found"), (3, "clients_dont_match")]
+        );
+
+        let report = Engine::default().process_batch(batch());
+        assert_eq!(report.processed, 2);
+        assert_eq!(
+            report
+                .errors
+                .iter()
+                .map(|err| err.index)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+    }
+
+    #[test]
+    fn test_rollback_restores_removed_account() {
+        let mut engine = Engine::new(Config {
+            remove_zero_accounts: true,
+            ..Default::default()
+        });
+        engine.process(deposit(1, 1, 5.0)).unwrap();
+
+        let mut journal = Journal::default();
+        let withdrawal = TX::Withdrawal(Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: Money::from(5.0),
+            destination: None,
+        });
+        engine.journal(&mut journal, &withdrawal);
+        engine.process(withdrawal).unwrap();
+        assert!(engine.accounts().is_empty());
+        assert_eq!(engine.transactions().len(), 2);
+
+        engine.rollback(journal);
+        assert_balance_eq!(engine.accounts()[&1].total, 5.0);
+        assert!(engine.transactions().contains_key(&1.into()));
+        engine
+            .process(TX::Dispute(Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_chargeback_order() {
+        let mut engine = Engine::new(Config {
+            validate_chargeback_order: true,
+            ..Default::default()
+        });
+        engine.process(deposit(1, 1, 5.0)).unwrap();
+        let chargeback = TX::Chargeback(Chargeback { client: 1, tx: 1 });
+        assert_eq!(
+            engine.process(chargeback.clone()),
+            Err(TXError::ChargebackOutOfOrder(chargeback.clone()))
+        );
+        engine
+            .process(TX::Dispute(Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }))
+            .unwrap();
+        engine.process(chargeback).unwrap();
+        assert!(engine.accounts()[&1].locked);
+
+        // Disputes only flagged by a loaded snapshot weren't seen in the
+        // stream.
+        let (accounts, transactions) = engine.into_state();
+        let mut snapshot = Engine::with_state(Config::default(), accounts, transactions);
+        snapshot.process(deposit(2, 2, 3.0)).unwrap();
+        snapshot
+            .process(TX::Dispute(Dispute {
+                client: 2,
+                tx: 2,
+                amount: None,
+            }))
+            .unwrap();
+        let (accounts, mut transactions) = snapshot.into_state();
+        for state in transactions.values_mut() {
+            state.disputed_at = None;
+        }
+        let config = Config {
+            validate_chargeback_order: true,
+            ..Default::default()
+        };
+        let mut engine = Engine::with_state(config, accounts, transactions);
+        let chargeback = TX::Chargeback(Chargeback { client: 2, tx: 2 });
+        assert_eq!(
+            engine.process(chargeback.clone()),
+            Err(TXError::ChargebackOutOfOrder(chargeback))
+        );
+        assert_eq!(
+            engine
+                .process(TX::Chargeback(Chargeback { client: 3, tx: 9 }))
+                .unwrap_err()
+                .kind(),
+            "parent_tx_not_found"
+        );
+    }
+
+    #[test]
+    fn test_chargeback_log() {
+        let mut engine = Engine::default();
+        engine.process(deposit(1, 1, 5.0)).unwrap();
+        engine.process(deposit(2, 2, 3.0)).unwrap();
+        engine
+            .process(TX::Dispute(Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }))
+            .unwrap();
+        engine
+            .process(TX::Chargeback(Chargeback { client: 1, tx: 1 }))
+            .unwrap();
+        engine
+            .process(TX::Chargeback(Chargeback { client: 2, tx: 2 }))
+            .unwrap_err();
+
+        let logged = vec![ChargebackRecord {
+            client: 1,
+            tx: 1,
+            kind: TXKind::Deposit,
+            amount: Money::from(5.0),
+            locked: true,
+        }];
+        assert_eq!(engine.chargebacks(), logged);
+        assert!(engine.transactions().get(&1.into()).is_none());
+
+        let mut journal = Journal::default();
+        for tx in [
+            TX::Dispute(Dispute {
+                client: 2,
+                tx: 2,
+                amount: None,
+            }),
+            TX::Chargeback(Chargeback { client: 2, tx: 2 }),
+        ] {
+            engine.journal(&mut journal, &tx);
+            engine.process(tx).unwrap();
+        }
+        assert_eq!(engine.chargebacks().len(), 2);
+        engine.rollback(journal);
+        assert_eq!(engine.chargebacks(), logged);
+    }
+
+    #[test]
+    fn test_process_parallel_matches_serial() {
+        let stream = || {
+            let mut txs = Vec::new();
+            for tx in 0..200u32 {
+                let client = (tx % 7) as u16;
+                txs.push(deposit(client, tx, 2.5));
+                if tx % 3 == 0 {
+                    txs.push(TX::Withdrawal(Withdrawal {
+                        client,
+                        tx: 1_000 + tx,
+                        amount: Money::from(4.0),
+                        destination: None,
+                    }));
+                }
+                if tx % 5 == 0 {
+                    txs.push(TX::Dispute(Dispute {
+                        client,
+                        tx,
+                        amount: None,
+                    }));
+                }
+                if tx % 10 == 0 {
+                    txs.push(TX::Chargeback(Chargeback { client, tx }));
+                }
+            }
+            txs
+        };
+
+        let mut serial = Engine::default();
+        serial.process(deposit(3, 5_000, 1.0)).unwrap();
+        let mut parallel = Engine::default();
+        parallel.process(deposit(3, 5_000, 1.0)).unwrap();
+
+        let expected = serial.process_batch(stream());
+        let report = parallel.process_parallel(stream(), 4);
+        assert_eq!(report, expected);
+        assert_eq!(parallel.transactions(), serial.transactions());
+        assert!(!serial.chargebacks().is_empty());
+        assert_eq!(parallel.chargebacks(), serial.chargebacks());
+        assert_eq!(parallel.accounts().len(), serial.accounts().len());
+        for (client, account) in serial.accounts() {
+            let other = &parallel.accounts()[client];
+            assert_eq!(
+                (
+                    account.available,
+                    account.held,
+                    account.total,
+                    account.locked
+                ),
+                (other.available, other.held, other.total, other.locked)
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_parallel_duplicate_across_clients() {
+        let mut engine = Engine::default();
+        let report = engine.process_parallel(
+            vec![deposit(1, 1, 1.0), deposit(2, 1, 5.0), deposit(2, 2, 5.0)],
+            2,
+        );
+        assert_eq!(report.processed, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].index, 1);
+        assert_eq!(report.errors[0].error.kind(), "duplicate_transaction");
+        assert_balance_eq!(engine.accounts()[&1].total, 1.0);
+        assert_balance_eq!(engine.accounts()[&2].total, 5.0);
+    }
+
+    #[test]
+    fn test_process_parallel_failed_rows_match_serial() {
+        let withdrawal = |client, tx, amount: f64| {
+            TX::Withdrawal(Withdrawal {
+                client,
+                tx,
+                amount: amount.into(),
+                destination: None,
+            })
+        };
+        let streams = [
+            // A failed withdrawal doesn't reserve its id.
+            vec![
+                withdrawal(1, 1, 5.0),
+                deposit(1, 1, 10.0),
+                deposit(2, 2, 3.0),
+            ],
+            // Nor does it across clients: the later deposit takes the id.
+            vec![
+                withdrawal(1, 1, 5.0),
+                deposit(2, 1, 10.0),
+                deposit(1, 1, 3.0),
+                deposit(3, 3, 1.0),
+            ],
+            // A charged back id is free again, for any client.
+            vec![
+                deposit(1, 1, 2.0),
+                TX::Dispute(Dispute {
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                }),
+                TX::Chargeback(Chargeback { client: 1, tx: 1 }),
+                deposit(2, 1, 4.0),
+                TX::Dispute(Dispute {
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                }),
+            ],
+        ];
+        for txs in streams {
+            let mut serial = Engine::default();
+            let expected = serial.process_batch(txs.clone());
+            let mut parallel = Engine::default();
+            assert_eq!(parallel.process_parallel(txs, 4), expected);
+            assert_eq!(parallel.transactions(), serial.transactions());
+            assert_eq!(parallel.chargebacks(), serial.chargebacks());
+            assert_eq!(
+                format!("{:?}", parallel.accounts()),
+                format!("{:?}", serial.accounts())
+            );
+        }
+    }
+
+    #[test]
+    fn test_dry_apply() {
+        let mut engine = Engine::default();
+        engine.process(deposit(1, 1, 5.0)).unwrap();
+
+        let projected = engine.dry_apply(deposit(1, 2, 2.5)).unwrap();
+        assert_balance_eq!(projected.available, 7.5);
+        assert_balance_eq!(projected.total, 7.5);
+        let account = &engine.accounts()[&1];
+        assert_balance_eq!(account.available, 5.0);
+        assert_balance_eq!(account.total, 5.0);
+        assert!(!engine.transactions().contains_key(&2.into()));
+
+        let overdraw = TX::Withdrawal(Withdrawal {
+            client: 1,
+            tx: 3,
+            amount: Money::from(9.0),
+            destination: None,
+        });
+        assert_eq!(
+            engine.dry_apply(overdraw).unwrap_err().kind(),
+            "not_enough_funds"
+        );
+    }
+
+    #[test]
+    fn test_disputable_transactions() {
+        let mut engine = Engine::default();
+        engine.process(deposit(1, 1, 5.0)).unwrap();
+        engine.process(deposit(1, 2, 5.0)).unwrap();
+        engine.process(deposit(1, 3, 5.0)).unwrap();
+        engine.process(deposit(1, 4, 5.0)).unwrap();
+        engine.process(deposit(2, 5, 5.0)).unwrap();
+        engine
+            .process(TX::Withdrawal(Withdrawal {
+                client: 1,
+                tx: 6,
+                amount: Money::from(1.0),
+                destination: None,
+            }))
+            .unwrap();
+        engine
+            .process(TX::Dispute(Dispute {
+                client: 1,
+                tx: 2,
+                amount: None,
+            }))
+            .unwrap();
+        engine
+            .process(TX::Dispute(Dispute {
+                client: 1,
+                tx: 3,
+                amount: None,
+            }))
+            .unwrap();
+        engine
+            .process(TX::Resolve(Resolve { client: 1, tx: 3 }))
+            .unwrap();
+
+        assert_eq!(engine.disputable_transactions(1), vec![1, 4, 6]);
+        assert_eq!(engine.disputable_transactions(2), vec![5]);
+        assert!(engine.disputable_transactions(3).is_empty());
+    }
+}
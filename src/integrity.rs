@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+
+use crate::config::Config;
+use crate::types::*;
+
+#[derive(Debug, PartialEq)]
+pub struct DanglingReference {
+    pub line: u64,
+    pub typ: &'static str,
+    pub client: u16,
+    pub tx: u32,
+}
+
+impl fmt::Display for DanglingReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "IntegrityError: {} on line {} for client {} references tx {} which is never deposited or withdrawn",
+            self.typ, self.line, self.client, self.tx
+        )
+    }
+}
+
+/// Scans the whole input for disputes, resolves and chargebacks whose `tx`
+/// doesn't appear as a deposit or withdrawal anywhere in it nor in `known`.
+/// Rows that fail to parse are left for the processing pass to report.
+pub fn find_dangling_references<R: io::Read>(
+    rdr: &mut csv::Reader<R>,
+    known: &Transactions,
+    config: &Config,
+) -> csv::Result<Vec<DanglingReference>> {
+    let mut dangling =
+        find_dangling_references_in_inputs(std::slice::from_mut(rdr), known, config)?;
+    Ok(dangling.pop().unwrap_or_default())
+}
+
+/// `find_dangling_references` over several inputs, where a reference may
+/// point to a deposit or withdrawal of any of them. Returns the dangling
+/// references of each input, in the order of `rdrs`.
+pub fn find_dangling_references_in_inputs<R: io::Read>(
+    rdrs: &mut [csv::Reader<R>],
+    known: &Transactions,
+    config: &Config,
+) -> csv::Result<Vec<Vec<DanglingReference>>> {
+    let mut parents: HashSet<TxKey> = HashSet::new();
+    let mut references = Vec::with_capacity(rdrs.len());
+    for rdr in rdrs {
+        references.push(scan(rdr, config, &mut parents)?);
+    }
+
+    for references in &mut references {
+        references.retain(|reference| {
+            let key = config.tx_key(reference.client, reference.tx);
+            !parents.contains(&key) && !known.contains_key(&key)
+        });
+    }
+    Ok(references)
+}
+
+/// Collects the input's deposit and withdrawal keys into `parents` and
+/// returns all its references.
+fn scan<R: io::Read>(
+    rdr: &mut csv::Reader<R>,
+    config: &Config,
+    parents: &mut HashSet<TxKey>,
+) -> csv::Result<Vec<DanglingReference>> {
+    let headers = rdr.byte_headers()?.clone();
+    let mut references: Vec<DanglingReference> = Vec::new();
+
+    for record in rdr.byte_records() {
+        let record = record?;
+        let tx = match record
+            .deserialize::<Transaction>(Some(&headers))
+            .ok()
+            .and_then(|tx| TX::from_transaction(tx).ok())
+        {
+            Some(tx) => tx,
+            None => continue,
+        };
+        if tx.is_dispute_flow() {
+            references.push(DanglingReference {
+                line: record.position().map_or(0, |pos| pos.line()),
+                typ: tx.name(),
+                client: tx.client(),
+                tx: tx.tx(),
+            });
+        } else {
+            parents.insert(config.tx_key(tx.client(), tx.tx()));
+        }
+    }
+    Ok(references)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_dangling_references() {
+        let csv_data = "\
+type,client,tx,amount
+dispute,1,2,
+deposit,1,1,1.0
+withdrawal,1,2,0.5
+dispute,1,9,
+resolve,1,1,
+chargeback,2,3,
+";
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv_data.as_bytes());
+        let mut known = Transactions::new();
+        known.insert(
+            3.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 2,
+                amount: Money::from(1.0),
+                disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+
+        let dangling = find_dangling_references(&mut rdr, &known, &Config::default()).unwrap();
+        assert_eq!(
+            dangling,
+            vec![DanglingReference {
+                line: 5,
+                typ: "dispute",
+                client: 1,
+                tx: 9,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_dangling_references_in_inputs() {
+        let reader = |data: &'static str| {
+            csv::ReaderBuilder::new()
+                .flexible(true)
+                .from_reader(data.as_bytes())
+        };
+        let mut rdrs = [
+            reader("type,client,tx,amount\ndeposit,1,1,1.0\nresolve,1,2,\n"),
+            reader("type,client,tx,amount\ndispute,1,1,\nchargeback,1,7,\ndeposit,1,2,1.0\n"),
+        ];
+
+        let dangling =
+            find_dangling_references_in_inputs(&mut rdrs, &Transactions::new(), &Config::default())
+                .unwrap();
+        assert_eq!(
+            dangling,
+            vec![
+                vec![],
+                vec![DanglingReference {
+                    line: 3,
+                    typ: "chargeback",
+                    client: 1,
+                    tx: 7,
+                }]
+            ]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+/// Asserts two balances are equal within a tolerance, `Money::ZERO` unless
+/// given. Either side may be a `Money` or an `f64` literal.
+#[cfg(test)]
+macro_rules! assert_balance_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        assert_balance_eq!($left, $right, $crate::types::Money::ZERO)
+    };
+    ($left:expr, $right:expr, $tolerance:expr $(,)?) => {{
+        let left: $crate::types::Money = ($left).into();
+        let right: $crate::types::Money = ($right).into();
+        let tolerance: $crate::types::Money = ($tolerance).into();
+        assert!(
+            (left - right).abs() <= tolerance,
+            "balances differ: {} != {} (tolerance {})",
+            left,
+            right,
+            tolerance
+        );
+    }};
+}
+
+pub mod batch;
+pub mod config;
+pub mod engine;
+pub mod integrity;
+pub mod line_limit;
+pub mod manifest;
+pub mod output;
+pub mod process_transaction;
+pub mod report;
+pub mod retry;
+pub mod schema;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod shutdown;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod store;
+pub mod transactions;
+pub mod types;
+
+pub use engine::Engine;
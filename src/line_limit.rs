@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+/// Hands lines to the reader above it only once they are known to fit
+/// `limit` bytes, not counting the line break. A longer line is replaced by
+/// an empty one as soon as it outgrows the limit, so at most `limit + 1`
+/// bytes of a line are ever held, and its number is queued for
+/// `rejected_before`. The rest of it is discarded by the next read.
+///
+/// Rejections are keyed by where their empty line starts in the output, as
+/// the csv reader skips empty lines without counting them.
+#[derive(Debug)]
+pub struct LineLimitReader<R> {
+    inner: R,
+    limit: Option<usize>,
+    /// The current line, released from `released` on.
+    line: Vec<u8>,
+    released: usize,
+    /// Number of the line being read.
+    number: u64,
+    /// Bytes handed to the reader above so far.
+    offset: u64,
+    /// Whether the rest of an oversized line still has to be discarded.
+    skipping: bool,
+    /// Output offset and line number of every rejected line not yet
+    /// reported.
+    rejected: VecDeque<(u64, u64)>,
+}
+
+impl<R: BufRead> LineLimitReader<R> {
+    pub fn new(inner: R, limit: Option<usize>) -> Self {
+        Self {
+            inner,
+            limit,
+            line: Vec::new(),
+            released: 0,
+            number: 1,
+            offset: 0,
+            skipping: false,
+            rejected: VecDeque::new(),
+        }
+    }
+
+    /// Pops the line number of the first rejected line, if it comes before
+    /// the record starting at output byte `start`. Lines are read ahead of
+    /// the records parsed from them.
+    pub fn rejected_before(&mut self, start: u64) -> Option<u64> {
+        self.rejected
+            .front()
+            .is_some_and(|(offset, _)| *offset <= start)
+            .then(|| self.rejected.pop_front())
+            .flatten()
+            .map(|(_, line)| line)
+    }
+
+    /// Discards the rest of an oversized line, up to and including its
+    /// line break.
+    fn skip_line(&mut self) -> io::Result<()> {
+        loop {
+            let available = self.inner.fill_buf()?;
+            if available.is_empty() {
+                return Ok(());
+            }
+            match available.iter().position(|byte| *byte == b'\n') {
+                Some(end) => {
+                    self.inner.consume(end + 1);
+                    return Ok(());
+                }
+                None => {
+                    let len = available.len();
+                    self.inner.consume(len);
+                }
+            }
+        }
+    }
+
+    /// Reads the next line into `line`, replacing it with an empty line
+    /// once it outgrows `limit`.
+    fn fill_line(&mut self, limit: usize) -> io::Result<()> {
+        self.line.clear();
+        self.released = 0;
+        loop {
+            let available = self.inner.fill_buf()?;
+            if available.is_empty() {
+                if self.line.len() > limit {
+                    self.reject();
+                }
+                return Ok(());
+            }
+            let newline = available.iter().position(|byte| *byte == b'\n');
+            let content = newline.unwrap_or(available.len());
+            // One byte over the limit may still be the `\r` of a `\r\n`.
+            if self.line.len() + content > limit + 1 {
+                self.skipping = true;
+                self.reject();
+                return Ok(());
+            }
+            let take = newline.map_or(content, |end| end + 1);
+            self.line.extend_from_slice(&available[..take]);
+            self.inner.consume(take);
+            if newline.is_some() {
+                let mut content = &self.line[..self.line.len() - 1];
+                if let [rest @ .., b'\r'] = content {
+                    content = rest;
+                }
+                if content.len() > limit {
+                    self.reject();
+                } else {
+                    self.number += 1;
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    /// Records the current line as rejected and leaves an empty line in its
+    /// place.
+    fn reject(&mut self) {
+        self.rejected.push_back((self.offset, self.number));
+        self.number += 1;
+        self.line.clear();
+        self.line.push(b'\n');
+    }
+}
+
+impl<R: BufRead> io::Read for LineLimitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limit) = self.limit else {
+            return self.inner.read(buf);
+        };
+        if self.released == self.line.len() {
+            if self.skipping {
+                self.skip_line()?;
+                self.skipping = false;
+            }
+            self.fill_line(limit)?;
+        }
+        let pending = &self.line[self.released..];
+        let len = pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&pending[..len]);
+        self.released += len;
+        self.offset += len as u64;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_line_limit_reader() {
+        let data = "short\nthis line is too long\r\nfits\r\n12345678\nlast";
+        let mut reader = LineLimitReader::new(data.as_bytes(), Some(8));
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "short\n\nfits\r\n12345678\nlast");
+        // The empty line left in place of the second one starts at byte 6.
+        assert_eq!(reader.rejected_before(5), None);
+        assert_eq!(reader.rejected_before(6), Some(2));
+        assert_eq!(reader.rejected_before(u64::MAX), None);
+    }
+
+    #[test]
+    fn test_line_limit_reader_stops_at_limit() {
+        // The oversized line never ends, so it can only be refused before
+        // being read whole.
+        let data = io::Cursor::new(b"type,client\n".to_vec()).chain(io::repeat(b'1'));
+        let mut reader = LineLimitReader::new(io::BufReader::new(data), Some(16));
+        let mut lines = [0; 13];
+        reader.read_exact(&mut lines).unwrap();
+        assert_eq!(&lines, b"type,client\n\n");
+        assert_eq!(reader.rejected_before(u64::MAX), Some(2));
+        assert!(reader.line.capacity() <= 17);
+    }
+
+    #[test]
+    fn test_line_limit_reader_without_limit() {
+        let mut reader = LineLimitReader::new("a\nbb\n".as_bytes(), None);
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "a\nbb\n");
+    }
+}
@@ -1,150 +1,2799 @@
-use clap::{Arg, Command};
-use csv::Writer;
-use serde::{Serialize, Serializer};
-use std::io;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use csv::ByteRecord;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::process;
+#[cfg(feature = "serve")]
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
 
-mod process_transaction;
-mod transactions;
-mod types;
-
-use process_transaction::*;
-use types::*;
+use transaction_processor::batch::{Batch, BatchMarker, Journal};
+use transaction_processor::config::*;
+use transaction_processor::integrity::find_dangling_references_in_inputs;
+use transaction_processor::line_limit::LineLimitReader;
+use transaction_processor::manifest::{tx_set_hash, ChecksumWriter, Manifest, ManifestConfig};
+use transaction_processor::output::{
+    schema, write_accounts, AccountStream, OutputColumn, OutputFormat, OutputOptions,
+    DEFAULT_FOOTER,
+};
+use transaction_processor::report::{
+    institution_balances, negative_balances, net_movements, write_error_report, write_report,
+    ChargebackRecord, ProcessingError,
+};
+use transaction_processor::retry::{RetryPolicy, RetryReader};
+use transaction_processor::schema::{read_marker, SchemaVersion};
+#[cfg(feature = "serve")]
+use transaction_processor::serve;
+use transaction_processor::shutdown::Interrupt;
+use transaction_processor::snapshot::{PeriodicSnapshots, Snapshot, SnapshotMode};
+#[cfg(feature = "sqlite")]
+use transaction_processor::sqlite;
+use transaction_processor::stats::Stats;
+use transaction_processor::store::{AccountStore, DenseAccounts};
+use transaction_processor::types::*;
+use transaction_processor::Engine;
 
 fn main() {
-    let matches = Command::new("transaction_processor")
+    let matches = cli().get_matches();
+    if matches.get_flag("print-schema") {
+        print!("{}", schema());
+        return;
+    }
+
+    let input_paths: Vec<String> = match matches.get_many::<String>("input") {
+        Some(paths) => paths.cloned().collect(),
+        None if matches.contains_id("serve") => Vec::new(),
+        None if !io::stdin().is_terminal() => vec![STDIN.to_string()],
+        None => {
+            eprintln!("no input: pass an input file, or - to read transactions piped on stdin");
+            process::exit(1);
+        }
+    };
+    if input_paths.iter().filter(|path| *path == STDIN).count() > 1 {
+        eprintln!("stdin can only be read once, pass - a single time");
+        process::exit(1);
+    }
+    if input_paths.len() > 1 && matches.contains_id("as-of") {
+        eprintln!("--as-of can't be combined with several input files");
+        process::exit(1);
+    }
+    let config = parse_config(&matches);
+    let mut options = parse_options(&matches);
+    let output_options = parse_output_options(&matches, options.precision);
+    if output_options.format == OutputFormat::Json
+        && (options.flush_every.is_some() || output_options.footer.is_some())
+    {
+        eprintln!("--flush-every and --footer only apply to CSV output");
+        process::exit(1);
+    }
+
+    let retry_policy = RetryPolicy {
+        max_retries: *matches.get_one::<u32>("io-retries").unwrap(),
+        delay: Duration::from_millis(*matches.get_one::<u64>("io-retry-delay").unwrap()),
+    };
+
+    let schema_version = matches.get_one::<SchemaVersion>("schema-version").copied();
+    if matches.get_flag("validate-only") {
+        let mut stats = Stats::default();
+        for path in &input_paths {
+            if options.strict && stats.errors > 0 {
+                break;
+            }
+            let (mut rdr, marker) = match open_reader(path, retry_policy, options.max_line_bytes) {
+                Ok(opened) => opened,
+                Err(err) => {
+                    report_failure(&mut stats, &options, "open", err.to_string(), None, None);
+                    stats.record_error("open");
+                    continue;
+                }
+            };
+            options.schema = schema_version.or(marker).unwrap_or_default();
+            stats.merge(validate_records(&mut rdr, &options));
+        }
+        eprintln!(
+            "{} well-formed records, {} malformed",
+            stats.processed, stats.errors
+        );
+        finish(&matches, &stats);
+    }
+    let Snapshot {
+        mut accounts,
+        transactions,
+    } = match matches.get_one::<String>("load-snapshot") {
+        Some(path) => match Snapshot::load(path) {
+            Ok(mut snapshot) => {
+                snapshot.rekey(&config);
+                snapshot
+            }
+            Err(err) => {
+                eprintln!("Failed to load snapshot {}: {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => Snapshot::default(),
+    };
+    if matches.get_flag("seeded") {
+        for account in accounts.values_mut() {
+            account.seeded = true;
+        }
+    }
+    // Without --dense-clients every account ends up in the store's map.
+    let mut store = DenseAccounts::with_range(
+        matches
+            .get_one::<usize>("dense-clients")
+            .copied()
+            .unwrap_or_default(),
+    );
+    for (client, account) in accounts {
+        store.insert_account(client, account);
+    }
+    let mut engine = Engine::with_state(config, store, transactions);
+    for client in matches.get_many::<u16>("unlock").unwrap_or_default() {
+        if !engine.unlock(*client) {
+            eprintln!("Cannot unlock client {}: account not found", client);
+        }
+    }
+
+    if let Some(expected) = matches.get_one::<usize>("expected-transactions") {
+        engine.reserve(*expected);
+    }
+
+    if matches.get_flag("check-references") || matches.get_flag("reject-on-dangling") {
+        check_references(&matches, &input_paths, &engine, retry_policy);
+    }
+
+    let interrupt = Interrupt::new();
+    #[cfg(feature = "graceful-shutdown")]
+    if let Err(err) = interrupt.install() {
+        eprintln!("Failed to install interrupt handler: {}", err);
+    }
+
+    let mut output = ChecksumWriter::new(io::stdout());
+    let mut stream = options
+        .flush_every
+        .map(|_| AccountStream::new(&mut output as &mut dyn io::Write, output_options.clone()));
+    let mut snapshots = options.snapshot_every.and_then(|_| {
+        let path = matches.get_one::<String>("save-snapshot")?;
+        let mode: SnapshotMode = matches
+            .get_one::<String>("snapshot-mode")
+            .unwrap()
+            .parse()
+            .unwrap();
+        Some(PeriodicSnapshots::new(path, mode))
+    });
+    let mut stats = Stats::default();
+    let mut opened = 0;
+    for path in &input_paths {
+        if interrupt.is_triggered() || (options.strict && stats.errors > 0) {
+            break;
+        }
+        let (mut rdr, marker) = match open_reader(path, retry_policy, options.max_line_bytes) {
+            Ok(opened) => opened,
+            Err(err) => {
+                report_failure(&mut stats, &options, "open", err.to_string(), None, None);
+                stats.record_error("open");
+                continue;
+            }
+        };
+        opened += 1;
+        options.schema = schema_version.or(marker).unwrap_or_default();
+        stats.merge(process_records(
+            &mut rdr,
+            &mut engine,
+            &options,
+            &interrupt,
+            stream.as_mut(),
+            snapshots.as_mut(),
+        ));
+    }
+    if let Some(Err(err)) = snapshots.map(PeriodicSnapshots::finish) {
+        eprintln!("Failed to write periodic snapshot: {}", err);
+    }
+    if opened == 0 && !input_paths.is_empty() {
+        finish(&matches, &stats);
+    }
+    if options.strict && stats.errors > 0 {
+        eprintln!("Aborting after the first failed row");
+        finish(&matches, &stats);
+    }
+    if let Some(addr) = matches.get_one::<String>("serve") {
+        #[cfg(feature = "serve")]
+        {
+            let shared = Mutex::new(engine);
+            let serve_options = serve::ServeOptions {
+                precision: options.precision,
+                min_amount: options.min_amount,
+                output: output_options.clone(),
+            };
+            eprintln!("Serving on {}", addr);
+            if let Err(err) = serve::serve(addr, &shared, &serve_options, &interrupt) {
+                eprintln!("Failed to serve on {}: {}", addr, err);
+                process::exit(1);
+            }
+            engine = shared.into_inner().unwrap_or_else(PoisonError::into_inner);
+        }
+        #[cfg(not(feature = "serve"))]
+        {
+            eprintln!("Can't serve on {}: built without the serve feature", addr);
+            process::exit(1);
+        }
+    }
+    let chargebacks = engine.chargebacks().to_vec();
+    let (accounts, transactions) = engine.into_state();
+    let accounts = accounts.into_accounts();
+
+    if options.dedup_tx_ids {
+        eprintln!(
+            "Skipped {} already processed transactions",
+            stats.duplicates
+        );
+    }
+    if options.collect_tx_ids {
+        eprintln!("TX set hash: xxh3:{:016x}", tx_set_hash(&stats.tx_ids));
+    }
+    if options.truncation_report {
+        eprintln!("Truncated {} from input amounts", stats.truncation_loss);
+    }
+
+    if let Some(mut stream) = stream.take() {
+        if let Err(err) = stream.write(&accounts) {
+            eprintln!("Failed to write accounts: {}", err);
+        }
+        if let Err(err) = stream.finish(accounts.len()) {
+            eprintln!("Failed to write footer: {}", err);
+        }
+    }
+    drop(stream);
+    if options.flush_every.is_none() {
+        write_accounts(&accounts, &mut output, &output_options);
+    }
+
+    if let Some(snapshot_path) = matches.get_one::<String>("save-snapshot") {
+        if let Err(err) = Snapshot::save(&accounts, &transactions, snapshot_path) {
+            eprintln!("Failed to save snapshot to {}: {}", snapshot_path, err);
+        }
+    }
+
+    write_reports(&matches, &accounts, &transactions, &stats, &chargebacks);
+    write_manifest(
+        &matches,
+        input_paths,
+        &stats,
+        accounts.len(),
+        &options,
+        output.checksum(),
+    );
+    finish(&matches, &stats);
+}
+
+/// Reports the disputes, resolves and chargebacks of the inputs that name a
+/// transaction found neither in the inputs nor in the engine, exiting when
+/// `--reject-on-dangling` finds any.
+fn check_references<A: AccountStore>(
+    matches: &ArgMatches,
+    input_paths: &[String],
+    engine: &Engine<A>,
+    retry_policy: RetryPolicy,
+) {
+    let reject_on_dangling = matches.get_flag("reject-on-dangling");
+    if input_paths.iter().any(|path| path == STDIN) {
+        eprintln!("--check-references reads the input twice and needs an input file, not stdin");
+        process::exit(1);
+    }
+    // Inputs that can't be opened are reported by the processing pass.
+    let (paths, mut rdrs): (Vec<_>, Vec<_>) = input_paths
+        .iter()
+        .filter_map(|path| Some((path, open_reader(path, retry_policy, None).ok()?.0)))
+        .unzip();
+    let dangling =
+        find_dangling_references_in_inputs(&mut rdrs, engine.transactions(), engine.config());
+    match dangling {
+        Ok(dangling) => {
+            for (path, references) in paths.iter().zip(&dangling) {
+                for reference in references {
+                    if input_paths.len() > 1 {
+                        eprintln!("{}: {}", path, reference);
+                    } else {
+                        eprintln!("{}", reference);
+                    }
+                }
+            }
+            let count: usize = dangling.iter().map(Vec::len).sum();
+            if reject_on_dangling && count > 0 {
+                eprintln!("Rejecting input: {} dangling references", count);
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("Failed to check references: {}", err);
+            if reject_on_dangling {
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn cli() -> Command {
+    Command::new("transaction_processor")
         .version("1.0")
         .author("Your Name <xavi@delape.net>")
         .about("Processes transactions and generates account balances")
         .arg(
             Arg::new("input")
-                .help("Sets the input CSV file to use")
-                .required(true)
+                .help("Sets the input CSV files to use, processed in order; - or omitted reads stdin")
+                .num_args(1..)
                 .index(1),
         )
-        .get_matches();
-    let input_path = matches.get_one::<String>("input").unwrap();
+        .arg(
+            Arg::new("print-schema")
+                .long("print-schema")
+                .help("Prints the input and output CSV columns and exits")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .help("Writes every successfully processed transaction to stderr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dedup-consecutive")
+                .long("dedup-consecutive")
+                .help("Skips rows identical to the immediately preceding row")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dedup-tx-ids")
+                .long("dedup-tx-ids")
+                .help("Skips deposits and withdrawals whose transaction id was already processed, e.g. in an overlapping earlier file")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("expect-monotonic-tx")
+                .long("expect-monotonic-tx")
+                .value_name("ACTION")
+                .value_parser(["report", "reject"])
+                .num_args(0..=1)
+                .default_missing_value("report")
+                .help("Flags deposits and withdrawals whose transaction id isn't greater than the previous one's, and reports or rejects them"),
+        )
+        .arg(
+            Arg::new("as-of")
+                .long("as-of")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64))
+                .help("Stops after the first N transactions and writes the accounts at that point"),
+        )
+        .arg(
+            Arg::new("sample-rate")
+                .long("sample-rate")
+                .value_name("RATE")
+                .value_parser(parse_sample_rate)
+                .help("Processes only a deterministic subset of about RATE (0 to 1) of the rows, for quick checks on large inputs"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0")
+                .requires("sample-rate")
+                .help("Seeds the row selection of --sample-rate, the same seed picks the same rows"),
+        )
+        .arg(
+            Arg::new("report-blank-rows")
+                .long("report-blank-rows")
+                .help("Reports empty and whitespace-only rows as errors instead of skipping them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-line-bytes")
+                .long("max-line-bytes")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Rejects rows longer than BYTES"),
+        )
+        .arg(
+            Arg::new("error-report")
+                .long("error-report")
+                .value_name("PATH")
+                .help("Writes failed and skipped rows to PATH as JSON instead of printing them to stderr"),
+        )
+        .arg(
+            Arg::new("raw-errors")
+                .long("raw-errors")
+                .help("Includes the line number and raw CSV row in error messages")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("check-references")
+                .long("check-references")
+                .help("Reports disputes, resolves and chargebacks referencing unknown transactions before processing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("reject-on-dangling")
+                .long("reject-on-dangling")
+                .help("Like --check-references, but refuses to process the input if any reference is dangling")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("error-exit-code")
+                .long("error-exit-code")
+                .value_name("CODE")
+                .value_parser(clap::value_parser!(i32).range(0..=255))
+                .default_value("1")
+                .help("Exit code when any row failed, the accounts are still written"),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Stops at the first failed row and exits without writing accounts")
+                .conflicts_with_all(["threads", "serve"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("validate-only")
+                .long("validate-only")
+                .help("Only checks that every record parses, without processing it or writing accounts")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("deposit-limit")
+                .long("deposit-limit")
+                .value_name("AMOUNT")
+                .value_parser(|s: &str| s.parse::<Money>())
+                .help("Rejects deposits that would take a client's cumulative deposits above AMOUNT"),
+        )
+        .arg(
+            Arg::new("max-balance")
+                .long("max-balance")
+                .value_name("AMOUNT")
+                .value_parser(|s: &str| s.parse::<Money>())
+                .help("Rejects deposits and disputes that would take a balance above AMOUNT"),
+        )
+        .arg(
+            Arg::new("max-open-disputes")
+                .long("max-open-disputes")
+                .value_name("COUNT")
+                .value_parser(clap::value_parser!(u32))
+                .help("Rejects new disputes from clients that already have COUNT open disputes"),
+        )
+        .arg(
+            Arg::new("total-floor")
+                .long("total-floor")
+                .value_name("AMOUNT")
+                .value_parser(|s: &str| s.parse::<Money>())
+                .allow_negative_numbers(true)
+                .help("Lowest total a chargeback may leave on an account"),
+        )
+        .arg(
+            Arg::new("total-floor-policy")
+                .long("total-floor-policy")
+                .value_name("POLICY")
+                .value_parser(["reject", "clamp"])
+                .default_value("reject")
+                .help("What to do with chargebacks that would take the total below --total-floor"),
+        )
+        .arg(
+            Arg::new("expected-transactions")
+                .long("expected-transactions")
+                .value_name("COUNT")
+                .value_parser(clap::value_parser!(usize))
+                .help("Pre-sizes the transaction store for COUNT transactions"),
+        )
+        .arg(
+            Arg::new("dense-clients")
+                .long("dense-clients")
+                .value_name("COUNT")
+                .value_parser(clap::value_parser!(usize))
+                .help("Keeps the accounts of clients 0 to COUNT-1 in a vector instead of a map, falling back to the map for any other client"),
+        )
+        .arg(
+            Arg::new("dispute-overdraw")
+                .long("dispute-overdraw")
+                .value_name("POLICY")
+                .value_parser(["strict", "permissive"])
+                .default_value("strict")
+                .help("Whether disputing already withdrawn funds is rejected or drives available negative"),
+        )
+        .arg(
+            Arg::new("sweep-dust")
+                .long("sweep-dust")
+                .help("No effect, amounts are fixed point and leave no residue to sweep")
+                .hide(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("decimals")
+                .long("decimals")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("4")
+                .help("Decimals amounts are processed with, at most 4. Input amounts are truncated to it"),
+        )
+        .arg(
+            Arg::new("min-amount")
+                .long("min-amount")
+                .value_name("AMOUNT")
+                .value_parser(|s: &str| validate_amount(s).map_err(|err| err.to_string()))
+                .help("Rejects deposits and withdrawals below AMOUNT (default 0.0001)"),
+        )
+        .arg(
+            Arg::new("output-precision")
+                .long("output-precision")
+                .value_name("DECIMALS")
+                .value_parser(clap::value_parser!(u32).range(0..=SCALE as i64))
+                .help("Decimals shown for output amounts, truncating the internal scale"),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_name("COLUMNS")
+                .value_parser(OutputColumn::parse_list)
+                .help("Comma separated output columns, in order (default client,available,held,total,locked)"),
+        )
+        .arg(
+            Arg::new("provenance")
+                .long("provenance")
+                .help("Tracks the transactions making up each balance and writes their ids in a tx_ids column")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .help("Adds deposit_count, withdrawal_count and total_deposited columns")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("under-dispute")
+                .long("under-dispute")
+                .help("Adds an under_dispute column, true for accounts with an open dispute")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sign-columns")
+                .long("sign-columns")
+                .help("Writes amounts as magnitudes followed by a +/- sign column")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output-format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .value_parser(["csv", "json"])
+                .default_value("csv")
+                .help("Writes the accounts as CSV or as a JSON array of objects"),
+        )
+        .arg(
+            Arg::new("compress-output")
+                .long("compress-output")
+                .value_name("FORMAT")
+                .value_parser(["none", "gzip"])
+                .default_value("none")
+                .help("Compresses the output written to stdout"),
+        )
+        .arg(
+            Arg::new("footer")
+                .long("footer")
+                .value_name("TEMPLATE")
+                .num_args(0..=1)
+                .default_missing_value(DEFAULT_FOOTER)
+                .help("Writes a line after the last account, {count} being replaced by the number of accounts"),
+        )
+        .arg(
+            Arg::new("sorted")
+                .long("sorted")
+                .help("Accounts are always written ordered by client id, kept for compatibility")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("flush-every")
+                .long("flush-every")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with_all(["sorted", "compress-output"])
+                .help("Writes the accounts touched by every N processed transactions as they happen, then all accounts at the end"),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("1")
+                .conflicts_with_all(["flush-every", "dedup-tx-ids"])
+                .help("Processes transactions on N worker threads, each owning a share of the clients"),
+        )
+        .arg(
+            Arg::new("sort-chunk-size")
+                .long("sort-chunk-size")
+                .value_name("ACCOUNTS")
+                .value_parser(clap::value_parser!(usize))
+                .help("No effect, kept for compatibility"),
+        )
+        .arg(
+            Arg::new("io-retries")
+                .long("io-retries")
+                .value_name("COUNT")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0")
+                .help("Retries input reads failing with a transient error up to COUNT times"),
+        )
+        .arg(
+            Arg::new("io-retry-delay")
+                .long("io-retry-delay")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10")
+                .help("Milliseconds to wait between --io-retries attempts"),
+        )
+        .arg(
+            Arg::new("tx-set-hash")
+                .long("tx-set-hash")
+                .help("Prints an order independent hash of the processed transaction ids to stderr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("truncation-report")
+                .long("truncation-report")
+                .help("Prints the total precision truncated from input amounts to stderr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("negative-report")
+                .long("negative-report")
+                .value_name("PATH")
+                .help("Writes the clients left with a negative available balance to a CSV file"),
+        )
+        .arg(
+            Arg::new("movement-report")
+                .long("movement-report")
+                .value_name("PATH")
+                .help("Writes each client's deposited, withdrawn, net and held amounts to a CSV file"),
+        )
+        .arg(
+            Arg::new("institution-report")
+                .long("institution-report")
+                .value_name("PATH")
+                .help("Writes balances summed per institution column value to a CSV file"),
+        )
+        .arg(
+            Arg::new("chargeback-log")
+                .long("chargeback-log")
+                .value_name("PATH")
+                .help("Writes every applied chargeback to a CSV file"),
+        )
+        .arg(
+            Arg::new("load-snapshot")
+                .long("load-snapshot")
+                .value_name("PATH")
+                .help("Loads accounts and transactions from a JSON snapshot before processing"),
+        )
+        .arg(
+            Arg::new("seeded")
+                .long("seeded")
+                .help("Marks accounts loaded from the snapshot as seeded, exempting them from --deposit-limit")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("redispute-resolved")
+                .long("redispute-resolved")
+                .help("Keeps resolved transactions so they can be disputed again")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("resolve-when-locked")
+                .long("resolve-when-locked")
+                .help("Lets locked accounts resolve their outstanding disputes")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("locked-behavior")
+                .long("locked-behavior")
+                .value_name("POLICY")
+                .value_parser(["block-all", "allow-dispute-flow"])
+                .default_value("block-all")
+                .help("Which transactions locked accounts still accept"),
+        )
+        .arg(
+            Arg::new("per-client-tx-ids")
+                .long("per-client-tx-ids")
+                .help("Treats transaction ids as unique per client instead of globally")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("schema-version")
+                .long("schema-version")
+                .value_name("VERSION")
+                .value_parser(|s: &str| s.parse::<SchemaVersion>())
+                .help("Input column layout, v1, v2 (with currency) or v3 (with credit/debit direction). Overrides a '# schema: v2' first line"),
+        )
+        .arg(
+            Arg::new("freeze-disputes")
+                .long("freeze-disputes")
+                .value_name("CLIENT")
+                .value_parser(clap::value_parser!(u16))
+                .action(ArgAction::Append)
+                .help("Rejects new disputes from CLIENT, can be repeated"),
+        )
+        .arg(
+            Arg::new("unlock")
+                .long("unlock")
+                .value_name("CLIENT")
+                .value_parser(clap::value_parser!(u16))
+                .action(ArgAction::Append)
+                .help("Unlocks CLIENT's loaded account before processing, can be repeated"),
+        )
+        .arg(
+            Arg::new("unlock-resolves-disputes")
+                .long("unlock-resolves-disputes")
+                .help("Resolves the open disputes of accounts unlocked with --unlock")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("validate-chargeback-order")
+                .long("validate-chargeback-order")
+                .help("Rejects chargebacks whose transaction wasn't disputed earlier in the input")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("remove-zero-accounts")
+                .long("remove-zero-accounts")
+                .help("Drops unlocked accounts whose balances all reach zero, a later deposit creates them again")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("disable")
+                .long("disable")
+                .value_name("TYPE")
+                .value_parser(TXType::ALL.map(|typ| typ.as_str()))
+                .action(ArgAction::Append)
+                .help("Skips every transaction of TYPE, can be repeated"),
+        )
+        .arg(
+            Arg::new("all-clients")
+                .long("all-clients")
+                .help("Writes a row for every client seen in the input, with zero balances if none of its transactions applied")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("disputes-only")
+                .long("disputes-only")
+                .help("Ignores deposits and withdrawals, replaying only disputes, resolves and chargebacks")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("save-snapshot")
+                .long("save-snapshot")
+                .value_name("PATH")
+                .help("Saves accounts and transactions to a JSON snapshot after processing"),
+        )
+        .arg(
+            Arg::new("snapshot-every")
+                .long("snapshot-every")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("save-snapshot")
+                .conflicts_with("threads")
+                .help("Also saves the snapshot every N processed transactions"),
+        )
+        .arg(
+            Arg::new("snapshot-mode")
+                .long("snapshot-mode")
+                .value_name("MODE")
+                .value_parser(["blocking", "background"])
+                .default_value("blocking")
+                .help("Writes periodic snapshots before processing continues, or on a background thread"),
+        )
+        .arg(
+            Arg::new("sqlite")
+                .long("sqlite")
+                .value_name("PATH")
+                .help("Writes accounts into a SQLite database at PATH (requires the sqlite feature)"),
+        )
+        .arg(
+            Arg::new("serve")
+                .long("serve")
+                .value_name("ADDR")
+                .conflicts_with_all(["flush-every", "threads"])
+                .help("After the inputs, serves transactions and accounts over HTTP on ADDR until interrupted (requires the serve feature)"),
+        )
+        .arg(
+            Arg::new("sqlite-transactions")
+                .long("sqlite-transactions")
+                .help("Also writes transactions into the SQLite database")
+                .requires("sqlite")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("metrics")
+                .long("metrics")
+                .value_name("PATH")
+                .help("Writes run counters in Prometheus text format to PATH"),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("PATH")
+                .help("Writes a JSON manifest describing the run to PATH"),
+        )
+}
+
+fn parse_config(matches: &ArgMatches) -> Config {
+    Config {
+        dispute_overdraw_policy: matches
+            .get_one::<String>("dispute-overdraw")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        deposit_limit: matches.get_one::<Money>("deposit-limit").copied(),
+        max_balance: matches.get_one::<Money>("max-balance").copied(),
+        max_open_disputes: matches.get_one::<u32>("max-open-disputes").copied(),
+        total_floor: matches.get_one::<Money>("total-floor").copied(),
+        total_floor_policy: matches
+            .get_one::<String>("total-floor-policy")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        resolve_when_locked: matches.get_flag("resolve-when-locked"),
+        redispute_resolved: matches.get_flag("redispute-resolved"),
+        locked_policy: matches
+            .get_one::<String>("locked-behavior")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        per_client_tx_ids: matches.get_flag("per-client-tx-ids"),
+        disabled: matches
+            .get_many::<String>("disable")
+            .unwrap_or_default()
+            .filter_map(|name| name.parse::<TXType>().ok())
+            .collect(),
+        disputes_frozen: matches
+            .get_many::<u16>("freeze-disputes")
+            .unwrap_or_default()
+            .copied()
+            .collect(),
+        unlock_resolves_disputes: matches.get_flag("unlock-resolves-disputes"),
+        validate_chargeback_order: matches.get_flag("validate-chargeback-order"),
+        remove_zero_accounts: matches.get_flag("remove-zero-accounts"),
+        track_provenance: matches.get_flag("provenance"),
+        ..Default::default()
+    }
+}
+
+fn parse_options(matches: &ArgMatches) -> Options {
+    Options {
+        trace: matches.get_flag("trace"),
+        dedup_consecutive: matches.get_flag("dedup-consecutive"),
+        dedup_tx_ids: matches.get_flag("dedup-tx-ids"),
+        monotonic_tx: matches
+            .get_one::<String>("expect-monotonic-tx")
+            .map(|action| match action.as_str() {
+                "reject" => MonotonicTx::Reject,
+                _ => MonotonicTx::Report,
+            }),
+        raw_errors: matches.get_flag("raw-errors"),
+        error_report: matches.contains_id("error-report"),
+        disputes_only: matches.get_flag("disputes-only"),
+        all_clients: matches.get_flag("all-clients"),
+        collect_tx_ids: matches.get_flag("tx-set-hash"),
+        truncation_report: matches.get_flag("truncation-report"),
+        collect_institutions: matches.contains_id("institution-report"),
+        report_blank_rows: matches.get_flag("report-blank-rows"),
+        strict: matches.get_flag("strict"),
+        max_line_bytes: matches
+            .get_one::<u64>("max-line-bytes")
+            .map(|bytes| *bytes as usize),
+        as_of: matches.get_one::<u64>("as-of").copied(),
+        sample: matches.get_one::<f64>("sample-rate").map(|rate| Sample {
+            rate: *rate,
+            seed: *matches.get_one::<u64>("seed").unwrap(),
+        }),
+        flush_every: matches.get_one::<u64>("flush-every").copied(),
+        snapshot_every: matches.get_one::<u64>("snapshot-every").copied(),
+        threads: *matches.get_one::<u64>("threads").unwrap() as usize,
+        precision: Precision::new(*matches.get_one::<u32>("decimals").unwrap()),
+        min_amount: matches.get_one::<Money>("min-amount").copied(),
+        // Resolved once the input is opened and its marker line read.
+        schema: SchemaVersion::default(),
+    }
+}
+
+/// The output options, at most as precise as `precision`, the input's.
+fn parse_output_options(matches: &ArgMatches, precision: Precision) -> OutputOptions {
+    OutputOptions {
+        precision: Precision::new(
+            matches
+                .get_one::<u32>("output-precision")
+                .map_or(precision.decimals(), |decimals| {
+                    (*decimals).min(precision.decimals())
+                }),
+        ),
+        columns: matches
+            .get_one::<Vec<OutputColumn>>("columns")
+            .cloned()
+            .unwrap_or_else(|| {
+                let mut columns = OutputColumn::DEFAULT.to_vec();
+                if matches.get_flag("provenance") {
+                    columns.push(OutputColumn::TxIds);
+                }
+                if matches.get_flag("stats") {
+                    columns.extend(OutputColumn::STATS);
+                }
+                if matches.get_flag("under-dispute") {
+                    columns.push(OutputColumn::UnderDispute);
+                }
+                columns
+            }),
+        sign_columns: matches.get_flag("sign-columns"),
+        compression: matches
+            .get_one::<String>("compress-output")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        format: matches
+            .get_one::<String>("output-format")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        footer: matches.get_one::<String>("footer").cloned(),
+        ..Default::default()
+    }
+}
+
+/// Writes the reports derived from the final accounts.
+fn write_reports(
+    matches: &ArgMatches,
+    accounts: &Accounts,
+    transactions: &Transactions,
+    stats: &Stats,
+    chargebacks: &[ChargebackRecord],
+) {
+    if let Some(path) = matches.get_one::<String>("negative-report") {
+        write_csv_report(
+            path,
+            "negative balance report",
+            &negative_balances(accounts),
+        );
+    }
+    if let Some(path) = matches.get_one::<String>("movement-report") {
+        write_csv_report(path, "net movement report", &net_movements(accounts));
+    }
+    if let Some(path) = matches.get_one::<String>("institution-report") {
+        let balances = institution_balances(accounts, &stats.institutions);
+        write_csv_report(path, "institution report", &balances);
+    }
+    if let Some(path) = matches.get_one::<String>("chargeback-log") {
+        write_csv_report(path, "chargeback log", chargebacks);
+    }
+
+    if let Some(sqlite_path) = matches.get_one::<String>("sqlite") {
+        #[cfg(feature = "sqlite")]
+        {
+            let transactions = matches
+                .get_flag("sqlite-transactions")
+                .then_some(transactions);
+            if let Err(err) = sqlite::export_to_path(sqlite_path, accounts, transactions) {
+                eprintln!("Failed to write SQLite database {}: {}", sqlite_path, err);
+            }
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = transactions;
+            eprintln!(
+                "Can't write SQLite database {}: built without the sqlite feature",
+                sqlite_path
+            );
+        }
+    }
+
+    if let Some(metrics_path) = matches.get_one::<String>("metrics") {
+        if let Err(err) = fs::write(metrics_path, stats.prometheus(accounts)) {
+            eprintln!("Failed to write metrics to {}: {}", metrics_path, err);
+        }
+    }
+}
+
+fn write_csv_report<T: Serialize>(path: &str, name: &str, rows: &[T]) {
+    let written = fs::File::create(path)
+        .map_err(csv::Error::from)
+        .and_then(|file| write_report(rows, file));
+    if let Err(err) = written {
+        eprintln!("Failed to write {} to {}: {}", name, path, err);
+    }
+}
+
+fn write_manifest(
+    matches: &ArgMatches,
+    inputs: Vec<String>,
+    stats: &Stats,
+    accounts: usize,
+    options: &Options,
+    output_checksum: u64,
+) {
+    let Some(manifest_path) = matches.get_one::<String>("manifest") else {
+        return;
+    };
+    let manifest = Manifest::new(
+        inputs,
+        stats,
+        accounts,
+        ManifestConfig::new(options.precision, options.dedup_consecutive),
+        output_checksum,
+    );
+    if let Err(err) = manifest.write(manifest_path) {
+        eprintln!("Failed to write manifest to {}: {}", manifest_path, err);
+    }
+}
+
+/// Writes the error report and exits, with `--error-exit-code` if any row
+/// failed. Every run past argument parsing ends here.
+fn finish(matches: &ArgMatches, stats: &Stats) -> ! {
+    if let Some(report_path) = matches.get_one::<String>("error-report") {
+        let written = fs::File::create(report_path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| write_error_report(&stats.error_report, io::BufWriter::new(file)));
+        if let Err(err) = written {
+            eprintln!("Failed to write error report to {}: {}", report_path, err);
+        }
+    }
+    let error_exit_code = *matches.get_one::<i32>("error-exit-code").unwrap();
+    process::exit(if stats.errors > 0 { error_exit_code } else { 0 });
+}
+
+/// Input path standing for stdin.
+const STDIN: &str = "-";
+
+/// Rows queued for the worker threads before they are processed, so
+/// `--threads` doesn't hold the whole input in memory.
+const PARALLEL_CHUNK_ROWS: usize = 65_536;
+
+type InputReader = csv::Reader<LineLimitReader<io::BufReader<RetryReader<Box<dyn io::Read>>>>>;
+
+#[derive(Debug)]
+struct InputError {
+    path: String,
+    error: io::Error,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "could not open input file '{}': {}",
+            self.path, self.error
+        )
+    }
+}
+
+/// Opens the input, consuming its schema marker line if it has one.
+fn open_reader(
+    path: &str,
+    policy: RetryPolicy,
+    max_line_bytes: Option<usize>,
+) -> Result<(InputReader, Option<SchemaVersion>), InputError> {
+    let input_error = |error| InputError {
+        path: path.to_string(),
+        error,
+    };
+    let source: Box<dyn io::Read> = match path {
+        STDIN => Box::new(io::stdin()),
+        path => Box::new(fs::File::open(path).map_err(input_error)?),
+    };
+    let mut input = io::BufReader::new(RetryReader::new(source, policy));
+    let marker = read_marker(&mut input).map_err(input_error)?;
+    let rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(LineLimitReader::new(input, max_line_bytes));
+    Ok((rdr, marker))
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    trace: bool,
+    dedup_consecutive: bool,
+    /// Skips deposits and withdrawals already in the transaction store.
+    dedup_tx_ids: bool,
+    /// Checks that deposit and withdrawal ids increase, for
+    /// `--expect-monotonic-tx`.
+    monotonic_tx: Option<MonotonicTx>,
+    raw_errors: bool,
+    /// Collects failed rows into `Stats::error_report` instead of printing
+    /// them.
+    error_report: bool,
+    disputes_only: bool,
+    /// Creates a zero-balance account for every client seen in the input.
+    all_clients: bool,
+    collect_tx_ids: bool,
+    /// Sums the precision truncated from amounts into
+    /// `Stats::truncation_loss`.
+    truncation_report: bool,
+    /// Maps clients to the `institution` column into
+    /// `Stats::institutions`.
+    collect_institutions: bool,
+    report_blank_rows: bool,
+    /// Stops reading at the first failed row, for `--strict`.
+    strict: bool,
+    /// Longest row accepted, for `--max-line-bytes`.
+    max_line_bytes: Option<usize>,
+    /// Number of transactions to process before stopping, for `--as-of`.
+    as_of: Option<u64>,
+    /// Rows to process, for `--sample-rate`.
+    sample: Option<Sample>,
+    /// Processed transactions between two writes of the accounts they
+    /// touched, for `--flush-every`.
+    flush_every: Option<u64>,
+    /// Processed transactions between two periodic snapshots, for
+    /// `--snapshot-every`.
+    snapshot_every: Option<u64>,
+    /// Worker threads for `--threads`, rows are processed serially unless
+    /// it's above 1.
+    threads: usize,
+    /// Decimals input amounts are truncated to, for `--decimals`.
+    precision: Precision,
+    /// Smallest deposit or withdrawal accepted, for `--min-amount`.
+    min_amount: Option<Money>,
+    schema: SchemaVersion,
+}
+
+/// A reproducible subset of the rows, for `--sample-rate` and `--seed`.
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    rate: f64,
+    seed: u64,
+}
+
+impl Sample {
+    /// Whether the row at `index` is processed. Each row gets its own draw
+    /// seeded with the seed and its index, so the choice doesn't depend on
+    /// the rows before it.
+    fn keeps(&self, index: u64) -> bool {
+        let draw = xxhash_rust::xxh3::xxh3_64_with_seed(&index.to_le_bytes(), self.seed);
+        // The top 53 bits, as a uniform float in [0, 1).
+        ((draw >> 11) as f64 / (1u64 << 53) as f64) < self.rate
+    }
+}
+
+fn parse_sample_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("{} isn't a rate between 0 and 1", s)),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum MonotonicTx {
+    /// Reports the row but processes it anyway.
+    Report,
+    /// Reports the row and skips it.
+    Reject,
+}
+
+#[derive(Debug)]
+enum RowError {
+    Deserialize(csv::Error),
+    Parse(TXBuildError),
+    Process(TXError),
+    /// A deposit or withdrawal id not greater than the previous one.
+    NonMonotonicTx(u32, TX),
+    /// A line longer than `--max-line-bytes`, with the limit.
+    TooLong(usize),
+}
+
+impl RowError {
+    fn kind(&self) -> &'static str {
+        match self {
+            RowError::Deserialize(_) => "deserialize",
+            RowError::Parse(err) => err.kind(),
+            RowError::Process(err) => err.kind(),
+            RowError::NonMonotonicTx(..) => "non_monotonic_tx",
+            RowError::TooLong(..) => "line_too_long",
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowError::Deserialize(err) => write!(f, "Failed to deserialize transaction: {}", err),
+            RowError::Parse(err) => write!(f, "Failed to parse transaction: {}", err),
+            RowError::Process(err) => write!(f, "{}", err),
+            RowError::NonMonotonicTx(previous, tx) => write!(
+                f,
+                "OrderError: Transaction id {} isn't greater than the previous id {}: {:?}",
+                tx.tx(),
+                previous,
+                tx
+            ),
+            RowError::TooLong(limit) => {
+                write!(f, "Rejecting line longer than the {} bytes limit", limit)
+            }
+        }
+    }
+}
+
+/// A row error together with the record it came from, for `--raw-errors`.
+struct RawRowError<'a> {
+    error: &'a dyn fmt::Display,
+    record: &'a ByteRecord,
+}
+
+impl fmt::Display for RawRowError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw = raw_record(self.record);
+        match self.record.position() {
+            Some(pos) => write!(f, "{} [line {}: {}]", self.error, pos.line(), raw),
+            None => write!(f, "{} [{}]", self.error, raw),
+        }
+    }
+}
+
+/// The record as it would appear in a CSV file, without line terminator.
+fn raw_record(record: &ByteRecord) -> String {
+    let mut wtr = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    if wtr.write_byte_record(record).is_err() {
+        return String::new();
+    }
+    let raw = wtr.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&raw)
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+/// Reports a failed or skipped row on stderr, or collects it for
+/// `--error-report`.
+fn report_failure(
+    stats: &mut Stats,
+    options: &Options,
+    kind: &'static str,
+    message: String,
+    record: Option<&ByteRecord>,
+    line: Option<u64>,
+) {
+    if options.error_report {
+        stats.error_report.push(ProcessingError {
+            line,
+            record: record.map(raw_record).unwrap_or_default(),
+            kind,
+            message,
+        });
+    } else if let (true, Some(record)) = (options.raw_errors, record) {
+        eprintln!(
+            "{}",
+            RawRowError {
+                error: &message,
+                record,
+            }
+        );
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+fn parse_record(
+    record: &ByteRecord,
+    headers: &ByteRecord,
+    options: &Options,
+) -> Result<TX, RowError> {
+    let tx = match options.schema {
+        SchemaVersion::V1 => record.deserialize::<Transaction>(Some(headers)),
+        SchemaVersion::V2 => record
+            .deserialize::<TransactionV2>(Some(headers))
+            .map(|tx| tx.0),
+        SchemaVersion::V3 => record
+            .deserialize::<TransactionV3>(Some(headers))
+            .map(|tx| tx.0),
+    }
+    .map_err(RowError::Deserialize)?;
+    TX::from_transaction(tx)
+        .and_then(|tx| tx.with_precision(options.precision))
+        .and_then(|tx| match options.min_amount {
+            Some(minimum) => tx.with_min_amount(minimum),
+            None => Ok(tx),
+        })
+        .map_err(RowError::Parse)
+}
+
+/// Reports the lines over `--max-line-bytes` that come before the record
+/// starting at byte `start`, without echoing them back.
+fn report_rejected_lines<R: io::BufRead>(
+    rdr: &mut csv::Reader<LineLimitReader<R>>,
+    start: u64,
+    stats: &mut Stats,
+    options: &Options,
+) {
+    let Some(limit) = options.max_line_bytes else {
+        return;
+    };
+    while let Some(rejected) = rdr.get_mut().rejected_before(start) {
+        let err = RowError::TooLong(limit);
+        stats.rows += 1;
+        report_failure(
+            stats,
+            options,
+            err.kind(),
+            err.to_string(),
+            None,
+            Some(rejected),
+        );
+        stats.record_error(err.kind());
+    }
+}
+
+/// Position of the column named `name`, ignoring case and surrounding
+/// whitespace.
+fn column_index(headers: &ByteRecord, name: &str) -> Option<usize> {
+    headers.iter().position(|header| {
+        String::from_utf8_lossy(header)
+            .trim()
+            .eq_ignore_ascii_case(name)
+    })
+}
+
+/// The amount of a record as written, from its `amount` column or its
+/// `amount_major` and `amount_minor` ones, given their indices.
+fn amount_text(record: &ByteRecord, indices: [Option<usize>; 3]) -> Option<String> {
+    let [amount, major, minor] = indices.map(|index| {
+        index
+            .and_then(|index| record.get(index))
+            .and_then(|field| std::str::from_utf8(field).ok())
+            .filter(|field| !field.trim().is_empty())
+    });
+    match (amount, major, minor) {
+        (Some(amount), _, _) => Some(amount.to_string()),
+        (None, None, None) => None,
+        (None, major, minor) => major_minor_amount(major, minor).ok(),
+    }
+}
 
-    let mut rdr = csv::ReaderBuilder::new()
-        .flexible(true)
-        .from_path(input_path)
-        .unwrap();
-    let mut accounts = Accounts::new();
-    let mut transactions = Transactions::new();
-
-    for record in rdr.deserialize() {
-        let tx: Transaction = match record {
-            Ok(tx) => tx,
+/// Whether every field of the record is empty or whitespace, e.g. a
+/// trailing `   ` line or `,,,`.
+fn is_blank(record: &ByteRecord) -> bool {
+    record
+        .iter()
+        .all(|field| field.iter().all(u8::is_ascii_whitespace))
+}
+
+/// Parses every record for `--validate-only` without applying any. Well-formed
+/// records are counted as processed, malformed ones as errors.
+fn validate_records<R: io::BufRead>(
+    rdr: &mut csv::Reader<LineLimitReader<R>>,
+    options: &Options,
+) -> Stats {
+    let mut stats = Stats::default();
+    let headers = match rdr.byte_headers() {
+        Ok(headers) => headers.clone(),
+        Err(err) => {
+            let line = err.position().map(|pos| pos.line());
+            let message = format!("Failed to read headers: {}", err);
+            report_failure(&mut stats, options, "read", message, None, line);
+            stats.record_error("read");
+            return stats;
+        }
+    };
+    let type_index = column_index(&headers, "type");
+
+    let mut records = rdr.byte_records();
+    while let Some(record) = records.next() {
+        let start = match &record {
+            Ok(record) => record.position(),
+            Err(err) => err.position(),
+        };
+        report_rejected_lines(
+            records.reader_mut(),
+            start.map_or(u64::MAX, |pos| pos.byte()),
+            &mut stats,
+            options,
+        );
+        if options.strict && stats.errors > 0 {
+            break;
+        }
+        stats.rows += 1;
+        let record = match record {
+            Ok(record) => record,
             Err(err) => {
-                eprintln!("Failed to deserialize transaction: {}", err);
+                let line = err.position().map(|pos| pos.line());
+                let message = format!("Failed to read record: {}", err);
+                report_failure(&mut stats, options, "read", message, None, line);
+                stats.record_error("read");
                 continue;
             }
         };
+        if !options.report_blank_rows && is_blank(&record) {
+            stats.ignored += 1;
+            continue;
+        }
+        let is_marker = type_index
+            .and_then(|index| record.get(index))
+            .and_then(BatchMarker::parse)
+            .is_some();
+        if is_marker {
+            continue;
+        }
+        if options
+            .sample
+            .is_some_and(|sample| !sample.keeps(stats.rows))
+        {
+            stats.ignored += 1;
+            continue;
+        }
+        match parse_record(&record, &headers, options) {
+            Ok(tx) => stats.record_processed(tx.name()),
+            Err(err) => {
+                let line = record.position().map(|pos| pos.line());
+                report_failure(
+                    &mut stats,
+                    options,
+                    err.kind(),
+                    err.to_string(),
+                    Some(&record),
+                    line,
+                );
+                stats.record_error(err.kind());
+            }
+        }
+    }
+    if !options.strict || stats.errors == 0 {
+        report_rejected_lines(rdr, u64::MAX, &mut stats, options);
+    }
+    stats
+}
+
+fn process_records<R: io::BufRead, A: AccountStore>(
+    rdr: &mut csv::Reader<LineLimitReader<R>>,
+    engine: &mut Engine<A>,
+    options: &Options,
+    interrupt: &Interrupt,
+    mut stream: Option<&mut AccountStream<&mut dyn io::Write>>,
+    mut snapshots: Option<&mut PeriodicSnapshots>,
+) -> Stats {
+    let mut stats = Stats::default();
+    let headers = match rdr.byte_headers() {
+        Ok(headers) => headers.clone(),
+        Err(err) => {
+            let line = err.position().map(|pos| pos.line());
+            let message = format!("Failed to read headers: {}", err);
+            report_failure(&mut stats, options, "read", message, None, line);
+            stats.record_error("read");
+            return stats;
+        }
+    };
+    let type_index = column_index(&headers, "type");
+    let amount_indices = [
+        column_index(&headers, "amount"),
+        column_index(&headers, "amount_major"),
+        column_index(&headers, "amount_minor"),
+    ];
+    let institution_index = column_index(&headers, "institution");
+    let mut previous: Option<ByteRecord> = None;
+    let mut batch = Batch::Idle;
+    // Rows processed inside the open batch, taken back from the stats if it
+    // is rolled back.
+    let mut uncommitted = Vec::new();
+    let mut seen = 0;
+    let mut clients = HashSet::new();
+    let mut touched = BTreeSet::new();
+    let mut pending = Vec::new();
+    let mut last_tx = None;
+    let mut since_snapshot = 0;
+
+    let mut records = rdr.byte_records();
+    while let Some(record) = records.next() {
+        if interrupt.is_triggered() {
+            eprintln!("Interrupted, writing accounts processed so far");
+            break;
+        }
+        let start = match &record {
+            Ok(record) => record.position(),
+            Err(err) => err.position(),
+        };
+        report_rejected_lines(
+            records.reader_mut(),
+            start.map_or(u64::MAX, |pos| pos.byte()),
+            &mut stats,
+            options,
+        );
+        if options.strict && stats.errors > 0 {
+            break;
+        }
 
-        let transaction = match TX::from_transaction(tx) {
-            Ok(transaction) => transaction,
+        stats.rows += 1;
+        let record = match record {
+            Ok(record) => record,
             Err(err) => {
-                eprintln!("Failed to parse transaction: {}", err);
+                let line = err.position().map(|pos| pos.line());
+                let message = format!("Failed to read record: {}", err);
+                report_failure(&mut stats, options, "read", message, None, line);
+                stats.record_error("read");
                 continue;
             }
         };
+        if !options.report_blank_rows && is_blank(&record) {
+            stats.ignored += 1;
+            continue;
+        }
+        if options.dedup_consecutive {
+            if previous.as_ref() == Some(&record) {
+                let line = record.position().map(|pos| pos.line());
+                let message = format!(
+                    "Skipping duplicate consecutive row: {}",
+                    raw_record(&record)
+                );
+                report_failure(
+                    &mut stats,
+                    options,
+                    "duplicate_row",
+                    message,
+                    Some(&record),
+                    line,
+                );
+                stats.duplicates += 1;
+                continue;
+            }
+            previous = Some(record.clone());
+        }
+
+        let marker = type_index
+            .and_then(|index| record.get(index))
+            .and_then(BatchMarker::parse);
+        let line = record.position().map(|pos| pos.line());
+        if let Some(marker) = marker {
+            // Batches are applied serially, after everything queued before
+            // them.
+            process_pending(engine, std::mem::take(&mut pending), &mut stats, options);
+            batch = match (marker, std::mem::take(&mut batch)) {
+                (BatchMarker::Begin, Batch::Idle) => Batch::Open(Journal::default()),
+                (BatchMarker::Commit, Batch::Open(_)) => {
+                    uncommitted.clear();
+                    Batch::Idle
+                }
+                (BatchMarker::Rollback, Batch::Open(journal)) => {
+                    rollback(engine, journal, &mut stats, &mut uncommitted);
+                    Batch::Idle
+                }
+                (BatchMarker::Commit | BatchMarker::Rollback, Batch::Aborted) => Batch::Idle,
+                (marker, state) => {
+                    let message = format!("Unexpected batch marker {:?} in {:?}", marker, record);
+                    report_failure(&mut stats, options, "batch", message, Some(&record), line);
+                    stats.record_error("batch");
+                    state
+                }
+            };
+            continue;
+        }
+        if options.as_of.is_some_and(|as_of| seen >= as_of) {
+            stats.rows -= 1;
+            break;
+        }
+        seen += 1;
+        if let Batch::Aborted = batch {
+            let message = format!("Skipping row of rolled back batch: {}", raw_record(&record));
+            report_failure(
+                &mut stats,
+                options,
+                "rolled_back",
+                message,
+                Some(&record),
+                line,
+            );
+            continue;
+        }
+        if options
+            .sample
+            .is_some_and(|sample| !sample.keeps(stats.rows))
+        {
+            stats.ignored += 1;
+            continue;
+        }
 
-        match process_transaction(transaction, &mut accounts, &mut transactions) {
-            Ok(_) => (),
-            Err(err) => eprintln!("{}", err),
+        let result = parse_record(&record, &headers, options);
+        if let (true, Ok(TX::Deposit(_) | TX::Withdrawal(_))) = (options.truncation_report, &result)
+        {
+            if let Some(amount) = amount_text(&record, amount_indices) {
+                stats.truncation_loss += TruncationLoss::of(&amount, options.precision);
+            }
+        }
+        if let (true, Ok(tx)) = (options.collect_institutions, &result) {
+            let institution = institution_index
+                .and_then(|index| record.get(index))
+                .map(|field| String::from_utf8_lossy(field).trim().to_string())
+                .filter(|institution| !institution.is_empty());
+            if let Some(institution) = institution {
+                stats.institutions.entry(tx.client()).or_insert(institution);
+            }
+        }
+        if let (true, Ok(tx)) = (options.all_clients, &result) {
+            clients.insert(tx.client());
+        }
+        if options.disputes_only && matches!(&result, Ok(tx) if !tx.is_dispute_flow()) {
+            stats.ignored += 1;
+            continue;
+        }
+        if options.dedup_tx_ids && matches!(&result, Ok(tx) if engine.is_recorded(tx)) {
+            let message = format!(
+                "Skipping already processed transaction: {}",
+                raw_record(&record)
+            );
+            report_failure(
+                &mut stats,
+                options,
+                "already_processed",
+                message,
+                Some(&record),
+                line,
+            );
+            stats.duplicates += 1;
+            continue;
+        }
+        if let (Some(check), Ok(tx)) = (options.monotonic_tx, &result) {
+            if !tx.is_dispute_flow() {
+                if let Some(previous) = last_tx.filter(|previous| tx.tx() <= *previous) {
+                    let err = RowError::NonMonotonicTx(previous, tx.clone());
+                    report_failure(
+                        &mut stats,
+                        options,
+                        err.kind(),
+                        err.to_string(),
+                        Some(&record),
+                        line,
+                    );
+                    if let MonotonicTx::Reject = check {
+                        stats.record_error(err.kind());
+                        continue;
+                    }
+                }
+                last_tx = Some(tx.tx());
+            }
+        }
+        let result = match result {
+            Ok(transaction) if options.threads > 1 && matches!(batch, Batch::Idle) => {
+                pending.push((transaction, record, line));
+                if pending.len() >= PARALLEL_CHUNK_ROWS {
+                    process_pending(engine, std::mem::take(&mut pending), &mut stats, options);
+                }
+                continue;
+            }
+            result => result,
+        };
+        let result = result.and_then(|transaction| {
+            if let Batch::Open(journal) = &mut batch {
+                engine.journal(journal, &transaction);
+            }
+            let name = transaction.name();
+            let id = transaction.tx();
+            let client = transaction.client();
+            let traced = options.trace.then(|| transaction.clone());
+            engine
+                .process(transaction)
+                .map(|_| (name, id, client, traced))
+                .map_err(RowError::Process)
+        });
+        match result {
+            Ok((name, id, client, traced)) => {
+                record_processed(&mut stats, options, name, id, traced);
+                if let Batch::Open(_) = batch {
+                    uncommitted.push((name, id));
+                }
+                if let (Some(every), Some(stream)) = (options.flush_every, stream.as_deref_mut()) {
+                    touched.insert(client);
+                    if stats.processed % every == 0 {
+                        let accounts = engine.accounts();
+                        let rows = touched
+                            .iter()
+                            .filter_map(|client| Some((client, accounts.account(*client)?)));
+                        if let Err(err) = stream.write(rows) {
+                            eprintln!("Failed to flush accounts: {}", err);
+                        }
+                        touched.clear();
+                    }
+                }
+                if let (Some(every), Some(snapshots)) =
+                    (options.snapshot_every, snapshots.as_deref_mut())
+                {
+                    since_snapshot += 1;
+                    // Snapshots only hold committed state, one due inside a
+                    // batch waits for the batch to end.
+                    if since_snapshot >= every && matches!(batch, Batch::Idle) {
+                        since_snapshot = 0;
+                        if let Err(err) =
+                            snapshots.write(&engine.accounts().as_accounts(), engine.transactions())
+                        {
+                            eprintln!("Failed to write periodic snapshot: {}", err);
+                        }
+                    }
+                }
+            }
+            Err(RowError::Process(TXError::TypeDisabled(_))) => stats.ignored += 1,
+            Err(err) => {
+                report_failure(
+                    &mut stats,
+                    options,
+                    err.kind(),
+                    err.to_string(),
+                    Some(&record),
+                    line,
+                );
+                stats.record_error(err.kind());
+                if let Batch::Open(journal) = std::mem::take(&mut batch) {
+                    eprintln!("Rolling back batch after error");
+                    rollback(engine, journal, &mut stats, &mut uncommitted);
+                    batch = Batch::Aborted;
+                }
+            }
         }
     }
 
-    write_accounts(&accounts, io::stdout())
+    if !interrupt.is_triggered() && (!options.strict || stats.errors == 0) {
+        report_rejected_lines(rdr, u64::MAX, &mut stats, options);
+    }
+    process_pending(engine, pending, &mut stats, options);
+    if let Batch::Open(journal) = batch {
+        eprintln!("Rolling back batch that was never committed");
+        rollback(engine, journal, &mut stats, &mut uncommitted);
+    }
+    for client in clients {
+        engine.accounts_mut().account_or_default(client);
+    }
+
+    stats
 }
 
-fn write_accounts(accounts: &Accounts, wtr: impl io::Write) {
-    let mut writer = Writer::from_writer(wtr);
-    let mut acc: OutputAccount;
-    for (client, account) in accounts {
-        acc = OutputAccount::new(client, account);
-        match writer.serialize(acc) {
-            Ok(_) => (),
-            Err(err) => eprintln!("Failed to write account: {}", err),
-        }
+/// Undoes the open batch and takes its rows back out of the stats.
+fn rollback<A: AccountStore>(
+    engine: &mut Engine<A>,
+    journal: Journal,
+    stats: &mut Stats,
+    uncommitted: &mut Vec<(&'static str, u32)>,
+) {
+    engine.rollback(journal);
+    for (name, id) in uncommitted.drain(..) {
+        stats.unrecord_processed(name);
+        stats.tx_ids.remove(&id);
     }
-    writer.flush().unwrap();
 }
 
-#[derive(Debug, Serialize)]
-struct OutputAccount {
-    client: u16,
-    #[serde(serialize_with = "truncate_serialize")]
-    available: f64,
-    #[serde(serialize_with = "truncate_serialize")]
-    held: f64,
-    #[serde(serialize_with = "truncate_serialize")]
-    total: f64,
-    locked: bool,
+fn record_processed(
+    stats: &mut Stats,
+    options: &Options,
+    name: &'static str,
+    id: u32,
+    traced: Option<TX>,
+) {
+    stats.record_processed(name);
+    if options.collect_tx_ids {
+        stats.tx_ids.insert(id);
+    }
+    if let Some(transaction) = traced {
+        eprintln!("Processed {}", transaction);
+    }
 }
 
-impl OutputAccount {
-    fn new(client: &u16, account: &Account) -> Self {
-        Self {
-            client: *client,
-            available: account.available,
-            held: account.held,
-            total: account.total,
-            locked: account.locked,
+/// Processes the rows queued for `--threads` on the worker threads and
+/// records their outcomes in input order.
+fn process_pending<A: AccountStore>(
+    engine: &mut Engine<A>,
+    pending: Vec<(TX, ByteRecord, Option<u64>)>,
+    stats: &mut Stats,
+    options: &Options,
+) {
+    if pending.is_empty() {
+        return;
+    }
+    let mut rows = Vec::with_capacity(pending.len());
+    let mut txs = Vec::with_capacity(pending.len());
+    for (transaction, record, line) in pending {
+        let traced = options.trace.then(|| transaction.clone());
+        rows.push((transaction.name(), transaction.tx(), traced, record, line));
+        txs.push(transaction);
+    }
+    let report = engine.process_parallel(txs, options.threads);
+    let mut errors = report.errors.into_iter().peekable();
+    for (index, (name, id, traced, record, line)) in rows.into_iter().enumerate() {
+        match errors
+            .next_if(|err| err.index == index)
+            .map(|err| err.error)
+        {
+            None => record_processed(stats, options, name, id, traced),
+            Some(TXError::TypeDisabled(_)) => stats.ignored += 1,
+            Some(error) => {
+                let err = RowError::Process(error);
+                report_failure(
+                    stats,
+                    options,
+                    err.kind(),
+                    err.to_string(),
+                    Some(&record),
+                    line,
+                );
+                stats.record_error(err.kind());
+            }
         }
     }
 }
 
-fn truncate_serialize<S>(x: &f64, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    s.serialize_f64(truncate(*x))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
+    use transaction_processor::process_transaction::*;
+
+    fn reader(data: &str) -> csv::Reader<LineLimitReader<&[u8]>> {
+        csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(LineLimitReader::new(data.as_bytes(), None))
+    }
 
     #[test]
-    fn test_write_accounts() {
-        let mut accounts = Accounts::new();
-        accounts.insert(
-            1,
-            Account {
-                available: 1.0,
-                held: 0.0,
-                total: 1.0,
-                locked: false,
-            },
+    fn test_interrupt_flushes_processed_accounts() {
+        let mut engine = Engine::default();
+        let interrupt = Interrupt::new();
+
+        let mut rdr = reader("type,client,tx,amount\ndeposit,1,1,1.0\n");
+        process_records(
+            &mut rdr,
+            &mut engine,
+            &Options::default(),
+            &interrupt,
+            None,
+            None,
         );
-        accounts.insert(
-            2,
-            Account {
-                available: 2.0,
-                held: 0.0,
-                total: 2.0,
-                locked: false,
-            },
+
+        interrupt.trigger();
+
+        let mut rdr = reader("type,client,tx,amount\ndeposit,1,2,5.0\ndeposit,2,3,1.0\n");
+        process_records(
+            &mut rdr,
+            &mut engine,
+            &Options::default(),
+            &interrupt,
+            None,
+            None,
+        );
+
+        let mut buf = Vec::new();
+        write_accounts(engine.accounts(), &mut buf, &OutputOptions::default());
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_dedup_consecutive() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n";
+        let options = Options {
+            dedup_consecutive: true,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::default();
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(engine.accounts()[&1].total, Money::from(2.0));
+        assert_eq!((stats.duplicates, stats.errors), (1, 0));
+
+        let options = Options {
+            error_report: true,
+            ..options
+        };
+        let stats = process_records(
+            &mut reader(data),
+            &mut Engine::default(),
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(stats.error_report.len(), 1);
+        let skipped = &stats.error_report[0];
+        assert_eq!(
+            (skipped.line, skipped.kind, skipped.record.as_str()),
+            (Some(3), "duplicate_row", "deposit,1,1,1.0")
+        );
+        assert_eq!(
+            skipped.message,
+            "Skipping duplicate consecutive row: deposit,1,1,1.0"
+        );
+
+        let mut engine = Engine::default();
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &Options::default(),
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(engine.accounts()[&1].total, Money::from(2.0));
+        assert_eq!(stats.errors_by_kind.get("duplicate_transaction"), Some(&1));
+    }
+
+    #[test]
+    fn test_dedup_tx_ids_across_files() {
+        let first = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+        let second = "type,client,tx,amount\ndeposit,1,2,2.0\ndeposit,1,3,4.0\ndispute,1,2,\n";
+        let options = Options {
+            dedup_tx_ids: true,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::default();
+        let mut duplicates = 0;
+        for data in [first, second] {
+            let stats = process_records(
+                &mut reader(data),
+                &mut engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            );
+            duplicates += stats.duplicates;
+        }
+        assert_eq!(duplicates, 1);
+        let account = &engine.accounts()[&1];
+        assert_eq!(account.total, Money::from(7.0));
+        assert_eq!(account.held, Money::from(2.0));
+
+        let options = Options {
+            error_report: true,
+            ..options
+        };
+        let stats = process_records(
+            &mut reader(second),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
         );
+        let kinds: Vec<&str> = stats.error_report.iter().map(|err| err.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                "already_processed",
+                "already_processed",
+                "parent_tx_already_disputed"
+            ]
+        );
+        assert_eq!(stats.error_report[0].line, Some(2));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,2.0
+deposit,1,1,2.0
+withdrawal,1,2,5.0
+bogus,1,3,1.0
+";
+        let options = Options {
+            dedup_consecutive: true,
+            ..Default::default()
+        };
+        let mut engine = Engine::default();
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+
+        let mut output = ChecksumWriter::new(Vec::new());
+        write_accounts(engine.accounts(), &mut output, &OutputOptions::default());
+        let manifest = Manifest::new(
+            vec!["transactions.csv".to_string()],
+            &stats,
+            engine.accounts().len(),
+            ManifestConfig::new(options.precision, options.dedup_consecutive),
+            output.checksum(),
+        );
+
+        let value = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(value["inputs"], serde_json::json!(["transactions.csv"]));
+        assert_eq!(value["rows"], 4);
+        assert_eq!(value["processed"], 1);
+        assert_eq!(value["duplicates"], 1);
+        assert_eq!(value["errors"], 2);
+        assert_eq!(value["accounts"], 1);
+        assert_eq!(value["config"]["scale"], 4);
+        assert_eq!(value["config"]["rounding"], "truncate");
+        assert_eq!(value["config"]["dedup_consecutive"], true);
+        assert_eq!(
+            value["output_checksum"],
+            format!(
+                "xxh3:{:016x}",
+                xxhash_rust::xxh3::xxh3_64(
+                    b"client,available,held,total,locked\n1,2.0,0.0,2.0,false\n"
+                )
+            )
+        );
+    }
+
+    fn process(data: &str) -> (Accounts, Transactions) {
+        let mut engine = Engine::default();
+        process_records(
+            &mut reader(data),
+            &mut engine,
+            &Options::default(),
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        engine.into_state()
+    }
+
+    #[test]
+    fn test_as_of() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,3.0
+deposit,2,2,2.0
+
+withdrawal,1,3,1.0
+dispute,2,2,
+";
+        let mut engine = Engine::default();
+        let options = Options {
+            as_of: Some(3),
+            ..Default::default()
+        };
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        let (accounts, transactions) = engine.into_state();
+        assert_eq!(stats.processed, 3);
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(2.0));
+        assert_eq!(accounts.get(&2).unwrap().available, Money::from(2.0));
+        assert_eq!(accounts.get(&2).unwrap().held, Money::ZERO);
+        assert!(!transactions.get(&2.into()).unwrap().disputed);
+    }
 
+    #[test]
+    fn test_flush_every() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+deposit,1,3,1.0
+dispute,1,1,
+withdrawal,2,4,9.0
+deposit,3,5,1.0
+";
+        let options = Options {
+            flush_every: Some(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::default();
         let mut buf = Vec::new();
-        write_accounts(&accounts, &mut buf);
+        let mut stream =
+            AccountStream::new(&mut buf as &mut dyn io::Write, OutputOptions::default());
+        process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            Some(&mut stream),
+            None,
+        );
+        drop(stream);
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\
+client,available,held,total,locked
+1,1.0,0.0,1.0,false
+2,2.0,0.0,2.0,false
+1,1.0,1.0,2.0,false
+"
+        );
+    }
+
+    #[test]
+    fn test_decimals() {
+        let data =
+            "type,client,tx,amount\ndeposit,1,1,1.239\ndeposit,1,2,0.009\nwithdrawal,1,3,0.011\n";
+        let options = Options {
+            precision: Precision::new(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::default();
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(stats.errors_by_kind.get("amount_below_precision"), Some(&1));
+        assert_eq!(engine.accounts()[&1].total, Money::from(1.22));
+    }
+
+    #[test]
+    fn test_threads_match_serial() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+withdrawal,1,3,9.0
+begin,,,
+deposit,3,4,1.0
+dispute,2,2,
+commit,,,
+deposit,4,5,2.0
+dispute,1,1,
+chargeback,1,1,
+withdrawal,2,6,1.0
+bogus,2,7,1.0
+";
+        let run = |threads| {
+            let options = Options {
+                threads,
+                ..Default::default()
+            };
+            let mut engine = Engine::default();
+            let stats = process_records(
+                &mut reader(data),
+                &mut engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            );
+            let accounts: Vec<_> = engine
+                .accounts()
+                .iter()
+                .map(|(client, acc)| (*client, acc.available, acc.held, acc.total, acc.locked))
+                .collect();
+            (stats, accounts)
+        };
+
+        let (serial_stats, serial) = run(1);
+        let (stats, accounts) = run(3);
+        assert_eq!(accounts, serial);
+        assert_eq!(stats.processed, serial_stats.processed);
+        assert_eq!(stats.errors_by_kind, serial_stats.errors_by_kind);
+        assert_eq!(stats.errors, 3);
+    }
+
+    #[test]
+    fn test_dense_accounts_match_map() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,3.0
+withdrawal,1,3,9.0
+begin,,,
+deposit,3,4,1.0
+dispute,2,2,
+rollback,,,
+deposit,6,5,2.0
+dispute,1,1,
+chargeback,1,1,
+withdrawal,2,6,1.0
+";
+        let output = |accounts: &Accounts| {
+            let mut buf = Vec::new();
+            write_accounts(accounts, &mut buf, &OutputOptions::default());
+            String::from_utf8(buf).unwrap()
+        };
+        fn run<A: AccountStore>(engine: &mut Engine<A>, data: &str, threads: usize) -> Stats {
+            let options = Options {
+                threads,
+                ..Default::default()
+            };
+            process_records(
+                &mut reader(data),
+                engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            )
+        }
+
+        let mut map = Engine::default();
+        let expected_stats = run(&mut map, data, 1);
+        let expected = output(map.accounts());
+        // Client 6 doesn't fit the smaller range and moves the store to a map.
+        for (range, dense) in [(8, true), (4, false)] {
+            for threads in [1, 2] {
+                let store = DenseAccounts::with_range(range);
+                let mut engine = Engine::with_state(Config::default(), store, Transactions::new());
+                assert_eq!(run(&mut engine, data, threads), expected_stats);
+                assert_eq!(engine.accounts().is_dense(), dense);
+                assert_eq!(output(&engine.into_state().0.into_accounts()), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_clients() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,2,2,5.0\n";
+        let (accounts, _) = process(data);
+        assert!(!accounts.contains_key(&2));
+
+        let mut engine = Engine::default();
+        let options = Options {
+            all_clients: true,
+            ..Default::default()
+        };
+        process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        let accounts = engine.accounts();
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(1.0));
+        let account = accounts.get(&2).unwrap();
+        assert_eq!(
+            (
+                account.available,
+                account.held,
+                account.total,
+                account.locked
+            ),
+            (Money::ZERO, Money::ZERO, Money::ZERO, false)
+        );
+    }
 
-        let expected1 = "\
-client,available,held,total,locked\n\
-1,1.0,0.0,1.0,false\n\
-2,2.0,0.0,2.0,false\n\
+    #[test]
+    fn test_error_report() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,1,2,5.0
+deposit,1,x,1.0
 ";
-        let expected2 = "\
-client,available,held,total,locked\n\
-2,2.0,0.0,2.0,false\n\
-1,1.0,0.0,1.0,false\n\
+        let options = Options {
+            error_report: true,
+            ..Default::default()
+        };
+        let mut engine = Engine::default();
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        let errors = &stats.error_report;
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].line, Some(3));
+        assert_eq!(errors[0].record, "withdrawal,1,2,5.0");
+        assert_eq!(errors[0].kind, "not_enough_funds");
+        assert_eq!(errors[1].line, Some(4));
+        assert_eq!(errors[1].kind, "deserialize");
+
+        let mut buf = Vec::new();
+        write_error_report(errors, &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json[0]["record"], "withdrawal,1,2,5.0");
+        assert_eq!(json[1]["line"], 4);
+    }
+
+    #[test]
+    fn test_leading_zero_client_ids() {
+        let (accounts, _) =
+            process("type,client,tx,amount\ndeposit,007,1,5.0\nwithdrawal,7,2,2.0\n");
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts.get(&7).unwrap().total, Money::from(3.0));
+    }
+
+    #[test]
+    fn test_batch_commit() {
+        let (accounts, transactions) = process(
+            "\
+type,client,tx,amount
+deposit,1,1,1.0
+begin,,,
+deposit,1,2,2.0
+withdrawal,1,3,0.5
+commit,,,
+",
+        );
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(2.5));
+        assert_eq!(transactions.len(), 3);
+    }
+
+    #[test]
+    fn test_batch_rollback() {
+        let (accounts, transactions) = process(
+            "\
+type,client,tx,amount
+deposit,1,1,1.0
+begin,,,
+deposit,1,2,2.0
+deposit,2,3,2.0
+dispute,1,1,
+rollback,,,
+deposit,1,4,1.0
+",
+        );
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts.get(&1).unwrap().available, Money::from(2.0));
+        assert_eq!(accounts.get(&1).unwrap().held, Money::ZERO);
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(2.0));
+        assert!(!transactions.get(&1.into()).unwrap().disputed);
+        assert!(!transactions.contains_key(&2.into()));
+        assert!(!transactions.contains_key(&3.into()));
+    }
+
+    #[test]
+    fn test_batch_error_rolls_back() {
+        let (accounts, transactions) = process(
+            "\
+type,client,tx,amount
+deposit,1,1,1.0
+begin,,,
+deposit,1,2,2.0
+withdrawal,1,3,10.0
+deposit,1,4,2.0
+commit,,,
+deposit,1,5,1.0
+",
+        );
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(2.0));
+        assert!(!transactions.contains_key(&2.into()));
+        assert!(!transactions.contains_key(&4.into()));
+        assert!(transactions.contains_key(&5.into()));
+    }
+
+    #[test]
+    fn test_batch_never_committed() {
+        let (accounts, _) = process(
+            "\
+type,client,tx,amount
+deposit,1,1,1.0
+begin,,,
+deposit,1,2,2.0
+",
+        );
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(1.0));
+    }
+
+    #[test]
+    fn test_batch_rollback_stats() {
+        let data = "\
+type,client,tx,amount
+deposit,1,1,1.0
+begin,,,
+deposit,1,2,2.0
+commit,,,
+begin,,,
+deposit,1,3,2.0
+rollback,,,
+begin,,,
+deposit,1,4,2.0
+withdrawal,1,5,10.0
+commit,,,
+begin,,,
+withdrawal,1,6,1.0
 ";
-        let expected = if buf == expected1.as_bytes() {
-            expected1
-        } else {
-            expected2
+        let options = Options {
+            collect_tx_ids: true,
+            ..Options::default()
+        };
+        let stats = process_records(
+            &mut reader(data),
+            &mut Engine::default(),
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.processed_by_type, BTreeMap::from([("deposit", 2)]));
+        assert_eq!(stats.tx_ids, BTreeSet::from([1, 2]));
+        assert_eq!(stats.errors, 1);
+    }
+
+    #[test]
+    fn test_raw_row_error() {
+        let mut rdr = reader("type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal, 1,2,5.0\n");
+        let headers = rdr.byte_headers().unwrap().clone();
+        let mut records = rdr.byte_records();
+        let mut accounts = Accounts::new();
+        let mut transactions = Transactions::new();
+        let config = Config::default();
+
+        let record = records.next().unwrap().unwrap();
+        let tx = parse_record(&record, &headers, &Options::default()).unwrap();
+        process_transaction(tx, &mut accounts, &mut transactions, &config).unwrap();
+
+        let record = records.next().unwrap().unwrap();
+        let tx = parse_record(&record, &headers, &Options::default()).unwrap();
+        let error = RowError::Process(
+            process_transaction(tx, &mut accounts, &mut transactions, &config).unwrap_err(),
+        );
+        let message = RawRowError {
+            error: &error,
+            record: &record,
+        }
+        .to_string();
+        assert!(message.starts_with("TransactionError: Not enough funds."));
+        assert!(message.ends_with("[line 3: withdrawal, 1,2,5.0]"));
+    }
+
+    #[test]
+    fn test_disputes_only_against_snapshot() {
+        let snapshot = r#"{
+            "accounts": {
+                "1": {"available": 5.0, "held": 0.0, "total": 5.0, "locked": false}
+            },
+            "transactions": {
+                "1": {"client": 1, "amount": 5.0, "disputed": false, "destination": null}
+            }
+        }"#;
+        let Snapshot {
+            accounts,
+            transactions,
+        } = Snapshot::from_reader(snapshot.as_bytes()).unwrap();
+        let mut engine = Engine::with_state(Config::default(), accounts, transactions);
+        let options = Options {
+            disputes_only: true,
+            ..Default::default()
+        };
+
+        let stats = process_records(
+            &mut reader(
+                "type,client,tx,amount\ndeposit,1,2,3.0\ndispute,1,1,\nwithdrawal,1,3,1.0\n",
+            ),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        let (accounts, transactions) = engine.into_state();
+        assert_eq!(stats.ignored, 2);
+        assert_eq!(stats.processed, 1);
+        assert_eq!(accounts.get(&1).unwrap().available, Money::ZERO);
+        assert_eq!(accounts.get(&1).unwrap().held, Money::from(5.0));
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(5.0));
+        assert!(!transactions.contains_key(&2.into()));
+    }
+
+    #[test]
+    fn test_tx_set_hash() {
+        let options = Options {
+            collect_tx_ids: true,
+            ..Default::default()
+        };
+        let hash = |data: &str| {
+            let mut engine = Engine::default();
+            let stats = process_records(
+                &mut reader(data),
+                &mut engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            );
+            tx_set_hash(&stats.tx_ids)
+        };
+
+        let first =
+            hash("type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\nwithdrawal,1,3,1.0\n");
+        let reordered =
+            hash("type,client,tx,amount\ndeposit,2,2,3.0\ndeposit,1,1,5.0\nwithdrawal,1,3,1.0\n");
+        let other =
+            hash("type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,4,3.0\nwithdrawal,1,3,1.0\n");
+        assert_eq!(first, reordered);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_disabled_type() {
+        let mut engine = Engine::new(Config {
+            disabled: HashSet::from([TXType::Withdrawal]),
+            ..Default::default()
+        });
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.0\ndeposit,1,3,2.0\nwithdrawal,1,4,9.0\n";
+
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &Options::default(),
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        let (accounts, transactions) = engine.into_state();
+        assert_eq!(stats.processed, 2);
+        assert_eq!(stats.ignored, 2);
+        assert_eq!(stats.errors, 0);
+        assert_eq!(accounts.get(&1).unwrap().available, Money::from(7.0));
+        assert_eq!(accounts.get(&1).unwrap().total, Money::from(7.0));
+        assert!(!transactions.contains_key(&2.into()));
+    }
+
+    #[test]
+    fn test_blank_rows() {
+        let data = "type,client,tx,amount\ndeposit,1,1,5.0\n   \n,,,\ndeposit,1,2,1.0\n\n  \t \n\n";
+
+        let mut engine = Engine::default();
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &Options::default(),
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(engine.accounts()[&1].total, Money::from(6.0));
+        assert_eq!(stats.errors, 0);
+        assert_eq!(stats.processed, 2);
+
+        let options = Options {
+            report_blank_rows: true,
+            ..Default::default()
+        };
+        let mut engine = Engine::default();
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(stats.errors, 3);
+    }
+
+    #[test]
+    fn test_expect_monotonic_tx() {
+        let data = "type,client,tx,amount\n\
+deposit,1,1,5.0\n\
+deposit,1,3,1.0\n\
+dispute,1,1,\n\
+withdrawal,1,2,1.0\n\
+deposit,1,4,1.0\n";
+        let run = |monotonic_tx| {
+            let options = Options {
+                monotonic_tx,
+                error_report: true,
+                ..Default::default()
+            };
+            let mut engine = Engine::default();
+            let stats = process_records(
+                &mut reader(data),
+                &mut engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            );
+            (stats, engine.accounts()[&1].clone())
+        };
+
+        let (stats, account) = run(Some(MonotonicTx::Reject));
+        assert_eq!(stats.errors_by_kind.get("non_monotonic_tx"), Some(&1));
+        assert_eq!(stats.error_report.len(), 1);
+        assert_eq!(stats.error_report[0].line, Some(5));
+        assert_eq!(stats.processed, 4);
+        assert_eq!(account.total, Money::from(7.0));
+
+        let (stats, account) = run(Some(MonotonicTx::Report));
+        assert_eq!(stats.errors, 0);
+        assert_eq!(stats.error_report.len(), 1);
+        assert_eq!(stats.processed, 5);
+        assert_eq!(account.total, Money::from(6.0));
+
+        let (stats, _) = run(None);
+        assert!(stats.error_report.is_empty());
+    }
+
+    #[test]
+    fn test_schema_v3_direction() {
+        let run = |data: &str, schema| {
+            let options = Options {
+                schema,
+                ..Default::default()
+            };
+            let mut engine = Engine::new(Config::default());
+            let stats = process_records(
+                &mut reader(data),
+                &mut engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            );
+            assert_eq!(stats.errors, 0);
+            engine.into_state()
+        };
+
+        let typed = run(
+            "type,client,tx,amount\n\
+deposit,1,1,5.0\n\
+withdrawal,1,2,1.5\n\
+deposit,2,3,2.0\n\
+dispute,2,3,\n",
+            SchemaVersion::V1,
+        );
+        let directed = run(
+            "type,direction,client,tx,amount\n\
+,credit,1,1,5.0\n\
+,debit,1,2,1.5\n\
+,credit,2,3,2.0\n\
+dispute,,2,3,\n",
+            SchemaVersion::V3,
+        );
+        assert_eq!(format!("{:?}", typed.0), format!("{:?}", directed.0));
+        assert_eq!(directed.0[&1].total, Money::from(3.5));
+        assert_eq!(directed.0[&2].held, Money::from(2.0));
+    }
+
+    #[test]
+    fn test_truncation_report() {
+        let data = "type,client,tx,amount\n\
+deposit,1,1,2500.12345\n\
+deposit,1,2,1.00009\n\
+withdrawal,1,3,0.50001\n\
+deposit,1,4,3.5\n\
+dispute,1,4,0.00001\n";
+        let run = |precision| {
+            let options = Options {
+                truncation_report: true,
+                precision,
+                ..Default::default()
+            };
+            let mut engine = Engine::new(Config::default());
+            process_records(
+                &mut reader(data),
+                &mut engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            )
+        };
+
+        let stats = run(Precision::default());
+        assert_eq!(stats.truncation_loss.to_string(), "0.00015");
+        let stats = run(Precision::new(2));
+        assert_eq!(stats.truncation_loss.to_string(), "0.00355");
+    }
+
+    #[test]
+    fn test_truncation_report_major_minor() {
+        let data = "type,client,tx,amount_major,amount_minor\n\
+deposit,1,1,2500,1234\n\
+withdrawal,1,2,1,0009\n\
+deposit,1,3,3,\n";
+        let options = Options {
+            truncation_report: true,
+            precision: Precision::new(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::new(Config::default());
+        let stats = process_records(
+            &mut reader(data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(stats.processed, 3);
+        assert_eq!(stats.truncation_loss.to_string(), "0.0043");
+    }
+
+    #[test]
+    fn test_validate_records() {
+        let data = "type,client,tx,amount\n\
+deposit,1,1,5.0\n\
+begin,,,\n\
+withdrawal,1,2,9.0\n\
+commit,,,\n\
+dispute,1,3,\n\
+deposit,1,4,-1.0\n\
+refund,1,5,1.0\n\
+deposit,x,6,1.0\n\
+,,,\n";
+
+        let stats = validate_records(&mut reader(data), &Options::default());
+        assert_eq!(stats.processed, 3);
+        assert_eq!(stats.errors, 3);
+        assert_eq!(stats.ignored, 1);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let data: String = std::iter::once("type,client,tx,amount\n".to_string())
+            .chain((1..=200).map(|tx| format!("deposit,{},{},1.0\n", tx, tx)))
+            .collect();
+        let sampled = |seed| {
+            let options = Options {
+                sample: Some(Sample { rate: 0.1, seed }),
+                ..Default::default()
+            };
+            let mut engine = Engine::new(Config::default());
+            let stats = process_records(
+                &mut reader(&data),
+                &mut engine,
+                &options,
+                &Interrupt::new(),
+                None,
+                None,
+            );
+            assert_eq!(stats.processed + stats.ignored, 200);
+            engine.accounts().keys().copied().collect::<Vec<u16>>()
+        };
+        let clients = sampled(42);
+        assert!((5..=40).contains(&clients.len()), "{:?}", clients);
+        assert_eq!(sampled(42), clients);
+        assert_ne!(sampled(7), clients);
+    }
+
+    #[test]
+    fn test_max_line_bytes() {
+        let long = "1".repeat(64);
+        let data = format!(
+            "type,client,tx,amount\n\
+deposit,1,1,5.0\n\
+deposit,1,2,{}\n\
+withdrawal,1,3,1.0\n",
+            long
+        );
+        // The header, the longest line to keep, is exactly 21 bytes.
+        let limited = |data: &str| {
+            let input = LineLimitReader::new(io::Cursor::new(data.to_string()), Some(21));
+            csv::ReaderBuilder::new().flexible(true).from_reader(input)
         };
-        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+        let options = Options {
+            error_report: true,
+            max_line_bytes: Some(21),
+            ..Default::default()
+        };
+        let mut engine = Engine::new(Config::default());
+        let stats = process_records(
+            &mut limited(&data),
+            &mut engine,
+            &options,
+            &Interrupt::new(),
+            None,
+            None,
+        );
+        assert_eq!(stats.errors_by_kind.get("line_too_long"), Some(&1));
+        assert_eq!(stats.error_report[0].line, Some(3));
+        assert_eq!(
+            stats.error_report[0].message,
+            "Rejecting line longer than the 21 bytes limit"
+        );
+        assert_eq!(stats.processed, 2);
+        assert_eq!(engine.accounts()[&1].total, Money::from(4.0));
+
+        // A trailing oversized line is reported once the input runs out.
+        let data = format!("{}deposit,1,4,{}\n", data, long);
+        let stats = validate_records(&mut limited(&data), &options);
+        assert_eq!(stats.errors_by_kind.get("line_too_long"), Some(&2));
+        assert_eq!(stats.error_report[1].line, Some(5));
+        assert_eq!(stats.processed, 2);
     }
 }
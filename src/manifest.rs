@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+
+use serde::Serialize;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::stats::Stats;
+use crate::types::Precision;
+
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub inputs: Vec<String>,
+    pub rows: u64,
+    pub processed: u64,
+    pub duplicates: u64,
+    pub errors: u64,
+    pub accounts: usize,
+    pub config: ManifestConfig,
+    pub output_checksum: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestConfig {
+    pub scale: u32,
+    pub rounding: &'static str,
+    pub dedup_consecutive: bool,
+}
+
+impl ManifestConfig {
+    pub fn new(precision: Precision, dedup_consecutive: bool) -> Self {
+        Self {
+            scale: precision.decimals(),
+            rounding: "truncate",
+            dedup_consecutive,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new(
+        inputs: Vec<String>,
+        stats: &Stats,
+        accounts: usize,
+        config: ManifestConfig,
+        output_checksum: u64,
+    ) -> Self {
+        Self {
+            inputs,
+            rows: stats.rows,
+            processed: stats.processed,
+            duplicates: stats.duplicates,
+            errors: stats.errors,
+            accounts,
+            config,
+            output_checksum: format!("xxh3:{:016x}", output_checksum),
+        }
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// Hashes a set of transaction ids independently of the order they were
+/// seen in, so two inputs covering the same transactions hash the same.
+pub fn tx_set_hash(ids: &BTreeSet<u32>) -> u64 {
+    let mut hasher = Xxh3::new();
+    for id in ids {
+        hasher.update(&id.to_le_bytes());
+    }
+    hasher.digest()
+}
+
+/// Forwards writes to `inner` while hashing everything written, so the
+/// manifest can record a checksum of the output without buffering it.
+pub struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Xxh3,
+}
+
+impl<W: io::Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Xxh3::new(),
+        }
+    }
+
+    pub fn checksum(&self) -> u64 {
+        self.hasher.digest()
+    }
+}
+
+impl<W: io::Write> io::Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use xxhash_rust::xxh3::xxh3_64;
+
+    #[test]
+    fn test_checksum_writer() {
+        let mut writer = ChecksumWriter::new(Vec::new());
+        writer.write_all(b"client,available").unwrap();
+        writer.write_all(b",held,total,locked\n").unwrap();
+        assert_eq!(
+            writer.checksum(),
+            xxh3_64(b"client,available,held,total,locked\n")
+        );
+        assert_eq!(writer.inner, b"client,available,held,total,locked\n");
+    }
+}
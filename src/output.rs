@@ -0,0 +1,923 @@
+use std::io;
+use std::str::FromStr;
+
+use csv::Writer;
+use flate2::write::GzEncoder;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use crate::types::*;
+
+#[derive(Clone, Debug)]
+pub struct OutputOptions {
+    /// Decimals shown for amounts. Extra decimals are truncated at display
+    /// time only.
+    pub precision: Precision,
+    /// Output columns, in order.
+    pub columns: Vec<OutputColumn>,
+    /// Writes amounts as magnitudes, each followed by a `<column>_sign`
+    /// column holding `+` or `-`.
+    pub sign_columns: bool,
+    pub compression: OutputCompression,
+    pub format: OutputFormat,
+    /// Renders amount columns, replacing the default decimal formatting.
+    /// Amounts are truncated to `precision` before being passed in.
+    pub amount_formatter: Option<AmountFormatter>,
+    /// Line written after the last account, with `{count}` replaced by the
+    /// number of accounts, so readers can tell complete output from
+    /// truncated output.
+    pub footer: Option<String>,
+}
+
+pub type AmountFormatter = fn(&f64) -> String;
+
+pub const DEFAULT_FOOTER: &str = "# end {count} accounts";
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            precision: Precision::default(),
+            columns: OutputColumn::DEFAULT.to_vec(),
+            sign_columns: false,
+            compression: OutputCompression::None,
+            format: OutputFormat::Csv,
+            amount_formatter: None,
+            footer: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl FromStr for OutputCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(OutputCompression::None),
+            "gzip" => Ok(OutputCompression::Gzip),
+            _ => Err(format!("Invalid output compression: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// An array of objects keyed by column name. Amounts are numbers
+    /// truncated to the output precision.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Invalid output format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// Space separated `Account::tx_ids`.
+    TxIds,
+    DepositCount,
+    WithdrawalCount,
+    /// `Account::deposited`, including deposits later charged back.
+    TotalDeposited,
+    /// Whether any transaction of the client is currently disputed.
+    UnderDispute,
+}
+
+impl OutputColumn {
+    /// Columns written unless others are selected.
+    pub const DEFAULT: [OutputColumn; 5] = [
+        OutputColumn::Client,
+        OutputColumn::Available,
+        OutputColumn::Held,
+        OutputColumn::Total,
+        OutputColumn::Locked,
+    ];
+
+    pub const ALL: [OutputColumn; 10] = [
+        OutputColumn::Client,
+        OutputColumn::Available,
+        OutputColumn::Held,
+        OutputColumn::Total,
+        OutputColumn::Locked,
+        OutputColumn::TxIds,
+        OutputColumn::DepositCount,
+        OutputColumn::WithdrawalCount,
+        OutputColumn::TotalDeposited,
+        OutputColumn::UnderDispute,
+    ];
+
+    /// Activity columns added by `--stats`.
+    pub const STATS: [OutputColumn; 3] = [
+        OutputColumn::DepositCount,
+        OutputColumn::WithdrawalCount,
+        OutputColumn::TotalDeposited,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputColumn::Client => "client",
+            OutputColumn::Available => "available",
+            OutputColumn::Held => "held",
+            OutputColumn::Total => "total",
+            OutputColumn::Locked => "locked",
+            OutputColumn::TxIds => "tx_ids",
+            OutputColumn::DepositCount => "deposit_count",
+            OutputColumn::WithdrawalCount => "withdrawal_count",
+            OutputColumn::TotalDeposited => "total_deposited",
+            OutputColumn::UnderDispute => "under_dispute",
+        }
+    }
+
+    pub fn is_amount(&self) -> bool {
+        matches!(
+            self,
+            OutputColumn::Available
+                | OutputColumn::Held
+                | OutputColumn::Total
+                | OutputColumn::TotalDeposited
+        )
+    }
+
+    /// Parses a comma separated column list such as
+    /// `client,total,available,held,locked`. Every column must be known and
+    /// appear at most once.
+    pub fn parse_list(spec: &str) -> Result<Vec<OutputColumn>, String> {
+        let mut columns = Vec::new();
+        for name in spec.split(',') {
+            let column: OutputColumn = name.parse()?;
+            if columns.contains(&column) {
+                return Err(format!("Duplicate output column: {}", column.name()));
+            }
+            columns.push(column);
+        }
+        Ok(columns)
+    }
+}
+
+impl FromStr for OutputColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        OutputColumn::ALL
+            .into_iter()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| format!("Invalid output column: {}", s))
+    }
+}
+
+pub fn write_accounts(accounts: &Accounts, wtr: impl io::Write, options: &OutputOptions) {
+    match options.compression {
+        OutputCompression::None => write_format(accounts, wtr, options),
+        OutputCompression::Gzip => {
+            let mut encoder = GzEncoder::new(wtr, flate2::Compression::default());
+            write_format(accounts, &mut encoder, options);
+            if let Err(err) = encoder.finish() {
+                eprintln!("Failed to finish compressed output: {}", err);
+            }
+        }
+    }
+}
+
+fn write_format(accounts: &Accounts, wtr: impl io::Write, options: &OutputOptions) {
+    match options.format {
+        OutputFormat::Csv => write_csv(accounts, wtr, options),
+        OutputFormat::Json => {
+            if let Err(err) = write_json(accounts, wtr, options) {
+                eprintln!("Failed to write accounts: {}", err);
+            }
+        }
+    }
+}
+
+/// Writes a JSON array with one object per line, e.g.
+/// `{"client":1,"available":1.5,"held":0.0,"total":1.5,"locked":false}`.
+/// `OutputOptions::footer` doesn't apply.
+fn write_json(
+    accounts: &Accounts,
+    mut wtr: impl io::Write,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let header = header(options);
+    wtr.write_all(b"[")?;
+    for (i, (client, account)) in accounts.iter().enumerate() {
+        let row = OutputAccount::new(client, account, options).row(options);
+        wtr.write_all(if i == 0 { b"\n" } else { b",\n" })?;
+        serde_json::to_writer(&mut wtr, &JsonRow(&header, row))?;
+    }
+    wtr.write_all(b"\n]\n")?;
+    wtr.flush()
+}
+
+/// Renders one account as an object of `write_json`'s output.
+pub fn account_json(client: u16, account: &Account, options: &OutputOptions) -> String {
+    let row = OutputAccount::new(&client, account, options).row(options);
+    serde_json::to_string(&JsonRow(&header(options), row)).unwrap_or_default()
+}
+
+fn write_csv(accounts: &Accounts, mut wtr: impl io::Write, options: &OutputOptions) {
+    let mut writer = Writer::from_writer(&mut wtr);
+    if !accounts.is_empty() {
+        if let Err(err) = write_header(&mut writer, options) {
+            eprintln!("Failed to write header: {}", err);
+        }
+    }
+    // `Accounts` iterates in client order, so the output always is sorted.
+    for (client, account) in accounts {
+        let acc = OutputAccount::new(client, account, options);
+        if let Err(err) = writer.serialize(acc.row(options)) {
+            eprintln!("Failed to write account: {}", err);
+        }
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    if let Err(err) = write_footer(&mut wtr, options, accounts.len()) {
+        eprintln!("Failed to write footer: {}", err);
+    }
+}
+
+/// Writes `OutputOptions::footer`, if set, as a raw line after the rows.
+fn write_footer(wtr: &mut impl io::Write, options: &OutputOptions, count: usize) -> io::Result<()> {
+    match &options.footer {
+        Some(footer) => writeln!(wtr, "{}", footer.replace("{count}", &count.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Writes account states incrementally as processing goes, for
+/// `--flush-every`. The header is written once, before the first rows.
+/// Compression and sorting aren't supported.
+pub struct AccountStream<W: io::Write> {
+    writer: Writer<W>,
+    options: OutputOptions,
+    header_written: bool,
+}
+
+impl<W: io::Write> AccountStream<W> {
+    pub fn new(wtr: W, options: OutputOptions) -> Self {
+        Self {
+            writer: Writer::from_writer(wtr),
+            options,
+            header_written: false,
+        }
+    }
+
+    /// Writes the current state of the given accounts and flushes them.
+    pub fn write<'a>(
+        &mut self,
+        accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    ) -> csv::Result<()> {
+        for (client, account) in accounts {
+            if !self.header_written {
+                write_header(&mut self.writer, &self.options)?;
+                self.header_written = true;
+            }
+            let acc = OutputAccount::new(client, account, &self.options);
+            self.writer.serialize(acc.row(&self.options))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the footer after the final accounts, `count` being the
+    /// number of accounts in the complete set.
+    pub fn finish(self, count: usize) -> io::Result<()> {
+        let mut wtr = self.writer.into_inner().map_err(|err| err.into_error())?;
+        write_footer(&mut wtr, &self.options, count)?;
+        wtr.flush()
+    }
+}
+
+fn write_header<W: io::Write>(writer: &mut Writer<W>, options: &OutputOptions) -> csv::Result<()> {
+    writer.write_record(header(options))
+}
+
+/// Names of the fields `OutputAccount::row` produces.
+fn header(options: &OutputOptions) -> Vec<String> {
+    let mut header = Vec::new();
+    for column in &options.columns {
+        header.push(column.name().to_string());
+        if options.sign_columns && column.is_amount() {
+            header.push(format!("{}_sign", column.name()));
+        }
+    }
+    header
+}
+
+#[derive(Debug)]
+pub struct OutputAccount {
+    client: u16,
+    available: Money,
+    held: Money,
+    total: Money,
+    locked: bool,
+    tx_ids: String,
+    deposit_count: u64,
+    withdrawal_count: u64,
+    total_deposited: Money,
+    under_dispute: bool,
+}
+
+impl OutputAccount {
+    pub const COLUMNS: &'static [Column] = &[
+        ("client", "u16", "client id"),
+        ("available", "decimal", "funds available for withdrawal"),
+        ("held", "decimal", "funds held by open disputes"),
+        ("total", "decimal", "available + held"),
+        ("locked", "bool", "whether a chargeback locked the account"),
+    ];
+
+    pub fn new(client: &u16, account: &Account, options: &OutputOptions) -> Self {
+        let precision = options.precision;
+        Self {
+            client: *client,
+            available: precision.apply(account.available),
+            held: precision.apply(account.held),
+            total: precision.apply(account.total),
+            locked: account.locked,
+            tx_ids: account
+                .tx_ids
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+            deposit_count: account.deposit_count,
+            withdrawal_count: account.withdrawal_count,
+            total_deposited: precision.apply(account.deposited),
+            under_dispute: account.open_disputes > 0,
+        }
+    }
+
+    /// The account's values for the output columns, in order.
+    pub fn row(&self, options: &OutputOptions) -> Vec<Cell> {
+        let mut row = Vec::with_capacity(options.columns.len());
+        for column in &options.columns {
+            let amount = match column {
+                OutputColumn::Client => {
+                    row.push(Cell::Client(self.client));
+                    continue;
+                }
+                OutputColumn::Locked => {
+                    row.push(Cell::Bool(self.locked));
+                    continue;
+                }
+                OutputColumn::TxIds => {
+                    row.push(Cell::Text(self.tx_ids.clone()));
+                    continue;
+                }
+                OutputColumn::DepositCount => {
+                    row.push(Cell::Count(self.deposit_count));
+                    continue;
+                }
+                OutputColumn::WithdrawalCount => {
+                    row.push(Cell::Count(self.withdrawal_count));
+                    continue;
+                }
+                OutputColumn::UnderDispute => {
+                    row.push(Cell::Bool(self.under_dispute));
+                    continue;
+                }
+                OutputColumn::Available => self.available,
+                OutputColumn::Held => self.held,
+                OutputColumn::Total => self.total,
+                OutputColumn::TotalDeposited => self.total_deposited,
+            };
+            let cell = |amount: Money| match options.amount_formatter {
+                Some(format) => Cell::Text(format(&amount.to_f64())),
+                None => Cell::Amount(amount),
+            };
+            if options.sign_columns {
+                row.push(cell(amount.abs()));
+                row.push(Cell::Sign(if amount.is_negative() { '-' } else { '+' }));
+            } else {
+                row.push(cell(amount));
+            }
+        }
+        row
+    }
+}
+
+/// A single output field.
+#[derive(Debug)]
+pub enum Cell {
+    Client(u16),
+    Amount(Money),
+    Bool(bool),
+    Sign(char),
+    Count(u64),
+    /// An amount rendered by a custom `AmountFormatter`, or the
+    /// transaction ids column.
+    Text(String),
+}
+
+impl Serialize for Cell {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Cell::Client(client) => s.serialize_u16(*client),
+            Cell::Amount(amount) => amount.serialize(s),
+            Cell::Bool(flag) => s.serialize_bool(*flag),
+            Cell::Sign(sign) => s.serialize_char(*sign),
+            Cell::Count(count) => s.serialize_u64(*count),
+            Cell::Text(text) => s.serialize_str(text),
+        }
+    }
+}
+
+/// A row as a JSON object, keeping the column order.
+struct JsonRow<'a>(&'a [String], Vec<Cell>);
+
+impl Serialize for JsonRow<'_> {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = s.serialize_map(Some(self.0.len()))?;
+        for (name, cell) in self.0.iter().zip(&self.1) {
+            map.serialize_entry(name, &cell.to_json())?;
+        }
+        map.end()
+    }
+}
+
+impl Cell {
+    /// Amounts become JSON numbers. They are already truncated to the output
+    /// precision, so the shortest float representation shows no more
+    /// decimals than the CSV output.
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Cell::Client(client) => (*client).into(),
+            Cell::Amount(amount) => amount.to_f64().into(),
+            Cell::Bool(flag) => (*flag).into(),
+            Cell::Sign(sign) => sign.to_string().into(),
+            Cell::Count(count) => (*count).into(),
+            Cell::Text(text) => text.as_str().into(),
+        }
+    }
+}
+
+/// Describes the input and output CSV columns for integrators.
+pub fn schema() -> String {
+    let mut out = String::new();
+    for (title, columns) in [
+        ("Input columns", Transaction::COLUMNS),
+        ("Output columns", OutputAccount::COLUMNS),
+    ] {
+        out.push_str(title);
+        out.push_str(":\n");
+        for (name, typ, description) in columns {
+            out.push_str(&format!("  {:<12} {:<8} {}\n", name, typ, description));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_accounts() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            2,
+            Account {
+                available: Money::from(2.0),
+                held: Money::ZERO,
+                total: Money::from(2.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.0),
+                held: Money::ZERO,
+                total: Money::from(1.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &OutputOptions::default());
+
+        let expected = "\
+client,available,held,total,locked\n\
+1,1.0,0.0,1.0,false\n\
+2,2.0,0.0,2.0,false\n\
+";
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_write_accounts_footer() {
+        let mut accounts = Accounts::new();
+        for client in [1, 2, 3] {
+            accounts.insert(client, Account::default());
+        }
+        let options = OutputOptions {
+            footer: Some(DEFAULT_FOOTER.to_string()),
+            ..Default::default()
+        };
+
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        let output = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[4], "# end 3 accounts");
+
+        let mut buf = Vec::new();
+        write_accounts(&Accounts::new(), &mut buf, &options);
+        assert_eq!(String::from_utf8(buf).unwrap(), "# end 0 accounts\n");
+    }
+
+    #[test]
+    fn test_write_accounts_under_dispute() {
+        use crate::config::Config;
+        use crate::process_transaction::process_transaction;
+
+        let mut accounts = Accounts::new();
+        let mut transactions = Transactions::new();
+        let config = Config::default();
+        for tx in [
+            TX::Deposit(Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(2.0),
+            }),
+            TX::Deposit(Deposit {
+                client: 2,
+                tx: 2,
+                amount: Money::from(3.0),
+            }),
+            TX::Dispute(Dispute {
+                client: 2,
+                tx: 2,
+                amount: None,
+            }),
+        ] {
+            process_transaction(tx, &mut accounts, &mut transactions, &config).unwrap();
+        }
+        let options = OutputOptions {
+            columns: vec![
+                OutputColumn::Client,
+                OutputColumn::Held,
+                OutputColumn::UnderDispute,
+            ],
+            ..Default::default()
+        };
+
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,held,under_dispute\n1,0.0,false\n2,3.0,true\n"
+        );
+
+        process_transaction(
+            TX::Resolve(Resolve { client: 2, tx: 2 }),
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        assert!(String::from_utf8(buf).unwrap().ends_with("2,0.0,false\n"));
+    }
+
+    #[test]
+    fn test_write_accounts_json() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            2,
+            Account {
+                available: Money::from_raw(12_345),
+                held: Money::from_raw(-5),
+                total: Money::from_raw(12_340),
+                locked: true,
+                ..Default::default()
+            },
+        );
+        accounts.insert(1, Account::default());
+        let options = OutputOptions {
+            precision: Precision::new(2),
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        let expected = "[
+{\"client\":1,\"available\":0.0,\"held\":0.0,\"total\":0.0,\"locked\":false},
+{\"client\":2,\"available\":1.23,\"held\":0.0,\"total\":1.23,\"locked\":true}
+]
+";
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+
+        let mut csv = Vec::new();
+        let csv_options = OutputOptions {
+            format: OutputFormat::Csv,
+            ..options.clone()
+        };
+        write_accounts(&accounts, &mut csv, &csv_options);
+        assert_eq!(
+            String::from_utf8(csv).unwrap().lines().last(),
+            Some("2,1.23,0.0,1.23,true")
+        );
+
+        let mut buf = Vec::new();
+        write_accounts(&Accounts::new(), &mut buf, &options);
+        assert_eq!(String::from_utf8(buf).unwrap(), "[\n]\n");
+    }
+
+    #[test]
+    fn test_account_stream() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.0),
+                total: Money::from(1.0),
+                ..Default::default()
+            },
+        );
+        let mut buf = Vec::new();
+        let mut stream = AccountStream::new(&mut buf, OutputOptions::default());
+        stream.write(&accounts).unwrap();
+        stream.write(&Accounts::new()).unwrap();
+        accounts.get_mut(&1).unwrap().held = Money::from(1.0);
+        accounts.get_mut(&1).unwrap().available = Money::ZERO;
+        stream.write(&accounts).unwrap();
+        drop(stream);
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n1,0.0,1.0,1.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = schema();
+        let (input, output) = schema.split_once("Output columns:\n").unwrap();
+        let names = |section: &str| -> Vec<String> {
+            section
+                .lines()
+                .filter(|line| line.starts_with("  "))
+                .map(|line| line.split_whitespace().next().unwrap().to_string())
+                .collect()
+        };
+        assert!(input.starts_with("Input columns:\n"));
+        assert_eq!(
+            names(input),
+            vec![
+                "type",
+                "client",
+                "tx",
+                "amount",
+                "amount_major",
+                "amount_minor",
+                "destination",
+                "currency",
+                "direction",
+                "institution"
+            ]
+        );
+        assert_eq!(
+            names(output),
+            vec!["client", "available", "held", "total", "locked"]
+        );
+
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: false,
+                ..Default::default()
+            },
+        );
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &OutputOptions::default());
+        let header = String::from_utf8(buf).unwrap();
+        assert_eq!(
+            header
+                .lines()
+                .next()
+                .unwrap()
+                .split(',')
+                .collect::<Vec<_>>(),
+            names(output)
+        );
+    }
+
+    #[test]
+    fn test_write_accounts_output_precision() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.2375),
+                held: Money::from(0.0051),
+                total: Money::from(1.244),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        let mut buf = Vec::new();
+        write_accounts(
+            &accounts,
+            &mut buf,
+            &OutputOptions {
+                precision: Precision::new(2),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n1,1.23,0.0,1.24,false\n"
+        );
+
+        let mut buf = Vec::new();
+        write_accounts(
+            &accounts,
+            &mut buf,
+            &OutputOptions {
+                precision: Precision::new(9),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n1,1.2375,0.0051,1.244,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_accounts_columns() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.5),
+                held: Money::from(0.5),
+                total: Money::from(2.0),
+                locked: true,
+                ..Default::default()
+            },
+        );
+        accounts.insert(
+            2,
+            Account {
+                available: Money::from(3.0),
+                held: Money::ZERO,
+                total: Money::from(3.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        let options = OutputOptions {
+            columns: OutputColumn::parse_list("client, Total,available,held,locked").unwrap(),
+            ..Default::default()
+        };
+        let expected = "\
+client,total,available,held,locked\n\
+1,2.0,1.5,0.5,true\n\
+2,3.0,3.0,0.0,false\n\
+";
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+
+        assert!(OutputColumn::parse_list("client,balance").is_err());
+        assert!(OutputColumn::parse_list("client,held,client").is_err());
+        assert!(OutputColumn::parse_list("").is_err());
+    }
+
+    #[test]
+    fn test_write_accounts_sign_columns() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(-7.5),
+                held: Money::from(10.0),
+                total: Money::from(2.5),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        let options = OutputOptions {
+            sign_columns: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,available_sign,held,held_sign,total,total_sign,locked\n\
+             1,7.5,-,10.0,+,2.5,+,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_accounts_amount_formatter() {
+        fn accounting(amount: &f64) -> String {
+            if *amount < 0.0 {
+                format!("({:.2})", amount.abs())
+            } else {
+                format!("{:.2}", amount)
+            }
+        }
+
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(-7.5),
+                held: Money::from(10.0),
+                total: Money::from(2.5),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        let options = OutputOptions {
+            amount_formatter: Some(accounting),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,available,held,total,locked\n1,(7.50),10.00,2.50,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_accounts_gzip() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.5),
+                held: Money::ZERO,
+                total: Money::from(1.5),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        let options = OutputOptions {
+            compression: OutputCompression::Gzip,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_accounts(&accounts, &mut buf, &options);
+        assert_eq!(&buf[..2], &[0x1f, 0x8b]);
+
+        let mut csv = String::new();
+        GzDecoder::new(buf.as_slice())
+            .read_to_string(&mut csv)
+            .unwrap();
+        assert_eq!(
+            csv,
+            "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n"
+        );
+    }
+}
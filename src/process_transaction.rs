@@ -1,88 +1,185 @@
+use crate::config::*;
+use crate::store::AccountStore;
 use crate::transactions::*;
 use crate::types::*;
 
-pub fn process_transaction(
+pub fn process_transaction<A: AccountStore>(
     transaction: TX,
-    accounts: &mut Accounts,
+    accounts: &mut A,
     transactions: &mut Transactions,
+    config: &Config,
 ) -> Result<(), TXError> {
-    match transaction {
-        TX::Deposit(operation) => deposit(operation, accounts, transactions),
-        TX::Withdrawal(operation) => withdraw(operation, accounts, transactions),
-        TX::Dispute(operation) => dispute(operation, accounts, transactions),
-        TX::Resolve(operation) => resolve(operation, accounts, transactions),
-        TX::Chargeback(operation) => chargeback(operation, accounts, transactions),
+    if config.disabled.contains(&transaction.typ()) {
+        return Err(TXError::TypeDisabled(transaction));
     }
+    let dispute_flow = transaction.is_dispute_flow();
+    let client = transaction.client();
+    let known_accounts = accounts.account_count();
+    let result = match transaction {
+        TX::Deposit(operation) => deposit(operation, accounts, transactions, config),
+        TX::Withdrawal(operation) => withdraw(operation, accounts, transactions, config),
+        TX::Dispute(operation) => dispute(operation, accounts, transactions, config),
+        TX::Resolve(operation) => resolve(operation, accounts, transactions, config),
+        TX::Chargeback(operation) => chargeback(operation, accounts, transactions, config),
+    };
+    if result.is_ok() && config.remove_zero_accounts {
+        let is_zero = accounts.account(client).is_some_and(|account| {
+            !account.locked
+                && account.available == Money::ZERO
+                && account.held == Money::ZERO
+                && account.total == Money::ZERO
+        });
+        if is_zero {
+            // Its transactions are kept so their ids stay taken. They can't
+            // be disputed until a deposit creates the account again.
+            accounts.remove_account(client);
+        }
+    }
+    // Disputes, resolves and chargebacks only look accounts up, they never
+    // create one.
+    debug_assert!(!dispute_flow || accounts.account_count() <= known_accounts);
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_remove_zero_accounts() {
+        let mut accounts = Accounts::new();
+        let mut transactions = Transactions::new();
+        let config = Config {
+            remove_zero_accounts: true,
+            ..Default::default()
+        };
+
+        let deposit = TX::Deposit(Deposit {
+            client: 1,
+            tx: 1,
+            amount: Money::from(2.5),
+        });
+        process_transaction(deposit, &mut accounts, &mut transactions, &config).unwrap();
+        let withdrawal = TX::Withdrawal(Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: Money::from(2.5),
+            destination: None,
+        });
+        process_transaction(withdrawal, &mut accounts, &mut transactions, &config).unwrap();
+        assert!(!accounts.contains_key(&1));
+        assert_eq!(transactions.len(), 2);
+
+        let dispute = TX::Dispute(Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        });
+        assert_eq!(
+            process_transaction(dispute.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::AccountNotFound(dispute))
+        );
+        assert!(!accounts.contains_key(&1));
+
+        let duplicate = TX::Deposit(Deposit {
+            client: 1,
+            tx: 1,
+            amount: Money::from(1.0),
+        });
+        assert_eq!(
+            process_transaction(duplicate.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::DuplicateTransaction(duplicate))
+        );
+        assert!(!accounts.contains_key(&1));
+
+        let deposit = TX::Deposit(Deposit {
+            client: 1,
+            tx: 3,
+            amount: Money::from(1.0),
+        });
+        process_transaction(deposit, &mut accounts, &mut transactions, &config).unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.available, 1.0);
+        assert_balance_eq!(account.held, 0.0);
+        assert_balance_eq!(account.total, 1.0);
+        assert_eq!((account.deposit_count, account.withdrawal_count), (1, 0));
+        assert!(!account.locked);
+    }
+
     #[test]
     fn test_process_transaction() {
         let mut accounts = Accounts::new();
         let mut transactions = Transactions::new();
+        let config = Config::default();
 
         let transaction = TX::Deposit(Deposit {
             client: 1,
             tx: 1,
-            amount: 1.0,
+            amount: Money::from(1.0),
         });
-        process_transaction(transaction, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 1.0);
-        assert_eq!(accounts.get(&1).unwrap().held, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 1.0);
-        assert_eq!(accounts.get(&1).unwrap().locked, false);
+        process_transaction(transaction, &mut accounts, &mut transactions, &config).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 1.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1.0);
+        assert!(!accounts.get(&1).unwrap().locked);
 
         let transaction = TX::Deposit(Deposit {
             client: 1,
             tx: 2,
-            amount: 1.0,
+            amount: Money::from(1.0),
         });
-        process_transaction(transaction, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 2.0);
-        assert_eq!(accounts.get(&1).unwrap().held, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 2.0);
-        assert_eq!(accounts.get(&1).unwrap().locked, false);
+        process_transaction(transaction, &mut accounts, &mut transactions, &config).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 2.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 2.0);
+        assert!(!accounts.get(&1).unwrap().locked);
 
         let transaction = TX::Withdrawal(Withdrawal {
             client: 1,
             tx: 3,
-            amount: 0.5,
+            amount: Money::from(0.5),
+            destination: None,
         });
-        process_transaction(transaction, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 1.5);
-        assert_eq!(accounts.get(&1).unwrap().held, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 1.5);
-        assert_eq!(accounts.get(&1).unwrap().locked, false);
+        process_transaction(transaction, &mut accounts, &mut transactions, &config).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 1.5);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1.5);
+        assert!(!accounts.get(&1).unwrap().locked);
 
-        let transaction = TX::Dispute(Dispute { client: 1, tx: 1 });
-        process_transaction(transaction, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 0.5);
-        assert_eq!(accounts.get(&1).unwrap().held, 1.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 1.5);
-        assert_eq!(accounts.get(&1).unwrap().locked, false);
+        let transaction = TX::Dispute(Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        });
+        process_transaction(transaction, &mut accounts, &mut transactions, &config).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.5);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 1.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1.5);
+        assert!(!accounts.get(&1).unwrap().locked);
 
         let transaction = TX::Resolve(Resolve { client: 1, tx: 1 });
-        process_transaction(transaction, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 1.5);
-        assert_eq!(accounts.get(&1).unwrap().held, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 1.5);
-        assert_eq!(accounts.get(&1).unwrap().locked, false);
+        process_transaction(transaction, &mut accounts, &mut transactions, &config).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 1.5);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1.5);
+        assert!(!accounts.get(&1).unwrap().locked);
 
-        let transaction = TX::Dispute(Dispute { client: 1, tx: 2 });
-        process_transaction(transaction, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 0.5);
-        assert_eq!(accounts.get(&1).unwrap().held, 1.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 1.5);
-        assert_eq!(accounts.get(&1).unwrap().locked, false);
+        let transaction = TX::Dispute(Dispute {
+            client: 1,
+            tx: 2,
+            amount: None,
+        });
+        process_transaction(transaction, &mut accounts, &mut transactions, &config).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.5);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 1.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1.5);
+        assert!(!accounts.get(&1).unwrap().locked);
 
         let transaction = TX::Chargeback(Chargeback { client: 1, tx: 2 });
-        process_transaction(transaction, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 0.5);
-        assert_eq!(accounts.get(&1).unwrap().held, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 0.5);
-        assert_eq!(accounts.get(&1).unwrap().locked, true);
+        process_transaction(transaction, &mut accounts, &mut transactions, &config).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.5);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.5);
+        assert!(accounts.get(&1).unwrap().locked);
     }
 }
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use serde::Serialize;
+
+use crate::types::*;
+
+/// A client whose available funds ended below zero, e.g. after disputing
+/// an already withdrawn deposit.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct NegativeBalance {
+    pub client: u16,
+    /// Magnitude of the negative available balance.
+    pub shortfall: Money,
+}
+
+/// Clients with a negative available balance, ordered by client id.
+pub fn negative_balances(accounts: &Accounts) -> Vec<NegativeBalance> {
+    accounts
+        .iter()
+        .filter(|(_, account)| account.available.is_negative())
+        .map(|(client, account)| NegativeBalance {
+            client: *client,
+            shortfall: -account.available,
+        })
+        .collect()
+}
+
+/// A client's money flows over the run, as opposed to its balances.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct NetMovement {
+    pub client: u16,
+    pub deposited: Money,
+    pub withdrawn: Money,
+    /// `deposited - withdrawn`.
+    pub net: Money,
+    pub held: Money,
+}
+
+/// Every client's deposits, withdrawals and currently held funds, ordered
+/// by client id.
+pub fn net_movements(accounts: &Accounts) -> Vec<NetMovement> {
+    accounts
+        .iter()
+        .map(|(client, account)| NetMovement {
+            client: *client,
+            deposited: account.deposited,
+            withdrawn: account.withdrawn,
+            net: account.deposited - account.withdrawn,
+            held: account.held,
+        })
+        .collect()
+}
+
+/// Balances summed over the clients of an institution.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InstitutionBalance {
+    pub institution: String,
+    pub clients: u64,
+    pub available: Money,
+    pub held: Money,
+    pub total: Money,
+}
+
+/// Balances per institution, ordered by name. `institutions` maps clients
+/// to their institution, clients without one are left out.
+pub fn institution_balances(
+    accounts: &Accounts,
+    institutions: &BTreeMap<u16, String>,
+) -> Vec<InstitutionBalance> {
+    let mut grouped: BTreeMap<&str, InstitutionBalance> = BTreeMap::new();
+    for (client, account) in accounts {
+        let Some(institution) = institutions.get(client) else {
+            continue;
+        };
+        let balance = grouped
+            .entry(institution)
+            .or_insert_with(|| InstitutionBalance {
+                institution: institution.clone(),
+                clients: 0,
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+            });
+        balance.clients += 1;
+        balance.available += account.available;
+        balance.held += account.held;
+        balance.total += account.total;
+    }
+    grouped.into_values().collect()
+}
+
+/// A transaction charged back, kept after its stored state is removed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChargebackRecord {
+    pub client: u16,
+    pub tx: u32,
+    pub kind: TXKind,
+    pub amount: Money,
+    /// Whether the chargeback left the account locked.
+    pub locked: bool,
+}
+
+/// A row that failed or was skipped, collected for `--error-report`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProcessingError {
+    /// Input line of the row, if known.
+    pub line: Option<u64>,
+    /// The raw CSV row, empty if it couldn't be read.
+    pub record: String,
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// Writes the collected errors as a JSON array.
+pub fn write_error_report(
+    errors: &[ProcessingError],
+    wtr: impl io::Write,
+) -> serde_json::Result<()> {
+    serde_json::to_writer_pretty(wtr, errors)
+}
+
+/// Writes report rows as CSV with a header.
+pub fn write_report<T: Serialize>(rows: &[T], wtr: impl io::Write) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(wtr);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, DisputeOverdrawPolicy};
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_institution_balances() {
+        let mut engine = Engine::new(Config::default());
+        for (client, tx, amount) in [(1, 1, 10.0), (2, 2, 2.5), (3, 3, 4.0), (4, 4, 1.0)] {
+            engine
+                .process(TX::Deposit(Deposit {
+                    client,
+                    tx,
+                    amount: Money::from(amount),
+                }))
+                .unwrap();
+        }
+        engine
+            .process(TX::Dispute(Dispute {
+                client: 2,
+                tx: 2,
+                amount: None,
+            }))
+            .unwrap();
+        let institutions = BTreeMap::from([
+            (1, "north".to_string()),
+            (2, "north".to_string()),
+            (3, "south".to_string()),
+        ]);
+
+        assert_eq!(
+            institution_balances(engine.accounts(), &institutions),
+            vec![
+                InstitutionBalance {
+                    institution: "north".to_string(),
+                    clients: 2,
+                    available: Money::from(10.0),
+                    held: Money::from(2.5),
+                    total: Money::from(12.5),
+                },
+                InstitutionBalance {
+                    institution: "south".to_string(),
+                    clients: 1,
+                    available: Money::from(4.0),
+                    held: Money::ZERO,
+                    total: Money::from(4.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_negative_balances() {
+        let mut engine = Engine::new(Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Permissive,
+            ..Default::default()
+        });
+        for tx in [
+            TX::Deposit(Deposit {
+                client: 2,
+                tx: 1,
+                amount: Money::from(10.0),
+            }),
+            TX::Withdrawal(Withdrawal {
+                client: 2,
+                tx: 2,
+                amount: Money::from(7.5),
+                destination: None,
+            }),
+            TX::Deposit(Deposit {
+                client: 1,
+                tx: 3,
+                amount: Money::from(5.0),
+            }),
+            TX::Dispute(Dispute {
+                client: 2,
+                tx: 1,
+                amount: None,
+            }),
+        ] {
+            engine.process(tx).unwrap();
+        }
+
+        let negative = negative_balances(engine.accounts());
+        assert_eq!(
+            negative,
+            vec![NegativeBalance {
+                client: 2,
+                shortfall: Money::from(7.5)
+            }]
+        );
+
+        let mut buf = Vec::new();
+        write_report(&negative, &mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "client,shortfall\n2,7.5\n");
+    }
+
+    #[test]
+    fn test_net_movements() {
+        let mut engine = Engine::default();
+        for tx in [
+            TX::Deposit(Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(10.0),
+            }),
+            TX::Deposit(Deposit {
+                client: 1,
+                tx: 2,
+                amount: Money::from(5.5),
+            }),
+            TX::Withdrawal(Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: Money::from(4.0),
+                destination: None,
+            }),
+            TX::Dispute(Dispute {
+                client: 1,
+                tx: 2,
+                amount: None,
+            }),
+        ] {
+            engine.process(tx).unwrap();
+        }
+
+        let movements = net_movements(engine.accounts());
+        assert_eq!(
+            movements,
+            vec![NetMovement {
+                client: 1,
+                deposited: Money::from(15.5),
+                withdrawn: Money::from(4.0),
+                net: Money::from(11.5),
+                held: Money::from(5.5),
+            }]
+        );
+
+        let mut buf = Vec::new();
+        write_report(&movements, &mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,deposited,withdrawn,net,held\n1,15.5,4.0,11.5,5.5\n"
+        );
+    }
+}
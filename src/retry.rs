@@ -0,0 +1,109 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// How often a read failing with a transient error (`WouldBlock` or
+/// `Interrupted`) is retried before the error is surfaced.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub delay: Duration,
+}
+
+/// Wraps a reader, retrying transient read errors according to a `RetryPolicy`.
+#[derive(Debug)]
+pub struct RetryReader<R> {
+    inner: R,
+    policy: RetryPolicy,
+}
+
+impl<R: io::Read> RetryReader<R> {
+    pub fn new(inner: R, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl<R: io::Read> io::Read for RetryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut retries = 0;
+        loop {
+            match self.inner.read(buf) {
+                Err(err) if is_transient(&err) && retries < self.policy.max_retries => {
+                    retries += 1;
+                    thread::sleep(self.policy.delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Money, Transaction};
+
+    /// Fails with the given errors before serving `data`.
+    struct FlakyReader {
+        errors: Vec<io::ErrorKind>,
+        data: io::Cursor<&'static [u8]>,
+    }
+
+    impl io::Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.errors.pop() {
+                Some(kind) => Err(io::Error::from(kind)),
+                None => self.data.read(buf),
+            }
+        }
+    }
+
+    fn flaky(errors: Vec<io::ErrorKind>) -> FlakyReader {
+        FlakyReader {
+            errors,
+            data: io::Cursor::new(b"type,client,tx,amount\ndeposit,1,1,1.0\n"),
+        }
+    }
+
+    #[test]
+    fn test_retry_transient_errors() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            delay: Duration::ZERO,
+        };
+        let reader = RetryReader::new(
+            flaky(vec![io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock]),
+            policy,
+        );
+        let mut rdr = csv::Reader::from_reader(reader);
+        let records: Vec<Transaction> = rdr.deserialize().collect::<Result<_, _>>().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].client, 1);
+        assert_eq!(records[0].amount, Some(Money::from(1.0)));
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let policy = RetryPolicy {
+            max_retries: 1,
+            delay: Duration::ZERO,
+        };
+        let mut reader = RetryReader::new(
+            flaky(vec![io::ErrorKind::WouldBlock, io::ErrorKind::WouldBlock]),
+            policy,
+        );
+        let err = io::Read::read(&mut reader, &mut [0; 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        let mut reader = RetryReader::new(flaky(vec![io::ErrorKind::Other]), policy);
+        let err = io::Read::read(&mut reader, &mut [0; 8]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+}
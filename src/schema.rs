@@ -0,0 +1,106 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+/// Input column layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SchemaVersion {
+    /// `type,client,tx,amount` plus the optional `destination`.
+    #[default]
+    V1,
+    /// V1 plus a `currency` column, required on deposits and withdrawals.
+    V2,
+    /// V1 with a `direction` column, `credit` or `debit`, in place of the
+    /// deposit and withdrawal types.
+    V3,
+}
+
+impl FromStr for SchemaVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().trim_start_matches('v') {
+            "1" => Ok(SchemaVersion::V1),
+            "2" => Ok(SchemaVersion::V2),
+            "3" => Ok(SchemaVersion::V3),
+            _ => Err(format!("Invalid schema version: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaVersion::V1 => write!(f, "v1"),
+            SchemaVersion::V2 => write!(f, "v2"),
+            SchemaVersion::V3 => write!(f, "v3"),
+        }
+    }
+}
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Consumes a leading `# schema: v2` comment line, if the input starts with
+/// one, and returns the version it names. A UTF-8 byte order mark before the
+/// marker is consumed with it. Inputs without the marker are left untouched.
+pub fn read_marker(rdr: &mut impl BufRead) -> io::Result<Option<SchemaVersion>> {
+    let buf = rdr.fill_buf()?;
+    let bom = if buf.starts_with(BOM) { BOM.len() } else { 0 };
+    if !buf[bom..].starts_with(b"#") {
+        return Ok(None);
+    }
+    rdr.consume(bom);
+    let mut line = String::new();
+    rdr.read_line(&mut line)?;
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid schema marker: {}", line.trim()),
+        )
+    };
+    let (key, version) = line
+        .trim_start_matches('#')
+        .split_once([':', '='])
+        .ok_or_else(invalid)?;
+    if key.trim().to_lowercase() != "schema" {
+        return Err(invalid());
+    }
+    version.parse().map(Some).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_read_marker() {
+        let mut input = "# schema: v2\ntype,client\n".as_bytes();
+        assert_eq!(read_marker(&mut input).unwrap(), Some(SchemaVersion::V2));
+        let mut rest = String::new();
+        input.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "type,client\n");
+
+        let mut input = "# schema: v3\n".as_bytes();
+        assert_eq!(read_marker(&mut input).unwrap(), Some(SchemaVersion::V3));
+
+        let mut input = "#schema=1\n".as_bytes();
+        assert_eq!(read_marker(&mut input).unwrap(), Some(SchemaVersion::V1));
+
+        let mut input = "type,client\n".as_bytes();
+        assert_eq!(read_marker(&mut input).unwrap(), None);
+        assert_eq!(input, b"type,client\n");
+
+        let mut input = "\u{feff}# schema: v2\r\ntype,client\r\n".as_bytes();
+        assert_eq!(read_marker(&mut input).unwrap(), Some(SchemaVersion::V2));
+        assert_eq!(input, b"type,client\r\n");
+
+        // Without a marker the byte order mark is left to the CSV reader.
+        let mut input = "\u{feff}type,client\n".as_bytes();
+        assert_eq!(read_marker(&mut input).unwrap(), None);
+        assert_eq!(input, "\u{feff}type,client\n".as_bytes());
+
+        assert!(read_marker(&mut "# schema: v9\n".as_bytes()).is_err());
+        assert!(read_marker(&mut "# generated nightly\n".as_bytes()).is_err());
+    }
+}
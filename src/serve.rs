@@ -0,0 +1,267 @@
+use std::io::{self, Read};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::Serialize;
+use tiny_http::{Header, Response, Server};
+
+use crate::engine::Engine;
+use crate::output::{account_json, OutputOptions};
+use crate::shutdown::Interrupt;
+use crate::store::AccountStore;
+use crate::types::{Money, Precision, Transaction, TX};
+
+/// How long `serve` waits for a request before checking the interrupt.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Largest request body read, far above any single transaction.
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// How served transactions are read and accounts rendered, matching the
+/// options of a CSV run.
+#[derive(Clone, Debug, Default)]
+pub struct ServeOptions {
+    /// Amounts with more decimals are truncated to it, and those that
+    /// truncate to zero are rejected.
+    pub precision: Precision,
+    /// Deposits and withdrawals below it are rejected.
+    pub min_amount: Option<Money>,
+    /// Accounts are rendered like the rows of `OutputFormat::Json`.
+    pub output: OutputOptions,
+}
+
+/// A JSON response body and its HTTP status.
+#[derive(Debug, PartialEq)]
+pub struct Reply {
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: String,
+}
+
+impl Reply {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Reply {
+            status,
+            body: serde_json::to_string(value).unwrap_or_default(),
+        }
+    }
+
+    fn account<A: AccountStore>(engine: &Engine<A>, client: u16, options: &OutputOptions) -> Self {
+        match engine.accounts().account(client) {
+            Some(account) => Reply {
+                status: 200,
+                body: account_json(client, account, options),
+            },
+            None => Reply::error(404, "account_not_found", "Account not found"),
+        }
+    }
+
+    fn error(status: u16, error: &str, message: impl ToString) -> Self {
+        Reply::json(
+            status,
+            &ErrorBody {
+                error,
+                message: message.to_string(),
+            },
+        )
+    }
+}
+
+/// Routes a request to the engine:
+/// - `POST /transactions` applies the JSON transaction in `body`, with the
+///   same fields as a CSV row, and replies with the client's updated account.
+/// - `GET /accounts/{client}` replies with the client's account.
+///
+/// Failed transactions reply with the error kind and message.
+pub fn handle<A: AccountStore>(
+    engine: &Mutex<Engine<A>>,
+    options: &ServeOptions,
+    method: &str,
+    url: &str,
+    body: &str,
+) -> Reply {
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["transactions"]) => {
+            let tx = match parse_transaction(body, options) {
+                Ok(tx) => tx,
+                Err(reply) => return reply,
+            };
+            let client = tx.client();
+            let mut engine = engine.lock().unwrap_or_else(PoisonError::into_inner);
+            match engine.process(tx) {
+                Ok(()) => Reply::account(&engine, client, &options.output),
+                Err(err) => Reply::error(422, err.kind(), &err),
+            }
+        }
+        ("GET", ["accounts", client]) => {
+            let engine = engine.lock().unwrap_or_else(PoisonError::into_inner);
+            match client.parse() {
+                Ok(client) => Reply::account(&engine, client, &options.output),
+                Err(_) => Reply::error(404, "account_not_found", "Account not found"),
+            }
+        }
+        (_, ["transactions"] | ["accounts", _]) => {
+            Reply::error(405, "method_not_allowed", "Method not allowed")
+        }
+        _ => Reply::error(404, "not_found", format!("No route for {}", path)),
+    }
+}
+
+fn parse_transaction(body: &str, options: &ServeOptions) -> Result<TX, Reply> {
+    let deserialize = |err: serde_json::Error| Reply::error(400, "deserialize", err);
+    let value: serde_json::Value = serde_json::from_str(body).map_err(deserialize)?;
+    let transaction = Transaction::from_json_value(&value).map_err(deserialize)?;
+    TX::from_transaction(transaction)
+        .and_then(|tx| tx.with_precision(options.precision))
+        .and_then(|tx| match options.min_amount {
+            Some(minimum) => tx.with_min_amount(minimum),
+            None => Ok(tx),
+        })
+        .map_err(|err| Reply::error(400, err.kind(), &err))
+}
+
+/// Reads a request body of at most `MAX_BODY_BYTES`, without reading past
+/// the limit.
+fn read_body(reader: impl Read) -> Result<String, Reply> {
+    let mut body = String::new();
+    reader
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_string(&mut body)
+        .map_err(|err| Reply::error(400, "read", err))?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(Reply::error(
+            413,
+            "body_too_large",
+            format!("Request body is over {} bytes", MAX_BODY_BYTES),
+        ));
+    }
+    Ok(body)
+}
+
+/// Serves `handle` over HTTP on `addr`, one request at a time, until the
+/// interrupt is triggered.
+pub fn serve<A: AccountStore>(
+    addr: &str,
+    engine: &Mutex<Engine<A>>,
+    options: &ServeOptions,
+    interrupt: &Interrupt,
+) -> io::Result<()> {
+    let server = Server::http(addr).map_err(io::Error::other)?;
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("valid content type header");
+    while !interrupt.is_triggered() {
+        let Some(mut request) = server.recv_timeout(POLL_INTERVAL)? else {
+            continue;
+        };
+        let reply = match read_body(request.as_reader()) {
+            Ok(body) => handle(
+                engine,
+                options,
+                request.method().as_str(),
+                request.url(),
+                &body,
+            ),
+            Err(reply) => reply,
+        };
+        let response = Response::from_string(reply.body)
+            .with_status_code(reply.status)
+            .with_header(content_type.clone());
+        if let Err(err) = request.respond(response) {
+            eprintln!("Failed to send response: {}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_handle() {
+        let engine = Mutex::new(Engine::new(Config::default()));
+        let options = ServeOptions::default();
+        let post = |body: &str| handle(&engine, &options, "POST", "/transactions", body);
+
+        let reply = post(r#"{"type":"deposit","client":1,"tx":1,"amount":5.5}"#);
+        assert_eq!(reply.status, 200);
+        let account: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(account["available"], 5.5);
+        assert_eq!(account["locked"], false);
+
+        let reply = post(r#"{"type":"withdrawal","client":1,"tx":2,"amount":"9"}"#);
+        assert_eq!(reply.status, 422);
+        let error: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(error["error"], "not_enough_funds");
+
+        assert_eq!(post(r#"{"type":"deposit","client":1}"#).status, 400);
+        assert_eq!(post("not json").status, 400);
+
+        let reply = handle(&engine, &options, "GET", "/accounts/1", "");
+        assert_eq!(reply.status, 200);
+        let account: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(account["total"], 5.5);
+
+        assert_eq!(
+            handle(&engine, &options, "GET", "/accounts/2", "").status,
+            404
+        );
+        assert_eq!(
+            handle(&engine, &options, "GET", "/accounts/x", "").status,
+            404
+        );
+        assert_eq!(
+            handle(&engine, &options, "DELETE", "/accounts/1", "").status,
+            405
+        );
+        assert_eq!(handle(&engine, &options, "GET", "/", "").status, 404);
+    }
+
+    #[test]
+    fn test_handle_options() {
+        let engine = Mutex::new(Engine::new(Config::default()));
+        let options = ServeOptions {
+            precision: Precision::new(2),
+            min_amount: Some(Money::from(1.0)),
+            ..Default::default()
+        };
+        let post = |body: &str| handle(&engine, &options, "POST", "/transactions", body);
+
+        let reply = post(r#"{"type":"deposit","client":1,"tx":1,"amount":"0.004"}"#);
+        assert_eq!(reply.status, 400);
+        let error: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(error["error"], "amount_below_precision");
+
+        let reply = post(r#"{"type":"deposit","client":1,"tx":2,"amount":"0.5"}"#);
+        assert_eq!(reply.status, 400);
+        let error: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(error["error"], "amount_below_minimum");
+
+        let reply = post(r#"{"type":"deposit","client":1,"tx":3,"amount":"1.259"}"#);
+        assert_eq!(reply.status, 200);
+        assert_eq!(
+            reply.body,
+            r#"{"client":1,"available":1.25,"held":0.0,"total":1.25,"locked":false}"#
+        );
+    }
+
+    #[test]
+    fn test_read_body_limit() {
+        let body = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.0"}"#;
+        assert_eq!(read_body(body.as_bytes()).unwrap(), body);
+
+        // An endless body is refused once it passes the limit.
+        let reply = read_body(io::repeat(b' ')).unwrap_err();
+        assert_eq!(reply.status, 413);
+        let error: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(error["error"], "body_too_large");
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Triggers the interrupt on Ctrl-C. The flag is only checked between
+    /// rows, so a process blocked reading an idle stdin never notices it: a
+    /// second Ctrl-C exits right away with status 130.
+    #[cfg(feature = "graceful-shutdown")]
+    pub fn install(&self) -> Result<(), ctrlc::Error> {
+        let interrupt = self.clone();
+        ctrlc::set_handler(move || {
+            if interrupt.signal() {
+                eprintln!("Interrupted again, exiting without writing accounts");
+                std::process::exit(130);
+            }
+        })
+    }
+
+    pub fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Triggers the interrupt, returning whether it already was.
+    pub fn signal(&self) -> bool {
+        self.0.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger() {
+        let interrupt = Interrupt::new();
+        let handler = interrupt.clone();
+        assert!(!interrupt.is_triggered());
+        handler.trigger();
+        assert!(interrupt.is_triggered());
+    }
+
+    #[test]
+    fn test_signal_reports_repeat() {
+        let interrupt = Interrupt::new();
+        assert!(!interrupt.signal());
+        assert!(interrupt.is_triggered());
+        // The handler exits on this one.
+        assert!(interrupt.signal());
+    }
+}
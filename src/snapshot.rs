@@ -0,0 +1,456 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::types::*;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Snapshot {
+    pub accounts: Accounts,
+    pub transactions: Transactions,
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    accounts: &'a Accounts,
+    transactions: &'a Transactions,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    InconsistentTotals(Vec<u16>),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "SnapshotError: {}", err),
+            SnapshotError::Parse(err) => write!(f, "SnapshotError: Invalid snapshot: {}", err),
+            SnapshotError::InconsistentTotals(clients) => write!(
+                f,
+                "SnapshotError: total doesn't equal available + held for clients {:?}",
+                clients
+            ),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn load(path: &str) -> Result<Self, SnapshotError> {
+        let file = File::open(path).map_err(SnapshotError::Io)?;
+        Self::from_reader(BufReader::new(file))
+    }
+
+    pub fn from_reader(rdr: impl io::Read) -> Result<Self, SnapshotError> {
+        let mut snapshot: Snapshot = serde_json::from_reader(rdr).map_err(SnapshotError::Parse)?;
+        snapshot.validate()?;
+        snapshot.count_open_disputes();
+        Ok(snapshot)
+    }
+
+    /// Recomputes `Account::open_disputes` from the disputed transactions,
+    /// as snapshots written before the counter existed load it as 0.
+    fn count_open_disputes(&mut self) {
+        for account in self.accounts.values_mut() {
+            account.open_disputes = 0;
+        }
+        for state in self.transactions.values().filter(|state| state.disputed) {
+            if let Some(account) = self.accounts.get_mut(&state.client) {
+                account.open_disputes += 1;
+            }
+        }
+    }
+
+    /// Re-keys the transactions for `config`, e.g. when a snapshot written
+    /// with global transaction ids is loaded with per-client ids.
+    pub fn rekey(&mut self, config: &Config) {
+        self.transactions = std::mem::take(&mut self.transactions)
+            .into_iter()
+            .map(|(key, state)| (config.tx_key(state.client, key.tx), state))
+            .collect();
+    }
+
+    /// Checks the `total == available + held` invariant of every account.
+    /// A sum that overflows breaks it too.
+    pub fn validate(&self) -> Result<(), SnapshotError> {
+        let mut clients: Vec<u16> = self
+            .accounts
+            .iter()
+            .filter(|(_, acc)| acc.available.checked_add(acc.held) != Some(acc.total))
+            .map(|(client, _)| *client)
+            .collect();
+        if clients.is_empty() {
+            return Ok(());
+        }
+        clients.sort_unstable();
+        Err(SnapshotError::InconsistentTotals(clients))
+    }
+
+    pub fn save(
+        accounts: &Accounts,
+        transactions: &Transactions,
+        path: &str,
+    ) -> Result<(), SnapshotError> {
+        let file = File::create(path).map_err(SnapshotError::Io)?;
+        Self::write(accounts, transactions, BufWriter::new(file))
+    }
+
+    pub fn write(
+        accounts: &Accounts,
+        transactions: &Transactions,
+        wtr: impl io::Write,
+    ) -> Result<(), SnapshotError> {
+        serde_json::to_writer(
+            wtr,
+            &SnapshotRef {
+                accounts,
+                transactions,
+            },
+        )
+        .map_err(SnapshotError::Parse)
+    }
+
+    /// Writes the snapshot next to `path` and renames it into place, so
+    /// `path` never holds a partially written snapshot.
+    fn replace(
+        accounts: &Accounts,
+        transactions: &Transactions,
+        path: &Path,
+    ) -> Result<(), SnapshotError> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut wtr = BufWriter::new(File::create(&tmp).map_err(SnapshotError::Io)?);
+        Self::write(accounts, transactions, &mut wtr)?;
+        wtr.flush().map_err(SnapshotError::Io)?;
+        drop(wtr);
+        fs::rename(&tmp, path).map_err(SnapshotError::Io)
+    }
+}
+
+/// How `PeriodicSnapshots` writes each snapshot.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SnapshotMode {
+    /// Serializes the state before processing continues.
+    #[default]
+    Blocking,
+    /// Hands a clone of the state to a worker thread that serializes it
+    /// while processing continues.
+    Background,
+}
+
+impl FromStr for SnapshotMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "blocking" => Ok(SnapshotMode::Blocking),
+            "background" => Ok(SnapshotMode::Background),
+            _ => Err(format!("Invalid snapshot mode: {}", s)),
+        }
+    }
+}
+
+/// Snapshots of a running engine, each replacing the previous one at the
+/// same path.
+///
+/// In background mode at most one snapshot waits for the worker: a snapshot
+/// taken while another is still queued is skipped, the next one catches up.
+pub struct PeriodicSnapshots {
+    path: PathBuf,
+    worker: Option<Worker>,
+}
+
+struct Worker {
+    sender: SyncSender<Snapshot>,
+    handle: JoinHandle<Result<(), SnapshotError>>,
+}
+
+impl PeriodicSnapshots {
+    pub fn new(path: impl Into<PathBuf>, mode: SnapshotMode) -> Self {
+        let path = path.into();
+        let worker = match mode {
+            SnapshotMode::Blocking => None,
+            SnapshotMode::Background => {
+                let (sender, receiver) = mpsc::sync_channel::<Snapshot>(1);
+                let path = path.clone();
+                let handle = thread::spawn(move || {
+                    for snapshot in receiver {
+                        Snapshot::replace(&snapshot.accounts, &snapshot.transactions, &path)?;
+                    }
+                    Ok(())
+                });
+                Some(Worker { sender, handle })
+            }
+        };
+        Self { path, worker }
+    }
+
+    /// Snapshots the state. A failed background write is returned by the
+    /// next call, or by `finish`.
+    pub fn write(
+        &mut self,
+        accounts: &Accounts,
+        transactions: &Transactions,
+    ) -> Result<(), SnapshotError> {
+        let Some(Worker { sender, .. }) = &self.worker else {
+            return Snapshot::replace(accounts, transactions, &self.path);
+        };
+        let snapshot = Snapshot {
+            accounts: accounts.clone(),
+            transactions: transactions.clone(),
+        };
+        match sender.try_send(snapshot) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            // The worker stopped on an error.
+            Err(TrySendError::Disconnected(_)) => self.join(),
+        }
+    }
+
+    /// Waits for the snapshot being written, if any.
+    pub fn finish(mut self) -> Result<(), SnapshotError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), SnapshotError> {
+        match self.worker.take() {
+            Some(Worker { sender, handle }) => {
+                drop(sender);
+                handle.join().expect("snapshot worker panicked")
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{write_accounts, OutputColumn, OutputOptions};
+    use crate::process_transaction::process_transaction;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(0.5),
+                held: Money::from(1.0),
+                total: Money::from(1.5),
+                locked: false,
+                ..Default::default()
+            },
+        );
+        let mut transactions = Transactions::new();
+        transactions.insert(
+            1.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 1,
+                amount: Money::from(1.0),
+                disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+
+        let mut buf = Vec::new();
+        Snapshot::write(&accounts, &transactions, &mut buf).unwrap();
+        let snapshot = Snapshot::from_reader(buf.as_slice()).unwrap();
+        assert_eq!(snapshot.accounts.get(&1).unwrap().held, Money::from(1.0));
+        assert_eq!(snapshot.transactions, transactions);
+    }
+
+    #[test]
+    fn test_snapshot_inconsistent_totals() {
+        let json = r#"{
+            "accounts": {
+                "1": {"available": 1.0, "held": 0.0, "total": 1.0, "locked": false},
+                "2": {"available": 1.0, "held": 1.0, "total": 1.0, "locked": false},
+                "3": {"available": 0.1, "held": 0.2, "total": 0.3, "locked": false},
+                "4": {"available": 5.0, "held": 0.0, "total": 7.5, "locked": true}
+            },
+            "transactions": {}
+        }"#;
+        match Snapshot::from_reader(json.as_bytes()) {
+            Err(SnapshotError::InconsistentTotals(clients)) => assert_eq!(clients, vec![2, 4]),
+            other => panic!("expected inconsistent totals, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_overflowing_totals() {
+        let json = r#"{
+            "accounts": {
+                "1": {"available": "900000000000000", "held": "900000000000000", "total": "-44674407370955.1616", "locked": false}
+            },
+            "transactions": {}
+        }"#;
+        match Snapshot::from_reader(json.as_bytes()) {
+            Err(SnapshotError::InconsistentTotals(clients)) => assert_eq!(clients, vec![1]),
+            other => panic!("expected inconsistent totals, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_locked_account_resolves() {
+        let json = r#"{
+            "accounts": {
+                "1": {"available": 1.0, "held": 4.0, "total": 5.0, "locked": true}
+            },
+            "transactions": {
+                "7": {"client": 1, "amount": 4.0, "disputed": true, "destination": null}
+            }
+        }"#;
+        let Snapshot {
+            mut accounts,
+            mut transactions,
+        } = Snapshot::from_reader(json.as_bytes()).unwrap();
+        let resolve = || TX::Resolve(Resolve { client: 1, tx: 7 });
+
+        assert_eq!(
+            process_transaction(
+                resolve(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountLocked(resolve()))
+        );
+
+        let config = Config {
+            resolve_when_locked: true,
+            ..Default::default()
+        };
+        process_transaction(resolve(), &mut accounts, &mut transactions, &config).unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (Money::from(5.0), Money::ZERO, Money::from(5.0))
+        );
+        assert!(account.locked);
+        assert!(!transactions.contains_key(&7.into()));
+    }
+
+    #[test]
+    fn test_snapshot_counts_open_disputes() {
+        let json = r#"{
+            "accounts": {
+                "1": {"available": 1.0, "held": 4.0, "total": 5.0, "locked": false},
+                "2": {"available": 2.0, "held": 0.0, "total": 2.0, "locked": false}
+            },
+            "transactions": {
+                "7": {"client": 1, "amount": 4.0, "disputed": true, "destination": null},
+                "8": {"client": 2, "amount": 2.0, "disputed": false, "destination": null}
+            }
+        }"#;
+        let snapshot = Snapshot::from_reader(json.as_bytes()).unwrap();
+        assert_eq!(snapshot.accounts[&1].open_disputes, 1);
+        assert_eq!(snapshot.accounts[&2].open_disputes, 0);
+
+        let mut buf = Vec::new();
+        let options = OutputOptions {
+            columns: vec![OutputColumn::Client, OutputColumn::UnderDispute],
+            ..Default::default()
+        };
+        write_accounts(&snapshot.accounts, &mut buf, &options);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,under_dispute\n1,true\n2,false\n"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_rekey() {
+        let json = r#"{
+            "accounts": {
+                "1": {"available": 1.0, "held": 0.0, "total": 1.0, "locked": false},
+                "2": {"available": 2.0, "held": 0.0, "total": 2.0, "locked": false}
+            },
+            "transactions": {
+                "5": {"client": 1, "amount": 1.0, "disputed": false, "destination": null},
+                "2:5": {"client": 2, "amount": 2.0, "disputed": false, "destination": null}
+            }
+        }"#;
+        let mut snapshot = Snapshot::from_reader(json.as_bytes()).unwrap();
+        let config = Config {
+            per_client_tx_ids: true,
+            ..Default::default()
+        };
+        snapshot.rekey(&config);
+        assert_eq!(
+            snapshot.transactions[&config.tx_key(1, 5)].amount,
+            Money::from(1.0)
+        );
+        assert_eq!(
+            snapshot.transactions[&config.tx_key(2, 5)].amount,
+            Money::from(2.0)
+        );
+
+        let mut buf = Vec::new();
+        Snapshot::write(&snapshot.accounts, &snapshot.transactions, &mut buf).unwrap();
+        let json = String::from_utf8(buf).unwrap();
+        assert!(json.contains("\"1:5\""));
+        assert!(json.contains("\"2:5\""));
+    }
+
+    #[test]
+    fn test_periodic_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let deposit = |tx: u32| {
+            TX::Deposit(Deposit {
+                client: 1,
+                tx,
+                amount: Money::from(1.0),
+            })
+        };
+
+        for mode in [SnapshotMode::Blocking, SnapshotMode::Background] {
+            let path = dir.path().join(format!("{:?}.json", mode));
+            let mut snapshots = PeriodicSnapshots::new(&path, mode);
+            let mut accounts = Accounts::new();
+            let mut transactions = Transactions::new();
+            let config = Config::default();
+            for tx in 1..=3 {
+                process_transaction(deposit(tx), &mut accounts, &mut transactions, &config)
+                    .unwrap();
+            }
+            snapshots.write(&accounts, &transactions).unwrap();
+
+            // Processing goes on while the snapshot is written.
+            for tx in 4..=10 {
+                process_transaction(deposit(tx), &mut accounts, &mut transactions, &config)
+                    .unwrap();
+            }
+            snapshots.finish().unwrap();
+
+            let snapshot = Snapshot::load(path.to_str().unwrap()).unwrap();
+            assert_eq!(snapshot.accounts[&1].total, Money::from(3.0));
+            assert_eq!(snapshot.transactions.len(), 3);
+            assert_eq!(accounts[&1].total, Money::from(10.0));
+        }
+    }
+
+    #[test]
+    fn test_periodic_snapshots_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("snapshot.json");
+        for mode in [SnapshotMode::Blocking, SnapshotMode::Background] {
+            let mut snapshots = PeriodicSnapshots::new(&path, mode);
+            let written = snapshots
+                .write(&Accounts::new(), &Transactions::new())
+                .and_then(|_| snapshots.finish());
+            assert!(matches!(written, Err(SnapshotError::Io(_))), "{:?}", mode);
+        }
+    }
+}
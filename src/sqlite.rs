@@ -0,0 +1,136 @@
+use rusqlite::{params, Connection};
+
+use crate::types::*;
+
+pub fn export_to_path(
+    path: &str,
+    accounts: &Accounts,
+    transactions: Option<&Transactions>,
+) -> rusqlite::Result<()> {
+    let mut conn = Connection::open(path)?;
+    export(&mut conn, accounts, transactions)
+}
+
+/// Writes accounts, and optionally transactions, into tables mirroring the
+/// CSV output columns and `TXState`, replacing rows with the same key.
+pub fn export(
+    conn: &mut Connection,
+    accounts: &Accounts,
+    transactions: Option<&Transactions>,
+) -> rusqlite::Result<()> {
+    let db = conn.transaction()?;
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS accounts (
+            client INTEGER PRIMARY KEY,
+            available REAL NOT NULL,
+            held REAL NOT NULL,
+            total REAL NOT NULL,
+            locked INTEGER NOT NULL
+        );",
+    )?;
+    {
+        let mut insert = db.prepare(
+            "INSERT OR REPLACE INTO accounts (client, available, held, total, locked)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (client, account) in accounts {
+            insert.execute(params![
+                client,
+                account.available.to_f64(),
+                account.held.to_f64(),
+                account.total.to_f64(),
+                account.locked,
+            ])?;
+        }
+    }
+
+    if let Some(transactions) = transactions {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS transactions (
+                tx INTEGER NOT NULL,
+                client INTEGER NOT NULL,
+                amount REAL NOT NULL,
+                disputed INTEGER NOT NULL,
+                destination TEXT,
+                PRIMARY KEY (client, tx)
+            );",
+        )?;
+        let mut insert = db.prepare(
+            "INSERT OR REPLACE INTO transactions (tx, client, amount, disputed, destination)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (key, state) in transactions {
+            insert.execute(params![
+                key.tx,
+                state.client,
+                state.amount.to_f64(),
+                state.disputed,
+                state.destination,
+            ])?;
+        }
+    }
+
+    db.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            7,
+            Account {
+                available: Money::from(1.5),
+                held: Money::from(2.0),
+                total: Money::from(3.5),
+                locked: true,
+                ..Default::default()
+            },
+        );
+        let mut transactions = Transactions::new();
+        transactions.insert(
+            9.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 7,
+                amount: Money::from(2.0),
+                disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        export(&mut conn, &accounts, Some(&transactions)).unwrap();
+
+        let row: (u16, f64, f64, f64, bool) = conn
+            .query_row(
+                "SELECT client, available, held, total, locked FROM accounts WHERE client = 7",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(row, (7, 1.5, 2.0, 3.5, true));
+
+        let disputed: bool = conn
+            .query_row(
+                "SELECT disputed FROM transactions WHERE tx = 9",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(disputed);
+    }
+}
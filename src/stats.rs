@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::report::ProcessingError;
+use crate::types::{Accounts, TruncationLoss};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub rows: u64,
+    pub processed: u64,
+    pub duplicates: u64,
+    pub ignored: u64,
+    pub errors: u64,
+    pub processed_by_type: BTreeMap<&'static str, u64>,
+    pub errors_by_kind: BTreeMap<&'static str, u64>,
+    /// Ids of the processed transactions, only collected when requested.
+    #[serde(skip)]
+    pub tx_ids: BTreeSet<u32>,
+    /// Failed and skipped rows, only collected when requested.
+    #[serde(skip)]
+    pub error_report: Vec<ProcessingError>,
+    /// Precision truncated from deposit and withdrawal amounts, only
+    /// collected when requested.
+    #[serde(skip)]
+    pub truncation_loss: TruncationLoss,
+    /// Institution of each client, from the first row naming one, only
+    /// collected when requested.
+    #[serde(skip)]
+    pub institutions: BTreeMap<u16, String>,
+}
+
+impl Stats {
+    pub fn record_processed(&mut self, typ: &'static str) {
+        self.processed += 1;
+        *self.processed_by_type.entry(typ).or_insert(0) += 1;
+    }
+
+    /// Takes back a processed row, e.g. one of a rolled back batch.
+    pub fn unrecord_processed(&mut self, typ: &'static str) {
+        self.processed -= 1;
+        if let Some(count) = self.processed_by_type.get_mut(typ) {
+            *count -= 1;
+            if *count == 0 {
+                self.processed_by_type.remove(typ);
+            }
+        }
+    }
+
+    pub fn record_error(&mut self, kind: &'static str) {
+        self.errors += 1;
+        *self.errors_by_kind.entry(kind).or_insert(0) += 1;
+    }
+
+    /// Adds the counters of another run, e.g. of the next input file.
+    pub fn merge(&mut self, other: Stats) {
+        self.rows += other.rows;
+        self.processed += other.processed;
+        self.duplicates += other.duplicates;
+        self.ignored += other.ignored;
+        self.errors += other.errors;
+        for (typ, count) in other.processed_by_type {
+            *self.processed_by_type.entry(typ).or_insert(0) += count;
+        }
+        for (kind, count) in other.errors_by_kind {
+            *self.errors_by_kind.entry(kind).or_insert(0) += count;
+        }
+        self.tx_ids.extend(other.tx_ids);
+        self.error_report.extend(other.error_report);
+        self.truncation_loss += other.truncation_loss;
+        for (client, institution) in other.institutions {
+            self.institutions.entry(client).or_insert(institution);
+        }
+    }
+
+    /// Renders the counters in the Prometheus text exposition format.
+    pub fn prometheus(&self, accounts: &Accounts) -> String {
+        let mut out = String::new();
+        metric(
+            &mut out,
+            "rows_total",
+            "counter",
+            "Rows read from the input.",
+            &[(String::new(), self.rows)],
+        );
+        metric(
+            &mut out,
+            "duplicates_total",
+            "counter",
+            "Rows skipped as duplicates.",
+            &[(String::new(), self.duplicates)],
+        );
+        metric(
+            &mut out,
+            "ignored_total",
+            "counter",
+            "Rows ignored by the selected mode.",
+            &[(String::new(), self.ignored)],
+        );
+        let by_type: Vec<(String, u64)> = self
+            .processed_by_type
+            .iter()
+            .map(|(typ, count)| (format!("{{type=\"{}\"}}", typ), *count))
+            .collect();
+        metric(
+            &mut out,
+            "transactions_total",
+            "counter",
+            "Transactions processed successfully, by type.",
+            &by_type,
+        );
+        let by_kind: Vec<(String, u64)> = self
+            .errors_by_kind
+            .iter()
+            .map(|(kind, count)| (format!("{{kind=\"{}\"}}", kind), *count))
+            .collect();
+        metric(
+            &mut out,
+            "errors_total",
+            "counter",
+            "Rows that failed, by error kind.",
+            &by_kind,
+        );
+        metric(
+            &mut out,
+            "accounts",
+            "gauge",
+            "Client accounts.",
+            &[(String::new(), accounts.len() as u64)],
+        );
+        metric(
+            &mut out,
+            "locked_accounts",
+            "gauge",
+            "Locked client accounts.",
+            &[(
+                String::new(),
+                accounts.values().filter(|acc| acc.locked).count() as u64,
+            )],
+        );
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, typ: &str, help: &str, samples: &[(String, u64)]) {
+    let name = format!("transaction_processor_{}", name);
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, typ).unwrap();
+    for (labels, value) in samples {
+        writeln!(out, "{}{} {}", name, labels, value).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Account, Money};
+
+    #[test]
+    fn test_merge() {
+        let mut stats = Stats {
+            rows: 2,
+            ..Default::default()
+        };
+        stats.record_processed("deposit");
+        stats.record_error("not_enough_funds");
+        let mut other = Stats {
+            rows: 3,
+            duplicates: 1,
+            ..Default::default()
+        };
+        other.record_processed("deposit");
+        other.record_processed("dispute");
+        other.tx_ids.insert(4);
+
+        stats.merge(other);
+        assert_eq!((stats.rows, stats.processed, stats.duplicates), (5, 3, 1));
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.processed_by_type.get("deposit"), Some(&2));
+        assert_eq!(stats.processed_by_type.get("dispute"), Some(&1));
+        assert_eq!(stats.errors_by_kind.get("not_enough_funds"), Some(&1));
+        assert!(stats.tx_ids.contains(&4));
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let mut stats = Stats {
+            rows: 4,
+            ..Default::default()
+        };
+        stats.record_processed("deposit");
+        stats.record_processed("deposit");
+        stats.record_processed("chargeback");
+        stats.record_error("not_enough_funds");
+
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.0),
+                held: Money::ZERO,
+                total: Money::from(1.0),
+                locked: true,
+                ..Default::default()
+            },
+        );
+        accounts.insert(
+            2,
+            Account {
+                available: Money::from(1.0),
+                held: Money::ZERO,
+                total: Money::from(1.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        let out = stats.prometheus(&accounts);
+        assert!(out.contains("# TYPE transaction_processor_rows_total counter\n"));
+        assert!(out.contains("\ntransaction_processor_rows_total 4\n"));
+        assert!(out.contains("\ntransaction_processor_transactions_total{type=\"deposit\"} 2\n"));
+        assert!(out.contains("\ntransaction_processor_transactions_total{type=\"chargeback\"} 1\n"));
+        assert!(out.contains("\ntransaction_processor_errors_total{kind=\"not_enough_funds\"} 1\n"));
+        assert!(out.contains("# TYPE transaction_processor_accounts gauge\n"));
+        assert!(out.contains("\ntransaction_processor_accounts 2\n"));
+        assert!(out.contains("\ntransaction_processor_locked_accounts 1\n"));
+    }
+}
@@ -0,0 +1,271 @@
+use std::borrow::Cow;
+
+use crate::types::{Account, Accounts};
+
+/// Where the transaction functions look up and create client accounts.
+pub trait AccountStore {
+    fn account(&self, client: u16) -> Option<&Account>;
+
+    fn account_mut(&mut self, client: u16) -> Option<&mut Account>;
+
+    /// The client's account, created empty if it doesn't exist yet.
+    fn account_or_default(&mut self, client: u16) -> &mut Account;
+
+    fn account_count(&self) -> usize;
+
+    fn remove_account(&mut self, client: u16) -> Option<Account>;
+
+    /// Replaces the client's account, creating it if needed.
+    fn insert_account(&mut self, client: u16, account: Account) {
+        *self.account_or_default(client) = account;
+    }
+
+    /// Moves every account out, leaving the store empty.
+    fn take_accounts(&mut self) -> Accounts;
+
+    /// Every account in client order, borrowed when the store already keeps
+    /// them in a map.
+    fn as_accounts(&self) -> Cow<'_, Accounts>;
+
+    fn into_accounts(mut self) -> Accounts
+    where
+        Self: Sized,
+    {
+        self.take_accounts()
+    }
+}
+
+impl AccountStore for Accounts {
+    fn account(&self, client: u16) -> Option<&Account> {
+        self.get(&client)
+    }
+
+    fn account_mut(&mut self, client: u16) -> Option<&mut Account> {
+        self.get_mut(&client)
+    }
+
+    fn account_or_default(&mut self, client: u16) -> &mut Account {
+        self.entry(client).or_default()
+    }
+
+    fn account_count(&self) -> usize {
+        self.len()
+    }
+
+    fn remove_account(&mut self, client: u16) -> Option<Account> {
+        self.remove(&client)
+    }
+
+    fn insert_account(&mut self, client: u16, account: Account) {
+        self.insert(client, account);
+    }
+
+    fn take_accounts(&mut self) -> Accounts {
+        std::mem::take(self)
+    }
+
+    fn as_accounts(&self) -> Cow<'_, Accounts> {
+        Cow::Borrowed(self)
+    }
+}
+
+/// Accounts kept in a `Vec` indexed by client id, which beats hashing when
+/// client ids are small and dense. Ids must fall in the range given to
+/// `with_range`: the first account created outside it moves every account
+/// into an `Accounts` map, which is used from then on.
+#[derive(Clone, Debug)]
+pub struct DenseAccounts(Slots);
+
+#[derive(Clone, Debug)]
+enum Slots {
+    Dense {
+        slots: Vec<Option<Account>>,
+        len: usize,
+    },
+    Sparse(Accounts),
+}
+
+impl DenseAccounts {
+    /// A store for client ids `0..range`.
+    pub fn with_range(range: usize) -> Self {
+        let range = range.min(u16::MAX as usize + 1);
+        DenseAccounts(Slots::Dense {
+            slots: vec![None; range],
+            len: 0,
+        })
+    }
+
+    /// Whether every client so far fit the dense range.
+    pub fn is_dense(&self) -> bool {
+        matches!(self.0, Slots::Dense { .. })
+    }
+
+    fn sparse(&mut self) -> &mut Accounts {
+        if let Slots::Dense { .. } = self.0 {
+            let accounts = self.take_accounts();
+            self.0 = Slots::Sparse(accounts);
+        }
+        match &mut self.0 {
+            Slots::Sparse(accounts) => accounts,
+            Slots::Dense { .. } => unreachable!(),
+        }
+    }
+}
+
+impl AccountStore for DenseAccounts {
+    fn account(&self, client: u16) -> Option<&Account> {
+        match &self.0 {
+            Slots::Dense { slots, .. } => slots.get(client as usize)?.as_ref(),
+            Slots::Sparse(accounts) => accounts.get(&client),
+        }
+    }
+
+    fn account_mut(&mut self, client: u16) -> Option<&mut Account> {
+        match &mut self.0 {
+            Slots::Dense { slots, .. } => slots.get_mut(client as usize)?.as_mut(),
+            Slots::Sparse(accounts) => accounts.get_mut(&client),
+        }
+    }
+
+    fn account_or_default(&mut self, client: u16) -> &mut Account {
+        let fits = match &self.0 {
+            Slots::Dense { slots, .. } => (client as usize) < slots.len(),
+            Slots::Sparse(_) => false,
+        };
+        if !fits {
+            return self.sparse().entry(client).or_default();
+        }
+        match &mut self.0 {
+            Slots::Dense { slots, len } => {
+                let slot = &mut slots[client as usize];
+                if slot.is_none() {
+                    *len += 1;
+                }
+                slot.get_or_insert_with(Account::default)
+            }
+            Slots::Sparse(_) => unreachable!(),
+        }
+    }
+
+    fn account_count(&self) -> usize {
+        match &self.0 {
+            Slots::Dense { len, .. } => *len,
+            Slots::Sparse(accounts) => accounts.len(),
+        }
+    }
+
+    fn remove_account(&mut self, client: u16) -> Option<Account> {
+        match &mut self.0 {
+            Slots::Dense { slots, len } => {
+                let account = slots.get_mut(client as usize)?.take()?;
+                *len -= 1;
+                Some(account)
+            }
+            Slots::Sparse(accounts) => accounts.remove(&client),
+        }
+    }
+
+    fn take_accounts(&mut self) -> Accounts {
+        match &mut self.0 {
+            Slots::Dense { slots, len } => {
+                *len = 0;
+                slots
+                    .iter_mut()
+                    .enumerate()
+                    .filter_map(|(client, slot)| Some((client as u16, slot.take()?)))
+                    .collect()
+            }
+            Slots::Sparse(accounts) => std::mem::take(accounts),
+        }
+    }
+
+    fn as_accounts(&self) -> Cow<'_, Accounts> {
+        match &self.0 {
+            Slots::Dense { slots, .. } => Cow::Owned(
+                slots
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(client, slot)| Some((client as u16, slot.clone()?)))
+                    .collect(),
+            ),
+            Slots::Sparse(accounts) => Cow::Borrowed(accounts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::output::{write_accounts, OutputOptions};
+    use crate::process_transaction::process_transaction;
+    use crate::types::*;
+
+    fn stream(clients: u16) -> Vec<TX> {
+        let mut txs = Vec::new();
+        for tx in 0..500u32 {
+            let client = (tx % clients as u32) as u16;
+            txs.push(TX::Deposit(Deposit {
+                client,
+                tx,
+                amount: Money::from(3.0),
+            }));
+            if tx % 4 == 0 {
+                txs.push(TX::Withdrawal(Withdrawal {
+                    client,
+                    tx: 10_000 + tx,
+                    amount: Money::from(5.0),
+                    destination: None,
+                }));
+            }
+            if tx % 6 == 0 {
+                txs.push(TX::Dispute(Dispute {
+                    client,
+                    tx,
+                    amount: None,
+                }));
+            }
+            if tx % 12 == 0 {
+                txs.push(TX::Chargeback(Chargeback { client, tx }));
+            }
+        }
+        txs
+    }
+
+    fn output(accounts: &Accounts) -> String {
+        let mut buf = Vec::new();
+        write_accounts(accounts, &mut buf, &OutputOptions::default());
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn process<A: AccountStore>(accounts: &mut A, txs: Vec<TX>) -> Vec<Result<(), TXError>> {
+        let mut transactions = Transactions::new();
+        txs.into_iter()
+            .map(|tx| process_transaction(tx, accounts, &mut transactions, &Config::default()))
+            .collect()
+    }
+
+    #[test]
+    fn test_dense_accounts_match_map() {
+        let mut accounts = Accounts::new();
+        let expected = process(&mut accounts, stream(16));
+
+        let mut dense = DenseAccounts::with_range(16);
+        assert_eq!(process(&mut dense, stream(16)), expected);
+        assert!(dense.is_dense());
+        assert_eq!(dense.account_count(), 16);
+        assert_eq!(output(&dense.into_accounts()), output(&accounts));
+    }
+
+    #[test]
+    fn test_dense_accounts_fall_back_to_map() {
+        let mut accounts = Accounts::new();
+        let expected = process(&mut accounts, stream(40));
+
+        let mut dense = DenseAccounts::with_range(16);
+        assert_eq!(process(&mut dense, stream(40)), expected);
+        assert!(!dense.is_dense());
+        assert_eq!(dense.account_count(), 40);
+        assert_eq!(output(&dense.into_accounts()), output(&accounts));
+    }
+}
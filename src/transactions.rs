@@ -1,38 +1,83 @@
+use crate::config::*;
+use crate::store::AccountStore;
 use crate::types::*;
 
-pub fn deposit(
+pub fn deposit<A: AccountStore>(
     op: Deposit,
-    accounts: &mut Accounts,
+    accounts: &mut A,
     transactions: &mut Transactions,
+    config: &Config,
 ) -> Result<(), TXError> {
-    let account = accounts.entry(op.client).or_insert(Account {
-        available: 0.0,
-        held: 0.0,
-        total: 0.0,
-        locked: false,
-    });
+    if transactions.contains_key(&config.tx_key(op.client, op.tx)) {
+        return Err(TXError::DuplicateTransaction(TX::Deposit(op)));
+    }
+    if let Some(limit) = config.deposit_limit {
+        let (deposited, seeded) = accounts
+            .account(op.client)
+            .map_or((Money::ZERO, false), |acc| (acc.deposited, acc.seeded));
+        if !seeded && deposited + op.amount > limit {
+            return Err(TXError::DepositLimitExceeded(TX::Deposit(op)));
+        }
+    }
+    let (available, total, deposited) = accounts
+        .account(op.client)
+        .map_or((Money::ZERO, Money::ZERO, Money::ZERO), |acc| {
+            (acc.available, acc.total, acc.deposited)
+        });
+    let (Some(available), Some(total), Some(deposited)) = (
+        add_balance(available, op.amount, config),
+        add_balance(total, op.amount, config),
+        deposited.checked_add(op.amount),
+    ) else {
+        return Err(TXError::BalanceOverflow(TX::Deposit(op)));
+    };
+    let created = config.on_account_created.is_some() && accounts.account(op.client).is_none();
+    let account = accounts.account_or_default(op.client);
     if account.locked {
         return Err(TXError::AccountLocked(TX::Deposit(op)));
     }
-    account.available += op.amount;
-    account.total += op.amount;
+    account.available = available;
+    account.total = total;
+    account.deposited = deposited;
+    account.deposit_count += 1;
+    if config.track_provenance {
+        account.tx_ids.insert(op.tx);
+    }
+    if let (true, Some(on_account_created)) = (created, config.on_account_created) {
+        on_account_created(op.client, account);
+    }
     transactions.insert(
-        op.tx,
+        config.tx_key(op.client, op.tx),
         TXState {
+            kind: TXKind::Deposit,
             client: op.client,
             amount: op.amount,
             disputed: false,
+            disputed_amount: None,
+            disputed_at: None,
+            destination: None,
         },
     );
-    return Ok(());
+    Ok(())
+}
+
+/// `balance + amount`, unless it overflows or exceeds `Config::max_balance`.
+fn add_balance(balance: Money, amount: Money, config: &Config) -> Option<Money> {
+    balance
+        .checked_add(amount)
+        .filter(|sum| config.max_balance.is_none_or(|max| *sum <= max))
 }
 
-pub fn withdraw(
+pub fn withdraw<A: AccountStore>(
     op: Withdrawal,
-    accounts: &mut Accounts,
+    accounts: &mut A,
     transactions: &mut Transactions,
+    config: &Config,
 ) -> Result<(), TXError> {
-    let account = match accounts.get_mut(&op.client) {
+    if transactions.contains_key(&config.tx_key(op.client, op.tx)) {
+        return Err(TXError::DuplicateTransaction(TX::Withdrawal(op)));
+    }
+    let account = match accounts.account_mut(op.client) {
         Some(acc) => acc,
         None => return Err(TXError::AccountNotFound(TX::Withdrawal(op))),
     };
@@ -48,599 +93,2447 @@ pub fn withdraw(
     }
     account.available -= op.amount;
     account.total -= op.amount;
+    account.withdrawn += op.amount;
+    account.withdrawal_count += 1;
+    if config.track_provenance {
+        account.tx_ids.insert(op.tx);
+    }
     transactions.insert(
-        op.tx,
+        config.tx_key(op.client, op.tx),
         TXState {
+            kind: TXKind::Withdrawal,
             client: op.client,
             amount: op.amount,
             disputed: false,
+            disputed_amount: None,
+            disputed_at: None,
+            destination: op.destination,
         },
     );
 
-    return Ok(());
+    Ok(())
 }
 
-pub fn dispute(
+pub fn dispute<A: AccountStore>(
     op: Dispute,
-    accounts: &mut Accounts,
+    accounts: &mut A,
     transactions: &mut Transactions,
+    config: &Config,
 ) -> Result<(), TXError> {
-    let parent_tx = match transactions.get_mut(&op.tx) {
+    let key = config.tx_key(op.client, op.tx);
+    let parent_tx = match transactions.get_mut(&key) {
         Some(tx) => tx,
         None => return Err(TXError::ParentTXNotFound(TX::Dispute(op))),
     };
-    let account = match accounts.get_mut(&parent_tx.client) {
+    if op.client != parent_tx.client {
+        return Err(TXError::ClientsDontMatch(parent_tx.client, TX::Dispute(op)));
+    }
+    let account = match accounts.account_mut(op.client) {
         Some(acc) => acc,
         None => return Err(TXError::AccountNotFound(TX::Dispute(op))),
     };
 
-    if op.client != parent_tx.client {
-        return Err(TXError::ClientsDontMatch(parent_tx.client, TX::Dispute(op)));
-    }
-    if account.locked {
+    if account.locked && config.locked_policy == LockedPolicy::BlockAll {
         return Err(TXError::AccountLocked(TX::Dispute(op)));
     }
+    if config.disputes_frozen.contains(&op.client) {
+        return Err(TXError::DisputesFrozen(TX::Dispute(op)));
+    }
     if parent_tx.disputed {
         return Err(TXError::ParentTXAlreadyDisputed(TX::Dispute(op)));
     }
-    if account.available < parent_tx.amount {
-        return Err(TXError::NotEnoughFunds(
-            account.available,
-            parent_tx.amount,
-            TX::Dispute(op),
-        ));
+    if config
+        .max_open_disputes
+        .is_some_and(|max| account.open_disputes >= max)
+    {
+        return Err(TXError::TooManyOpenDisputes(TX::Dispute(op)));
     }
-
-    account.available -= parent_tx.amount;
-    account.held += parent_tx.amount;
+    // A partial dispute holds only its own amount.
+    let amount = match op.amount {
+        Some(amount) if amount > parent_tx.amount => {
+            return Err(TXError::DisputeAmountTooLarge(
+                parent_tx.amount,
+                TX::Dispute(op),
+            ));
+        }
+        Some(amount) => amount,
+        None => parent_tx.amount,
+    };
+    let (available, total) = match parent_tx.kind {
+        // `available` already excludes funds held by other open disputes.
+        TXKind::Deposit => {
+            if config.dispute_overdraw_policy == DisputeOverdrawPolicy::Strict
+                && account.available < amount
+            {
+                return Err(TXError::NotEnoughFunds(
+                    account.available,
+                    amount,
+                    TX::Dispute(op),
+                ));
+            }
+            (account.available.checked_sub(amount), Some(account.total))
+        }
+        // The withdrawn funds already left the account, they are held
+        // until the dispute is settled.
+        TXKind::Withdrawal => (
+            Some(account.available),
+            add_balance(account.total, amount, config),
+        ),
+    };
+    let (Some(available), Some(total), Some(held)) =
+        (available, total, add_balance(account.held, amount, config))
+    else {
+        return Err(TXError::BalanceOverflow(TX::Dispute(op)));
+    };
+    account.available = available;
+    account.total = total;
+    account.held = held;
+    account.open_disputes += 1;
     parent_tx.disputed = true;
-    return Ok(());
+    parent_tx.disputed_amount = op.amount.filter(|amount| *amount < parent_tx.amount);
+    Ok(())
 }
 
-pub fn resolve(
+pub fn resolve<A: AccountStore>(
     op: Resolve,
-    accounts: &mut Accounts,
+    accounts: &mut A,
     transactions: &mut Transactions,
+    config: &Config,
 ) -> Result<(), TXError> {
-    let parent_tx = match transactions.get_mut(&op.tx) {
+    let key = config.tx_key(op.client, op.tx);
+    let parent_tx = match transactions.get_mut(&key) {
         Some(tx) => tx,
         None => return Err(TXError::ParentTXNotFound(TX::Resolve(op))),
     };
-    let account = match accounts.get_mut(&op.client) {
+    if op.client != parent_tx.client {
+        return Err(TXError::ClientsDontMatch(parent_tx.client, TX::Resolve(op)));
+    }
+    let account = match accounts.account_mut(op.client) {
         Some(acc) => acc,
         None => return Err(TXError::AccountNotFound(TX::Resolve(op))),
     };
 
-    if op.client != parent_tx.client {
-        return Err(TXError::ClientsDontMatch(parent_tx.client, TX::Resolve(op)));
-    }
-    if account.locked {
+    if account.locked
+        && !config.resolve_when_locked
+        && config.locked_policy == LockedPolicy::BlockAll
+    {
         return Err(TXError::AccountLocked(TX::Resolve(op)));
     }
     if !parent_tx.disputed {
         return Err(TXError::ParentTXNotDisputed(TX::Resolve(op)));
     }
 
-    account.available += parent_tx.amount;
-    account.held -= parent_tx.amount;
-    transactions.remove(&op.tx);
-    return Ok(());
+    release_hold(account, parent_tx);
+    if config.redispute_resolved {
+        parent_tx.disputed = false;
+        parent_tx.disputed_amount = None;
+        parent_tx.disputed_at = None;
+    } else {
+        transactions.remove(&key);
+    }
+    Ok(())
+}
+
+/// Undoes a dispute's hold, leaving the balances as they were before the
+/// dispute.
+fn release_hold(account: &mut Account, state: &TXState) {
+    let amount = state.held_amount();
+    match state.kind {
+        TXKind::Deposit => account.available += amount,
+        TXKind::Withdrawal => account.total -= amount,
+    }
+    account.held -= amount;
+    account.open_disputes = account.open_disputes.saturating_sub(1);
 }
 
-pub fn chargeback(
+pub fn chargeback<A: AccountStore>(
     op: Chargeback,
-    accounts: &mut Accounts,
+    accounts: &mut A,
     transactions: &mut Transactions,
+    config: &Config,
 ) -> Result<(), TXError> {
-    let parent_tx = match transactions.get(&op.tx) {
+    let key = config.tx_key(op.client, op.tx);
+    let parent_tx = match transactions.get(&key) {
         Some(tx) => tx,
         None => return Err(TXError::ParentTXNotFound(TX::Chargeback(op))),
     };
-    let account = match accounts.get_mut(&op.client) {
-        Some(acc) => acc,
-        None => return Err(TXError::AccountNotFound(TX::Chargeback(op))),
-    };
-
     if op.client != parent_tx.client {
         return Err(TXError::ClientsDontMatch(
             parent_tx.client,
             TX::Chargeback(op),
         ));
     }
-    if account.locked {
+    let account = match accounts.account_mut(op.client) {
+        Some(acc) => acc,
+        None => return Err(TXError::AccountNotFound(TX::Chargeback(op))),
+    };
+
+    if account.locked && config.locked_policy == LockedPolicy::BlockAll {
         return Err(TXError::AccountLocked(TX::Chargeback(op)));
     }
     if !parent_tx.disputed {
         return Err(TXError::ParentTXNotDisputed(TX::Chargeback(op)));
     }
 
-    account.held -= parent_tx.amount;
-    account.total -= parent_tx.amount;
+    let amount = parent_tx.held_amount();
+    if parent_tx.kind == TXKind::Withdrawal {
+        // Reversing a withdrawal hands the held funds back to the client.
+        account.held -= amount;
+        account.available += amount;
+        account.open_disputes = account.open_disputes.saturating_sub(1);
+        account.locked = true;
+        account.tx_ids.remove(&op.tx);
+        transactions.remove(&key);
+        return Ok(());
+    }
+
+    let mut write_off = Money::ZERO;
+    if let Some(floor) = config.total_floor {
+        let Some(shortfall) = account
+            .total
+            .checked_sub(amount)
+            .and_then(|total| floor.checked_sub(total))
+        else {
+            return Err(TXError::BalanceOverflow(TX::Chargeback(op)));
+        };
+        if shortfall > Money::ZERO {
+            match config.total_floor_policy {
+                TotalFloorPolicy::Reject => {
+                    return Err(TXError::TotalBelowFloor(TX::Chargeback(op)))
+                }
+                TotalFloorPolicy::Clamp => write_off = shortfall,
+            }
+        }
+    }
+
+    account.held -= amount;
+    account.available += write_off;
+    account.total -= amount - write_off;
+    account.open_disputes = account.open_disputes.saturating_sub(1);
     account.locked = true;
-    transactions.remove(&op.tx);
-    return Ok(());
+    account.tx_ids.remove(&op.tx);
+    transactions.remove(&key);
+    Ok(())
+}
+
+/// Admin unlock of a locked account. Returns false if the client has no
+/// account.
+pub fn unlock<A: AccountStore>(
+    client: u16,
+    accounts: &mut A,
+    transactions: &mut Transactions,
+    config: &Config,
+) -> bool {
+    let account = match accounts.account_mut(client) {
+        Some(acc) => acc,
+        None => return false,
+    };
+    account.locked = false;
+    if config.unlock_resolves_disputes {
+        transactions.retain(|_, state| {
+            if state.client != client || !state.disputed {
+                return true;
+            }
+            release_hold(account, state);
+            state.disputed = false;
+            state.disputed_amount = None;
+            state.disputed_at = None;
+            config.redispute_resolved
+        });
+    }
+    true
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashMap;
+    use std::collections::{BTreeSet, HashSet};
 
     #[test]
     fn test_deposit() {
-        let mut accounts = HashMap::new();
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
         let op = Deposit {
             client: 1,
             tx: 1,
-            amount: 1.0,
+            amount: Money::from(1.0),
         };
-        deposit(op, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 1.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 1.0);
-        assert_eq!(transactions.get(&1).unwrap().amount, 1.0);
+        deposit(op, &mut accounts, &mut transactions, &Config::default()).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 1.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1.0);
+        assert_eq!(
+            transactions.get(&1.into()).unwrap().amount,
+            Money::from(1.0)
+        );
     }
 
     #[test]
-    fn test_withdraw() {
-        let mut accounts = HashMap::new();
+    fn test_deposit_existing_account() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Withdrawal {
+        accounts.insert(
+            1,
+            Account {
+                available: Money::from(1.0),
+                held: Money::from(2.0),
+                total: Money::from(3.0),
+                locked: false,
+                deposited: Money::from(3.0),
+                ..Default::default()
+            },
+        );
+        for (client, tx) in [(1, 1), (2, 2), (1, 3)] {
+            deposit(
+                Deposit {
+                    client,
+                    tx,
+                    amount: Money::from(1.5),
+                },
+                &mut accounts,
+                &mut transactions,
+                &Config::default(),
+            )
+            .unwrap();
+        }
+
+        let existing = accounts.get(&1).unwrap();
+        assert_eq!(
+            (
+                existing.available,
+                existing.held,
+                existing.total,
+                existing.deposited
+            ),
+            (
+                Money::from(4.0),
+                Money::from(2.0),
+                Money::from(6.0),
+                Money::from(6.0)
+            )
+        );
+        let created = accounts.get(&2).unwrap();
+        assert_eq!(
+            (
+                created.available,
+                created.held,
+                created.total,
+                created.deposited
+            ),
+            (
+                Money::from(1.5),
+                Money::ZERO,
+                Money::from(1.5),
+                Money::from(1.5)
+            )
+        );
+        assert!(!created.locked);
+    }
+
+    #[test]
+    fn test_balance_overflow() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            max_balance: Some(Money::from(1000.0)),
+            ..Default::default()
+        };
+        let deposit_op = |tx, amount: f64| Deposit {
             client: 1,
-            tx: 1,
-            amount: 1.0,
+            tx,
+            amount: amount.into(),
         };
         deposit(
-            Deposit {
-                client: 1,
-                tx: 1,
-                amount: 1.0,
-            },
+            deposit_op(1, 999.0),
             &mut accounts,
             &mut transactions,
+            &config,
         )
         .unwrap();
-        withdraw(op, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 0.0);
-        assert_eq!(transactions.get(&1).unwrap().amount, 1.0);
-    }
-
-    #[test]
-    fn test_dispute() {
-        let mut accounts = HashMap::new();
-        let mut transactions = HashMap::new();
-        let op = Dispute { client: 1, tx: 1 };
+        let op = deposit_op(2, 1.5);
+        assert_eq!(
+            deposit(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::BalanceOverflow(TX::Deposit(op)))
+        );
         deposit(
-            Deposit {
-                client: 1,
-                tx: 1,
-                amount: 1.0,
-            },
+            deposit_op(3, 1.0),
             &mut accounts,
             &mut transactions,
+            &config,
         )
         .unwrap();
-        dispute(op, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().held, 1.0);
-        assert_eq!(transactions.get(&1).unwrap().disputed, true);
-    }
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1000.0);
 
-    #[test]
-    fn test_resolve() {
-        let mut accounts = HashMap::new();
-        let mut transactions = HashMap::new();
-        let op = Resolve { client: 1, tx: 1 };
-        deposit(
-            Deposit {
+        // Disputing a withdrawal holds its funds on top of the total.
+        withdraw(
+            Withdrawal {
                 client: 1,
-                tx: 1,
-                amount: 1.0,
+                tx: 4,
+                amount: Money::from(1.0),
+                destination: None,
             },
             &mut accounts,
             &mut transactions,
+            &config,
         )
         .unwrap();
-        dispute(
-            Dispute { client: 1, tx: 1 },
+        deposit(
+            deposit_op(5, 1.0),
             &mut accounts,
             &mut transactions,
+            &config,
         )
         .unwrap();
-        resolve(op, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().available, 1.0);
-        assert_eq!(accounts.get(&1).unwrap().held, 0.0);
-        assert_eq!(transactions.get(&1), None);
+        let op = Dispute {
+            client: 1,
+            tx: 4,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::BalanceOverflow(TX::Dispute(op)))
+        );
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.total, 1000.0);
+        assert_balance_eq!(account.held, 0.0);
+        assert!(!transactions.get(&4.into()).unwrap().disputed);
+
+        // Without a maximum, the integer range still bounds the balances.
+        let config = Config::default();
+        let op = Deposit {
+            client: 2,
+            tx: 6,
+            amount: Money::from_raw(i64::MAX - 1),
+        };
+        deposit(op, &mut accounts, &mut transactions, &config).unwrap();
+        let op = Deposit {
+            client: 2,
+            tx: 7,
+            amount: Money::from_raw(2),
+        };
+        assert_eq!(
+            deposit(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::BalanceOverflow(TX::Deposit(op)))
+        );
+        assert_eq!(
+            accounts.get(&2).unwrap().total,
+            Money::from_raw(i64::MAX - 1)
+        );
     }
 
     #[test]
-    fn test_chargeback() {
-        let mut accounts = HashMap::new();
+    fn test_deposit_limit_seeded() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Chargeback { client: 1, tx: 1 };
+        let config = Config {
+            deposit_limit: Some(Money::from(1000.0)),
+            ..Default::default()
+        };
+        let seed = Account {
+            available: Money::from(5000.0),
+            total: Money::from(5000.0),
+            deposited: Money::from(5000.0),
+            ..Default::default()
+        };
+        accounts.insert(
+            1,
+            Account {
+                seeded: true,
+                ..seed.clone()
+            },
+        );
+        accounts.insert(2, seed);
+
         deposit(
             Deposit {
                 client: 1,
                 tx: 1,
-                amount: 1.0,
+                amount: Money::from(500.0),
             },
             &mut accounts,
             &mut transactions,
+            &config,
         )
         .unwrap();
-        dispute(
-            Dispute { client: 1, tx: 1 },
-            &mut accounts,
-            &mut transactions,
-        )
-        .unwrap();
-        chargeback(op, &mut accounts, &mut transactions).unwrap();
-        assert_eq!(accounts.get(&1).unwrap().held, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().total, 0.0);
-        assert_eq!(accounts.get(&1).unwrap().locked, true);
-        assert_eq!(transactions.get(&1), None);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 5500.0);
+
+        let op = Deposit {
+            client: 2,
+            tx: 2,
+            amount: Money::from(500.0),
+        };
+        assert_eq!(
+            deposit(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::DepositLimitExceeded(TX::Deposit(op)))
+        );
     }
 
     #[test]
-    fn test_deposit_locked_account() {
-        let mut accounts = HashMap::new();
+    fn test_deposit_limit() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
+        let config = Config {
+            deposit_limit: Some(Money::from(1000.0)),
+            ..Default::default()
+        };
+        for (tx, amount) in [(1, 400.0), (2, 500.0)] {
+            deposit(
+                Deposit {
+                    client: 1,
+                    tx,
+                    amount: amount.into(),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+
         let op = Deposit {
             client: 1,
-            tx: 1,
-            amount: 1.0,
+            tx: 3,
+            amount: Money::from(200.0),
         };
-        accounts.insert(
-            1,
-            Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: true,
-            },
-        );
         assert_eq!(
-            deposit(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::AccountLocked(TX::Deposit(op)))
+            deposit(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::DepositLimitExceeded(TX::Deposit(op)))
         );
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 900.0);
+        assert!(!transactions.contains_key(&3.into()));
+
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 4,
+                amount: Money::from(100.0),
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1000.0);
+        assert_eq!(accounts.get(&1).unwrap().deposited, Money::from(1000.0));
     }
 
     #[test]
-    fn test_withdraw_not_enough_funds() {
-        let mut accounts = HashMap::new();
+    fn test_withdraw() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
         let op = Withdrawal {
             client: 1,
-            tx: 1,
-            amount: 1.0,
+            tx: 2,
+            amount: Money::from(1.0),
+            destination: None,
         };
-        accounts.insert(
-            1,
-            Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: false,
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(1.0),
             },
-        );
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        withdraw(op, &mut accounts, &mut transactions, &Config::default()).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.0);
         assert_eq!(
-            withdraw(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::NotEnoughFunds(0.0, op.amount, TX::Withdrawal(op)))
+            transactions.get(&2.into()).unwrap().amount,
+            Money::from(1.0)
         );
     }
 
     #[test]
-    fn test_dispute_parent_tx_not_found() {
-        let mut accounts = HashMap::new();
+    fn test_redispute_resolved() {
+        for redispute_resolved in [false, true] {
+            let mut accounts = Accounts::new();
+            let mut transactions = HashMap::new();
+            let config = Config {
+                redispute_resolved,
+                ..Default::default()
+            };
+            deposit(
+                Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: Money::from(5.0),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+            let op = Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            };
+            dispute(op.clone(), &mut accounts, &mut transactions, &config).unwrap();
+            resolve(
+                Resolve { client: 1, tx: 1 },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+
+            let result = dispute(op.clone(), &mut accounts, &mut transactions, &config);
+            let account = accounts.get(&1).unwrap();
+            if redispute_resolved {
+                assert_eq!(result, Ok(()));
+                assert_balance_eq!(account.available, 0.0);
+                assert_balance_eq!(account.held, 5.0);
+                assert!(transactions.get(&1.into()).unwrap().disputed);
+            } else {
+                assert_eq!(result, Err(TXError::ParentTXNotFound(TX::Dispute(op))));
+                assert_balance_eq!(account.available, 5.0);
+                assert_balance_eq!(account.held, 0.0);
+            }
+            assert_balance_eq!(account.total, 5.0);
+        }
+    }
+
+    #[test]
+    fn test_on_account_created() {
+        thread_local! {
+            static CREATED: std::cell::RefCell<Vec<(u16, Money)>> = Default::default();
+        }
+        fn record(client: u16, account: &Account) {
+            CREATED.with(|created| created.borrow_mut().push((client, account.total)));
+        }
+
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Dispute { client: 1, tx: 1 };
+        let config = Config {
+            on_account_created: Some(record),
+            ..Default::default()
+        };
+        for (tx, client) in [(1, 1), (2, 2), (3, 1), (4, 2), (5, 1)] {
+            deposit(
+                Deposit {
+                    client,
+                    tx,
+                    amount: Money::from(tx as f64),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        withdraw(
+            Withdrawal {
+                client: 3,
+                tx: 6,
+                amount: Money::from(1.0),
+                destination: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap_err();
+
         assert_eq!(
-            dispute(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::ParentTXNotFound(TX::Dispute(op)))
+            CREATED.with(|created| created.take()),
+            vec![(1, Money::from(1.0)), (2, Money::from(2.0))]
         );
     }
 
     #[test]
-    fn test_dispute_account_not_found() {
-        let mut accounts = HashMap::new();
+    fn test_dispute_flow_never_creates_accounts() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Dispute { client: 1, tx: 1 };
+        let config = Config::default();
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(1.0),
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        // A stored transaction whose account is gone, e.g. from a
+        // hand-edited snapshot.
         transactions.insert(
-            1,
+            2.into(),
             TXState {
-                client: 1,
-                amount: 1.0,
-                disputed: false,
+                kind: TXKind::Deposit,
+                client: 2,
+                amount: Money::from(1.0),
+                disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
+
         assert_eq!(
-            dispute(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::AccountNotFound(TX::Dispute(op)))
+            dispute(
+                Dispute {
+                    client: 3,
+                    tx: 9,
+                    amount: None
+                },
+                &mut accounts,
+                &mut transactions,
+                &config
+            )
+            .unwrap_err()
+            .kind(),
+            "parent_tx_not_found"
         );
+        let results = [
+            dispute(
+                Dispute {
+                    client: 2,
+                    tx: 2,
+                    amount: None,
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            ),
+            resolve(
+                Resolve { client: 2, tx: 2 },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            ),
+            chargeback(
+                Chargeback { client: 2, tx: 2 },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            ),
+        ];
+        for result in results {
+            assert_eq!(result.unwrap_err().kind(), "account_not_found");
+        }
+        assert_eq!(accounts.len(), 1);
+        assert!(!accounts.contains_key(&2));
+        assert!(!accounts.contains_key(&3));
     }
 
     #[test]
-    fn test_dispute_account_locked() {
-        let mut accounts = HashMap::new();
+    fn test_duplicate_transaction() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Dispute { client: 1, tx: 1 };
-        transactions.insert(
-            1,
-            TXState {
-                client: 1,
-                amount: 1.0,
-                disputed: false,
-            },
+        let config = Config::default();
+        let op = Deposit {
+            client: 1,
+            tx: 1,
+            amount: Money::from(1.0),
+        };
+        deposit(op.clone(), &mut accounts, &mut transactions, &config).unwrap();
+
+        let second = Deposit {
+            amount: Money::from(5.0),
+            ..op
+        };
+        assert_eq!(
+            deposit(second.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::DuplicateTransaction(TX::Deposit(second)))
         );
-        accounts.insert(
-            1,
-            Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: true,
-            },
+        let withdrawal = Withdrawal {
+            client: 1,
+            tx: 1,
+            amount: Money::from(0.5),
+            destination: None,
+        };
+        assert_eq!(
+            withdraw(
+                withdrawal.clone(),
+                &mut accounts,
+                &mut transactions,
+                &config
+            ),
+            Err(TXError::DuplicateTransaction(TX::Withdrawal(withdrawal)))
         );
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 1.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 1.0);
         assert_eq!(
-            dispute(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::AccountLocked(TX::Dispute(op)))
+            transactions.get(&1.into()).unwrap().amount,
+            Money::from(1.0)
         );
     }
 
     #[test]
-    fn test_dispute_parent_tx_already_disputed() {
-        let mut accounts = HashMap::new();
+    fn test_withdraw_destination() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Dispute { client: 1, tx: 1 };
-        accounts.insert(
-            1,
-            Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: false,
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(1.0),
             },
-        );
-        transactions.insert(
-            1,
-            TXState {
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        withdraw(
+            Withdrawal {
                 client: 1,
-                amount: 1.0,
-                disputed: true,
+                tx: 2,
+                amount: Money::from(1.0),
+                destination: Some("ACME-001".to_string()),
             },
-        );
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
         assert_eq!(
-            dispute(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::ParentTXAlreadyDisputed(TX::Dispute(op)))
+            transactions.get(&2.into()).unwrap().destination,
+            Some("ACME-001".to_string())
         );
+        assert_eq!(transactions.get(&1.into()).unwrap().destination, None);
     }
 
     #[test]
-    fn test_dispute_not_enough_funds() {
-        let mut accounts = HashMap::new();
+    fn test_dispute() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Dispute { client: 1, tx: 1 };
-        accounts.insert(
-            1,
-            Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: false,
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(1.0),
+            },
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        dispute(op, &mut accounts, &mut transactions, &Config::default()).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 1.0);
+        assert!(transactions.get(&1.into()).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Resolve { client: 1, tx: 1 };
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(1.0),
+            },
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        resolve(op, &mut accounts, &mut transactions, &Config::default()).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 1.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_eq!(transactions.get(&1.into()), None);
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config::default();
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(10.0),
             },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+
+        let too_large = Dispute {
+            client: 1,
+            tx: 1,
+            amount: Some(Money::from(12.0)),
+        };
+        assert_eq!(
+            dispute(too_large.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::DisputeAmountTooLarge(
+                Money::from(10.0),
+                TX::Dispute(too_large)
+            ))
         );
-        transactions.insert(
-            1,
-            TXState {
+
+        let partial = Dispute {
+            client: 1,
+            tx: 1,
+            amount: Some(Money::from(4.0)),
+        };
+        dispute(partial, &mut accounts, &mut transactions, &config).unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.available, 6.0);
+        assert_balance_eq!(account.held, 4.0);
+        assert_balance_eq!(account.total, 10.0);
+        let state = transactions.get(&1.into()).unwrap();
+        assert_balance_eq!(state.amount, 10.0);
+        assert_eq!(state.disputed_amount, Some(Money::from(4.0)));
+
+        let op = Resolve { client: 1, tx: 1 };
+        resolve(op, &mut accounts, &mut transactions, &config).unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.available, 10.0);
+        assert_balance_eq!(account.held, 0.0);
+        assert_balance_eq!(account.total, 10.0);
+        assert_eq!(account.open_disputes, 0);
+    }
+
+    #[test]
+    fn test_partial_dispute_chargeback() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config::default();
+        deposit(
+            Deposit {
                 client: 1,
-                amount: 1.0,
-                disputed: false,
+                tx: 1,
+                amount: Money::from(10.0),
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        let partial = Dispute {
+            client: 1,
+            tx: 1,
+            amount: Some(Money::from(2.5)),
+        };
+        dispute(partial, &mut accounts, &mut transactions, &config).unwrap();
+        let op = Chargeback { client: 1, tx: 1 };
+        chargeback(op, &mut accounts, &mut transactions, &config).unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.available, 7.5);
+        assert_balance_eq!(account.held, 0.0);
+        assert_balance_eq!(account.total, 7.5);
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_chargeback() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Chargeback { client: 1, tx: 1 };
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(1.0),
+            },
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        chargeback(op, &mut accounts, &mut transactions, &Config::default()).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.0);
+        assert!(accounts.get(&1).unwrap().locked);
+        assert_eq!(transactions.get(&1.into()), None);
+    }
+
+    #[test]
+    fn test_deposit_locked_account() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Deposit {
+            client: 1,
+            tx: 1,
+            amount: Money::from(1.0),
+        };
+        accounts.insert(
+            1,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: true,
+                ..Default::default()
             },
         );
         assert_eq!(
-            dispute(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::NotEnoughFunds(0.0, 1.0, TX::Dispute(op)))
+            deposit(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountLocked(TX::Deposit(op)))
         );
     }
 
     #[test]
-    fn test_resolve_parent_tx_not_found() {
-        let mut accounts = HashMap::new();
+    fn test_withdraw_not_enough_funds() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Resolve { client: 1, tx: 1 };
+        let op = Withdrawal {
+            client: 1,
+            tx: 1,
+            amount: Money::from(1.0),
+            destination: None,
+        };
+        accounts.insert(
+            1,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: false,
+                ..Default::default()
+            },
+        );
         assert_eq!(
-            resolve(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::ParentTXNotFound(TX::Resolve(op)))
+            withdraw(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::NotEnoughFunds(
+                Money::ZERO,
+                op.amount,
+                TX::Withdrawal(op)
+            ))
         );
     }
 
     #[test]
-    fn test_resolve_account_not_found() {
-        let mut accounts = HashMap::new();
+    fn test_dispute_parent_tx_not_found() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Resolve { client: 1, tx: 1 };
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::ParentTXNotFound(TX::Dispute(op)))
+        );
+    }
+
+    #[test]
+    fn test_dispute_account_not_found() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
         transactions.insert(
-            1,
+            1.into(),
             TXState {
+                kind: TXKind::Deposit,
                 client: 1,
-                amount: 1.0,
-                disputed: true,
+                amount: Money::from(1.0),
+                disputed: false,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
         assert_eq!(
-            resolve(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::AccountNotFound(TX::Resolve(op)))
+            dispute(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountNotFound(TX::Dispute(op)))
         );
     }
 
     #[test]
-    fn test_resolve_account_locked() {
-        let mut accounts = HashMap::new();
+    fn test_dispute_account_locked() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Resolve { client: 1, tx: 1 };
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
         transactions.insert(
-            1,
+            1.into(),
             TXState {
+                kind: TXKind::Deposit,
                 client: 1,
-                amount: 1.0,
-                disputed: true,
+                amount: Money::from(1.0),
+                disputed: false,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
         accounts.insert(
             1,
             Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
                 locked: true,
+                ..Default::default()
             },
         );
         assert_eq!(
-            resolve(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::AccountLocked(TX::Resolve(op)))
+            dispute(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountLocked(TX::Dispute(op)))
         );
     }
 
     #[test]
-    fn test_resolve_parent_tx_not_disputed() {
-        let mut accounts = HashMap::new();
+    fn test_dispute_parent_tx_already_disputed() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Resolve { client: 1, tx: 1 };
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
         accounts.insert(
             1,
             Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
                 locked: false,
+                ..Default::default()
             },
         );
         transactions.insert(
+            1.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 1,
+                amount: Money::from(1.0),
+                disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+        assert_eq!(
+            dispute(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::ParentTXAlreadyDisputed(TX::Dispute(op)))
+        );
+    }
+
+    #[test]
+    fn test_dispute_not_enough_funds() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        accounts.insert(
             1,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: false,
+                ..Default::default()
+            },
+        );
+        transactions.insert(
+            1.into(),
             TXState {
+                kind: TXKind::Deposit,
                 client: 1,
-                amount: 1.0,
+                amount: Money::from(1.0),
                 disputed: false,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
         assert_eq!(
-            resolve(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::ParentTXNotDisputed(TX::Resolve(op)))
+            dispute(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::NotEnoughFunds(
+                Money::ZERO,
+                Money::from(1.0),
+                TX::Dispute(op)
+            ))
         );
     }
 
     #[test]
-    fn test_chargeback_parent_tx_not_found() {
-        let mut accounts = HashMap::new();
+    fn test_dispute_funds_exact() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Chargeback { client: 1, tx: 1 };
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        // 0.7 - 0.4 is just below 0.3 in floats, but exact in fixed point.
+        let available = Money::from(0.7) - Money::from(0.4);
+        accounts.insert(
+            1,
+            Account {
+                available,
+                held: Money::ZERO,
+                total: available,
+                locked: false,
+                ..Default::default()
+            },
+        );
+        transactions.insert(
+            1.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 1,
+                amount: Money::from(0.3),
+                disputed: false,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+
+        dispute(op, &mut accounts, &mut transactions, &Config::default()).unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.3);
+        assert!(transactions.get(&1.into()).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_resolve_parent_tx_not_found() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Resolve { client: 1, tx: 1 };
         assert_eq!(
-            chargeback(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::ParentTXNotFound(TX::Chargeback(op)))
+            resolve(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::ParentTXNotFound(TX::Resolve(op)))
         );
     }
 
     #[test]
-    fn test_chargeback_account_not_found() {
-        let mut accounts = HashMap::new();
+    fn test_resolve_account_not_found() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Chargeback { client: 1, tx: 1 };
+        let op = Resolve { client: 1, tx: 1 };
         transactions.insert(
-            1,
+            1.into(),
             TXState {
+                kind: TXKind::Deposit,
                 client: 1,
-                amount: 1.0,
+                amount: Money::from(1.0),
                 disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
         assert_eq!(
-            chargeback(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::AccountNotFound(TX::Chargeback(op)))
+            resolve(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountNotFound(TX::Resolve(op)))
         );
     }
 
     #[test]
-    fn test_chargeback_account_locked() {
-        let mut accounts = HashMap::new();
+    fn test_resolve_account_locked() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Chargeback { client: 1, tx: 1 };
+        let op = Resolve { client: 1, tx: 1 };
         transactions.insert(
-            1,
+            1.into(),
             TXState {
+                kind: TXKind::Deposit,
                 client: 1,
-                amount: 1.0,
+                amount: Money::from(1.0),
                 disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
         accounts.insert(
             1,
             Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
                 locked: true,
+                ..Default::default()
             },
         );
         assert_eq!(
-            chargeback(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::AccountLocked(TX::Chargeback(op)))
+            resolve(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountLocked(TX::Resolve(op)))
         );
     }
 
     #[test]
-    fn test_chargeback_parent_tx_not_disputed() {
-        let mut accounts = HashMap::new();
+    fn test_resolve_parent_tx_not_disputed() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
-        let op = Chargeback { client: 1, tx: 1 };
+        let op = Resolve { client: 1, tx: 1 };
         accounts.insert(
             1,
             Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
                 locked: false,
+                ..Default::default()
             },
         );
         transactions.insert(
-            1,
+            1.into(),
             TXState {
+                kind: TXKind::Deposit,
                 client: 1,
-                amount: 1.0,
+                amount: Money::from(1.0),
                 disputed: false,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
         assert_eq!(
-            chargeback(op.clone(), &mut accounts, &mut transactions),
-            Err(TXError::ParentTXNotDisputed(TX::Chargeback(op)))
+            resolve(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::ParentTXNotDisputed(TX::Resolve(op)))
         );
     }
 
     #[test]
-    fn test_chargeback_clients_dont_match() {
-        let mut accounts = HashMap::new();
+    fn test_chargeback_parent_tx_not_found() {
+        let mut accounts = Accounts::new();
         let mut transactions = HashMap::new();
         let op = Chargeback { client: 1, tx: 1 };
-        accounts.insert(
-            1,
-            Account {
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: false,
-            },
+        assert_eq!(
+            chargeback(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::ParentTXNotFound(TX::Chargeback(op)))
         );
+    }
+
+    #[test]
+    fn test_chargeback_account_not_found() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Chargeback { client: 1, tx: 1 };
         transactions.insert(
-            1,
+            1.into(),
             TXState {
-                client: 2,
-                amount: 1.0,
+                kind: TXKind::Deposit,
+                client: 1,
+                amount: Money::from(1.0),
                 disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
             },
         );
         assert_eq!(
-            chargeback(op.clone(), &mut accounts, &mut transactions),
+            chargeback(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountNotFound(TX::Chargeback(op)))
+        );
+    }
+
+    #[test]
+    fn test_chargeback_account_locked() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Chargeback { client: 1, tx: 1 };
+        transactions.insert(
+            1.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 1,
+                amount: Money::from(1.0),
+                disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+        accounts.insert(
+            1,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            chargeback(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::AccountLocked(TX::Chargeback(op)))
+        );
+    }
+
+    #[test]
+    fn test_locked_policy_allow_dispute_flow() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            locked_policy: LockedPolicy::AllowDisputeFlow,
+            ..Default::default()
+        };
+        for tx in [1, 2, 3] {
+            deposit(
+                Deposit {
+                    client: 1,
+                    tx,
+                    amount: Money::from(1.0),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        for tx in [1, 2] {
+            dispute(
+                Dispute {
+                    client: 1,
+                    tx,
+                    amount: None,
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        chargeback(
+            Chargeback { client: 1, tx: 1 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert!(accounts.get(&1).unwrap().locked);
+
+        let op = Deposit {
+            client: 1,
+            tx: 4,
+            amount: Money::from(1.0),
+        };
+        assert_eq!(
+            deposit(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::AccountLocked(TX::Deposit(op)))
+        );
+        let op = Withdrawal {
+            client: 1,
+            tx: 5,
+            amount: Money::from(1.0),
+            destination: None,
+        };
+        assert_eq!(
+            withdraw(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::AccountLocked(TX::Withdrawal(op)))
+        );
+
+        resolve(
+            Resolve { client: 1, tx: 2 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 3,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        chargeback(
+            Chargeback { client: 1, tx: 3 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.available, 1.0);
+        assert_balance_eq!(account.held, 0.0);
+        assert_balance_eq!(account.total, 1.0);
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_chargeback_parent_tx_not_disputed() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Chargeback { client: 1, tx: 1 };
+        accounts.insert(
+            1,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: false,
+                ..Default::default()
+            },
+        );
+        transactions.insert(
+            1.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 1,
+                amount: Money::from(1.0),
+                disputed: false,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+        assert_eq!(
+            chargeback(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
+            Err(TXError::ParentTXNotDisputed(TX::Chargeback(op)))
+        );
+    }
+
+    #[test]
+    fn test_chargeback_clients_dont_match() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let op = Chargeback { client: 1, tx: 1 };
+        accounts.insert(
+            1,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: false,
+                ..Default::default()
+            },
+        );
+        transactions.insert(
+            1.into(),
+            TXState {
+                kind: TXKind::Deposit,
+                client: 2,
+                amount: Money::from(1.0),
+                disputed: true,
+                disputed_amount: None,
+                disputed_at: None,
+                destination: None,
+            },
+        );
+        assert_eq!(
+            chargeback(
+                op.clone(),
+                &mut accounts,
+                &mut transactions,
+                &Config::default()
+            ),
             Err(TXError::ClientsDontMatch(2, TX::Chargeback(op)))
         );
     }
+
+    #[test]
+    fn test_dispute_clients_dont_match_before_funds() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+        let config = Config::default();
+
+        // Client 2 has no account and client 1 has no available funds, the
+        // client mismatch is reported either way.
+        let op = Dispute {
+            client: 2,
+            tx: 1,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::ClientsDontMatch(1, TX::Dispute(op)))
+        );
+
+        accounts.insert(
+            2,
+            Account {
+                available: Money::ZERO,
+                held: Money::ZERO,
+                total: Money::ZERO,
+                locked: false,
+                ..Default::default()
+            },
+        );
+        let op = Dispute {
+            client: 2,
+            tx: 1,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::ClientsDontMatch(1, TX::Dispute(op)))
+        );
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert!(!transactions.get(&1.into()).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_resolve_chargeback_clients_dont_match_before_account() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config::default();
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(5.0),
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+
+        // Client 2 has no account, the mismatch is reported rather than the
+        // missing account.
+        let op = Resolve { client: 2, tx: 1 };
+        assert_eq!(
+            resolve(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::ClientsDontMatch(1, TX::Resolve(op)))
+        );
+        let op = Chargeback { client: 2, tx: 1 };
+        assert_eq!(
+            chargeback(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::ClientsDontMatch(1, TX::Chargeback(op)))
+        );
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 5.0);
+        assert!(transactions.get(&1.into()).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_max_open_disputes() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            max_open_disputes: Some(2),
+            ..Default::default()
+        };
+        for tx in 1..=3 {
+            deposit(
+                Deposit {
+                    client: 1,
+                    tx,
+                    amount: Money::from(1.0),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        for tx in 1..=2 {
+            dispute(
+                Dispute {
+                    client: 1,
+                    tx,
+                    amount: None,
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        let op = Dispute {
+            client: 1,
+            tx: 3,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::TooManyOpenDisputes(TX::Dispute(op.clone())))
+        );
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 2.0);
+
+        resolve(
+            Resolve { client: 1, tx: 1 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        dispute(op, &mut accounts, &mut transactions, &config).unwrap();
+        assert_eq!(accounts.get(&1).unwrap().open_disputes, 2);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            track_provenance: true,
+            ..Default::default()
+        };
+        for tx in [1, 2] {
+            deposit(
+                Deposit {
+                    client: 1,
+                    tx,
+                    amount: Money::from(1.0),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        assert_eq!(accounts.get(&1).unwrap().tx_ids, BTreeSet::from([1, 2]));
+
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 2,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        chargeback(
+            Chargeback { client: 1, tx: 2 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(accounts.get(&1).unwrap().tx_ids, BTreeSet::from([1]));
+    }
+
+    #[test]
+    fn test_dispute_withdrawal() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config::default();
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+
+        let dispute_withdrawal = |accounts: &mut Accounts, transactions: &mut Transactions| {
+            dispute(
+                Dispute {
+                    client: 1,
+                    tx: 2,
+                    amount: None,
+                },
+                accounts,
+                transactions,
+                &config,
+            )
+            .unwrap();
+            let account = accounts.get(&1).unwrap();
+            assert_balance_eq!(account.available, 0.0);
+            assert_balance_eq!(account.held, 10.0);
+            assert_balance_eq!(account.total, 10.0);
+        };
+
+        dispute_withdrawal(&mut accounts, &mut transactions);
+        resolve(
+            Resolve { client: 1, tx: 2 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.available, 0.0);
+        assert_balance_eq!(account.held, 0.0);
+        assert_balance_eq!(account.total, 0.0);
+
+        let (mut accounts, mut transactions) = (Accounts::new(), HashMap::new());
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+        dispute_withdrawal(&mut accounts, &mut transactions);
+        chargeback(
+            Chargeback { client: 1, tx: 2 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        let account = accounts.get(&1).unwrap();
+        assert_balance_eq!(account.available, 10.0);
+        assert_balance_eq!(account.held, 0.0);
+        assert_balance_eq!(account.total, 10.0);
+        assert!(account.locked);
+    }
+
+    fn deposit_then_withdraw_all(accounts: &mut Accounts, transactions: &mut Transactions) {
+        deposit(
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: Money::from(10.0),
+            },
+            accounts,
+            transactions,
+            &Config::default(),
+        )
+        .unwrap();
+        withdraw(
+            Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Money::from(10.0),
+                destination: None,
+            },
+            accounts,
+            transactions,
+            &Config::default(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_dispute_withdrawn_deposit_strict() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Strict,
+            ..Default::default()
+        };
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::NotEnoughFunds(
+                Money::ZERO,
+                Money::from(10.0),
+                TX::Dispute(op)
+            ))
+        );
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.0);
+        assert!(!transactions.get(&1.into()).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_second_dispute_holds_remaining_available() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config::default();
+        for (tx, amount) in [(1, 10.0), (2, 5.0)] {
+            deposit(
+                Deposit {
+                    client: 1,
+                    tx,
+                    amount: Money::from(amount),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 5.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 10.0);
+
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 2,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 15.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 15.0);
+    }
+
+    #[test]
+    fn test_second_dispute_over_remaining_available() {
+        for policy in [
+            DisputeOverdrawPolicy::Strict,
+            DisputeOverdrawPolicy::Permissive,
+        ] {
+            let mut accounts = Accounts::new();
+            let mut transactions = HashMap::new();
+            let config = Config {
+                dispute_overdraw_policy: policy,
+                ..Default::default()
+            };
+            for (tx, amount) in [(1, 10.0), (2, 5.0)] {
+                deposit(
+                    Deposit {
+                        client: 1,
+                        tx,
+                        amount: Money::from(amount),
+                    },
+                    &mut accounts,
+                    &mut transactions,
+                    &config,
+                )
+                .unwrap();
+            }
+            withdraw(
+                Withdrawal {
+                    client: 1,
+                    tx: 3,
+                    amount: Money::from(1.0),
+                    destination: None,
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+
+            let first = Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            };
+            dispute(first, &mut accounts, &mut transactions, &config).unwrap();
+            assert_balance_eq!(accounts.get(&1).unwrap().available, 4.0);
+            assert_balance_eq!(accounts.get(&1).unwrap().held, 10.0);
+
+            // Only 4.0 is left available, the 10.0 held by the first
+            // dispute can't cover the second one.
+            let second = Dispute {
+                client: 1,
+                tx: 2,
+                amount: None,
+            };
+            let result = dispute(second.clone(), &mut accounts, &mut transactions, &config);
+            let account = accounts.get(&1).unwrap();
+            match policy {
+                DisputeOverdrawPolicy::Strict => {
+                    assert_eq!(
+                        result,
+                        Err(TXError::NotEnoughFunds(
+                            Money::from(4.0),
+                            Money::from(5.0),
+                            TX::Dispute(second)
+                        ))
+                    );
+                    assert_balance_eq!(account.available, 4.0);
+                    assert_balance_eq!(account.held, 10.0);
+                }
+                DisputeOverdrawPolicy::Permissive => {
+                    assert_eq!(result, Ok(()));
+                    assert_balance_eq!(account.available, -1.0);
+                    assert_balance_eq!(account.held, 15.0);
+                }
+            }
+            assert_balance_eq!(account.total, 14.0);
+        }
+    }
+
+    #[test]
+    fn test_dispute_withdrawn_deposit_permissive() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Permissive,
+            ..Default::default()
+        };
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, -10.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 10.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.0);
+        assert!(transactions.get(&1.into()).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_dispute_withdrawn_deposit_permissive_resolve() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Permissive,
+            ..Default::default()
+        };
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        resolve(
+            Resolve { client: 1, tx: 1 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.0);
+        assert!(!accounts.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_dispute_withdrawn_deposit_permissive_chargeback() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Permissive,
+            ..Default::default()
+        };
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        chargeback(
+            Chargeback { client: 1, tx: 1 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, -10.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, -10.0);
+        assert!(accounts.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_chargeback_below_total_floor_reject() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Permissive,
+            total_floor: Some(Money::ZERO),
+            total_floor_policy: TotalFloorPolicy::Reject,
+            ..Default::default()
+        };
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+
+        let op = Chargeback { client: 1, tx: 1 };
+        assert_eq!(
+            chargeback(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::TotalBelowFloor(TX::Chargeback(op)))
+        );
+        assert_balance_eq!(accounts.get(&1).unwrap().available, -10.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 10.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.0);
+        assert!(!accounts.get(&1).unwrap().locked);
+        assert!(transactions.get(&1.into()).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_chargeback_below_total_floor_clamp() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Permissive,
+            total_floor: Some(Money::ZERO),
+            total_floor_policy: TotalFloorPolicy::Clamp,
+            ..Default::default()
+        };
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+
+        chargeback(
+            Chargeback { client: 1, tx: 1 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().available, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert_balance_eq!(accounts.get(&1).unwrap().total, 0.0);
+        assert!(accounts.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_chargeback_total_floor_overflow() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            dispute_overdraw_policy: DisputeOverdrawPolicy::Permissive,
+            total_floor: Some(Money::from_raw(i64::MAX)),
+            total_floor_policy: TotalFloorPolicy::Clamp,
+            ..Default::default()
+        };
+        deposit_then_withdraw_all(&mut accounts, &mut transactions);
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+
+        let op = Chargeback { client: 1, tx: 1 };
+        assert_eq!(
+            chargeback(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::BalanceOverflow(TX::Chargeback(op)))
+        );
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 10.0);
+        assert!(!accounts.get(&1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_per_client_tx_ids() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            per_client_tx_ids: true,
+            ..Default::default()
+        };
+        for (client, amount) in [(1, 10.0), (2, 4.0)] {
+            deposit(
+                Deposit {
+                    client,
+                    tx: 7,
+                    amount: amount.into(),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        assert_eq!(transactions.len(), 2);
+
+        dispute(
+            Dispute {
+                client: 1,
+                tx: 7,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 10.0);
+        assert_balance_eq!(accounts.get(&2).unwrap().held, 0.0);
+
+        dispute(
+            Dispute {
+                client: 2,
+                tx: 7,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        chargeback(
+            Chargeback { client: 2, tx: 7 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 10.0);
+        assert!(!accounts.get(&1).unwrap().locked);
+        assert_balance_eq!(accounts.get(&2).unwrap().total, 0.0);
+        assert!(accounts.get(&2).unwrap().locked);
+        assert!(transactions.get(&config.tx_key(1, 7)).unwrap().disputed);
+        assert!(!transactions.contains_key(&config.tx_key(2, 7)));
+
+        let op = Dispute {
+            client: 3,
+            tx: 7,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::ParentTXNotFound(TX::Dispute(op)))
+        );
+    }
+
+    #[test]
+    fn test_dispute_frozen_client() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            disputes_frozen: HashSet::from([1]),
+            ..Default::default()
+        };
+        for (client, tx) in [(1, 1), (2, 2)] {
+            deposit(
+                Deposit {
+                    client,
+                    tx,
+                    amount: Money::from(5.0),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+
+        let op = Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        assert_eq!(
+            dispute(op.clone(), &mut accounts, &mut transactions, &config),
+            Err(TXError::DisputesFrozen(TX::Dispute(op)))
+        );
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 0.0);
+        assert!(!transactions.get(&1.into()).unwrap().disputed);
+
+        dispute(
+            Dispute {
+                client: 2,
+                tx: 2,
+                amount: None,
+            },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert_balance_eq!(accounts.get(&2).unwrap().held, 5.0);
+    }
+
+    #[test]
+    fn test_unlock_resolves_disputes() {
+        let mut accounts = Accounts::new();
+        let mut transactions = HashMap::new();
+        let config = Config {
+            unlock_resolves_disputes: true,
+            ..Default::default()
+        };
+        for tx in [1, 2] {
+            deposit(
+                Deposit {
+                    client: 1,
+                    tx,
+                    amount: Money::from(5.0),
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+            dispute(
+                Dispute {
+                    client: 1,
+                    tx,
+                    amount: None,
+                },
+                &mut accounts,
+                &mut transactions,
+                &config,
+            )
+            .unwrap();
+        }
+        chargeback(
+            Chargeback { client: 1, tx: 1 },
+            &mut accounts,
+            &mut transactions,
+            &config,
+        )
+        .unwrap();
+        assert!(accounts.get(&1).unwrap().locked);
+        assert_balance_eq!(accounts.get(&1).unwrap().held, 5.0);
+
+        let mut kept = (accounts.clone(), transactions.clone());
+        assert!(unlock(1, &mut kept.0, &mut kept.1, &Config::default()));
+        assert!(!kept.0.get(&1).unwrap().locked);
+        assert_balance_eq!(kept.0.get(&1).unwrap().held, 5.0);
+
+        assert!(unlock(1, &mut accounts, &mut transactions, &config));
+        let account = accounts.get(&1).unwrap();
+        assert!(!account.locked);
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (Money::from(5.0), Money::ZERO, Money::from(5.0))
+        );
+        assert!(transactions.is_empty());
+        assert!(!unlock(2, &mut accounts, &mut transactions, &config));
+    }
 }
@@ -1,122 +1,447 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
 use std::str::FromStr;
 
-use serde::{Deserialize, Serialize};
-use serde::de::{Error, MapAccess, Visitor};
+use serde::de::{Error, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::schema::SchemaVersion;
 
 #[derive(Debug, PartialEq)]
 pub struct Transaction {
-    pub typ: String,
+    pub typ: TXType,
     pub client: u16,
     pub tx: u32,
-    pub amount: Option<f64>,
+    pub amount: Option<Money>,
+    pub destination: Option<String>,
+    /// Currency code, only read with `SchemaVersion::V2`.
+    pub currency: Option<String>,
+}
+
+/// A documented CSV column: name, value type and description.
+pub type Column = (&'static str, &'static str, &'static str);
+
+impl Transaction {
+    pub const COLUMNS: &'static [Column] = &[
+        (
+            "type",
+            "string",
+            "deposit, withdrawal, dispute, resolve or chargeback (case-insensitive)",
+        ),
+        ("client", "u16", "client id"),
+        ("tx", "u32", "transaction id"),
+        (
+            "amount",
+            "decimal",
+            "positive amount with up to 4 decimals, for deposits and withdrawals, or partial disputes",
+        ),
+        (
+            "amount_major",
+            "u64",
+            "whole units of the amount, read with amount_minor instead of amount",
+        ),
+        (
+            "amount_minor",
+            "u16",
+            "ten-thousandths of the amount, 0 to 9999",
+        ),
+        (
+            "destination",
+            "string",
+            "optional external account of a withdrawal",
+        ),
+        (
+            "currency",
+            "string",
+            "ISO 4217 code of deposits and withdrawals, schema v2 only",
+        ),
+        (
+            "direction",
+            "string",
+            "credit or debit in place of the deposit or withdrawal type, schema v3 only",
+        ),
+        (
+            "institution",
+            "string",
+            "optional institution of the client, for --institution-report",
+        ),
+    ];
+
+    /// Builds a transaction from a JSON object with the same fields as the
+    /// CSV input. Fields may be strings or numbers and go through the same
+    /// validation as CSV rows.
+    pub fn from_json_value(value: &serde_json::Value) -> Result<Transaction, serde_json::Error> {
+        let value = match value {
+            serde_json::Value::Object(fields) => serde_json::Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, field)| {
+                        let field = match field {
+                            serde_json::Value::Number(n) => {
+                                serde_json::Value::String(n.to_string())
+                            }
+                            other => other.clone(),
+                        };
+                        (key.clone(), field)
+                    })
+                    .collect(),
+            ),
+            other => other.clone(),
+        };
+        Transaction::deserialize(value)
+    }
 }
 
+/// A transaction deserialized with the `SchemaVersion::V2` column layout.
+#[derive(Debug, PartialEq)]
+pub struct TransactionV2(pub Transaction);
+
+/// A transaction deserialized with the `SchemaVersion::V3` column layout.
+#[derive(Debug, PartialEq)]
+pub struct TransactionV3(pub Transaction);
+
 impl<'de> Deserialize<'de> for Transaction {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        struct TransactionVisitor;
+        deserializer.deserialize_map(TransactionVisitor {
+            schema: SchemaVersion::V1,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionV2 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_map(TransactionVisitor {
+                schema: SchemaVersion::V2,
+            })
+            .map(TransactionV2)
+    }
+}
 
-        impl<'de> Visitor<'de> for TransactionVisitor {
-            type Value = Transaction;
+impl<'de> Deserialize<'de> for TransactionV3 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_map(TransactionVisitor {
+                schema: SchemaVersion::V3,
+            })
+            .map(TransactionV3)
+    }
+}
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a map representing a Transaction")
-            }
+struct TransactionVisitor {
+    schema: SchemaVersion,
+}
 
-            fn visit_map<V>(self, mut map: V) -> Result<Transaction, V::Error>
-            where
-                V: MapAccess<'de>,
+impl<'de> Visitor<'de> for TransactionVisitor {
+    type Value = Transaction;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map representing a Transaction")
+    }
+
+    fn visit_map<V>(self, mut map: V) -> Result<Transaction, V::Error>
+    where
+        V: MapAccess<'de>,
+    {
+        let mut typ: Option<String> = None;
+        let mut client: Option<String> = None;
+        let mut tx: Option<String> = None;
+        let mut amount: Option<String> = None;
+        let mut amount_major: Option<String> = None;
+        let mut amount_minor: Option<String> = None;
+        let mut destination: Option<String> = None;
+        let mut currency: Option<String> = None;
+        let mut direction: Option<String> = None;
+        while let Some(key) = map.next_key::<String>()? {
+            // Spreadsheet exports may start the first header with a byte
+            // order mark, `trim` already drops a stray `\r`.
+            match key
+                .trim_start_matches('\u{feff}')
+                .trim()
+                .to_lowercase()
+                .as_str()
             {
-                let typ = map.next_value::<String>()?.trim().to_string();
-                let typ = match typ.to_lowercase().as_str() {
-                    s if s == TXType::Dispute.as_str() => Ok(s.to_string()),
-                    s if s == TXType::Resolve.as_str() => Ok(s.to_string()),
-                    s if s == TXType::Chargeback.as_str() => Ok(s.to_string()),
-                    s if s == TXType::Deposit.as_str() => Ok(s.to_string()),
-                    s if s == TXType::Withdrawal.as_str() => Ok(s.to_string()),
-                    _ => return Err(V::Error::custom("Invalid transaction type")),
-                }?;
-                let client = map
-                    .next_value::<String>()?
-                    .trim()
-                    .parse::<u16>()
-                    .map_err(V::Error::custom)?;
-                let tx = map
-                    .next_value::<String>()?
+                "type" => typ = map.next_value()?,
+                "client" => client = map.next_value()?,
+                "tx" => tx = map.next_value()?,
+                "amount" => amount = map.next_value()?,
+                "amount_major" => amount_major = map.next_value()?,
+                "amount_minor" => amount_minor = map.next_value()?,
+                "destination" => destination = map.next_value()?,
+                "currency" => currency = map.next_value()?,
+                "direction" => direction = map.next_value()?,
+                _ => {
+                    map.next_value::<Option<IgnoredAny>>()?;
+                }
+            }
+        }
+
+        let direction = match self.schema {
+            SchemaVersion::V3 => direction
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty()),
+            _ => None,
+        };
+        let typ = match direction.as_deref() {
+            Some(_) if typ.as_deref().is_some_and(|s| !s.trim().is_empty()) => {
+                return Err(V::Error::custom("Both type and direction given"))
+            }
+            Some("credit") => TXType::Deposit,
+            Some("debit") => TXType::Withdrawal,
+            Some(other) => return Err(V::Error::custom(format!("Invalid direction: {:?}", other))),
+            None => typ
+                .ok_or_else(|| V::Error::missing_field("type"))?
+                // Some dialects leave the quotes of a quoted field in place.
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .parse()
+                .map_err(V::Error::custom)?,
+        };
+        if self.schema == SchemaVersion::V3
+            && direction.is_none()
+            && matches!(typ, TXType::Deposit | TXType::Withdrawal)
+        {
+            return Err(V::Error::custom(
+                "Deposits and withdrawals take a direction in schema v3",
+            ));
+        }
+        let client = client
+            .ok_or_else(|| V::Error::missing_field("client"))?
+            .trim()
+            .parse::<u16>()
+            .map_err(V::Error::custom)?;
+        let tx = tx
+            .ok_or_else(|| V::Error::missing_field("tx"))?
+            .trim()
+            .parse::<u32>()
+            .map_err(V::Error::custom)?;
+        let amount = match (amount, amount_major, amount_minor) {
+            (Some(amount), None, None) => Some(amount),
+            (None, None, None) => None,
+            (None, major, minor) => Some(
+                major_minor_amount(major.as_deref(), minor.as_deref()).map_err(V::Error::custom)?,
+            ),
+            (Some(_), _, _) => {
+                return Err(V::Error::custom(
+                    "Both amount and amount_major/amount_minor given",
+                ))
+            }
+        };
+        let amount = match (amount, typ) {
+            (Some(s), TXType::Deposit | TXType::Withdrawal) => {
+                Some(validate_amount(&s).map_err(V::Error::custom)?)
+            }
+            // A dispute's amount is optional and disputes only that part.
+            // An empty or zero amount disputes the whole transaction.
+            (Some(s), TXType::Dispute) => match validate_amount(&s) {
+                Ok(amount) => Some(amount),
+                Err(AmountError::Zero(_)) => None,
+                Err(_) if s.trim().is_empty() => None,
+                Err(err) => return Err(V::Error::custom(err)),
+            },
+            (Some(s), _) => {
+                Money::from_str(&s).map_err(V::Error::custom)?;
+                None
+            }
+            (None, _) => None,
+        };
+        let destination = match typ {
+            TXType::Withdrawal => destination
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            _ => None,
+        };
+        let currency = match (self.schema, typ) {
+            (SchemaVersion::V2, TXType::Deposit | TXType::Withdrawal) => {
+                let currency = currency
+                    .ok_or_else(|| V::Error::missing_field("currency"))?
                     .trim()
-                    .parse::<u32>()
-                    .map_err(V::Error::custom)?;
-                let s: Option<String> = map.next_value()?;
-                let amount = if let Some(s) = s {
-                    let s = s.trim().to_string();
-                    let f = f64::from_str(&s).map_err(V::Error::custom)?;
-                    match typ.as_str() {
-                        "deposit" | "withdrawal" => {
-                            if f.is_normal() && f.is_sign_positive() && f >= 0.0001 {
-                                Some(truncate(f))
-                            } else {
-                                return Err(V::Error::custom(format!(
-                                    "Invalid amount value: {:?}",
-                                    f
-                                )));
-                            }
-                        }
-                        _ => None,
-                    }
-                } else {
-                    None
-                };
-
-                Ok(Transaction {
-                    typ,
-                    client,
-                    tx,
-                    amount,
-                })
+                    .to_uppercase();
+                if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+                    return Err(V::Error::custom(format!(
+                        "Invalid currency: {:?}",
+                        currency
+                    )));
+                }
+                Some(currency)
             }
+            _ => None,
+        };
+
+        Ok(Transaction {
+            typ,
+            client,
+            tx,
+            amount,
+            destination,
+            currency,
+        })
+    }
+}
+
+/// Why a deposit or withdrawal amount was rejected. Each variant holds the
+/// amount as written.
+#[derive(Debug, PartialEq)]
+pub enum AmountError {
+    Invalid(String),
+    Negative(String),
+    Zero(String),
+    /// Positive, but below the smallest representable amount.
+    TooSmall(String),
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountError::Invalid(s) => write!(f, "Invalid amount: {:?}", s),
+            AmountError::Negative(s) => write!(f, "Invalid amount {:?}: negative", s),
+            AmountError::Zero(s) => write!(f, "Invalid amount {:?}: zero", s),
+            AmountError::TooSmall(s) => write!(
+                f,
+                "Invalid amount {:?}: below the smallest amount of {}",
+                s,
+                Money::from_raw(1)
+            ),
         }
+    }
+}
+
+impl std::error::Error for AmountError {}
 
-        deserializer.deserialize_map(TransactionVisitor)
+/// Parses the amount of a deposit or withdrawal, which must be positive and
+/// at least `0.0001`.
+pub fn validate_amount(s: &str) -> Result<Money, AmountError> {
+    let s = s.trim();
+    let amount = Money::from_str(s).map_err(|_| AmountError::Invalid(s.to_string()))?;
+    let nonzero = s.chars().any(|c| matches!(c, '1'..='9'));
+    if s.starts_with('-') && nonzero {
+        Err(AmountError::Negative(s.to_string()))
+    } else if amount > Money::ZERO {
+        Ok(amount)
+    } else if nonzero {
+        Err(AmountError::TooSmall(s.to_string()))
+    } else {
+        Err(AmountError::Zero(s.to_string()))
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+/// The decimal amount written as `amount_major` and `amount_minor` columns,
+/// e.g. `12.0050` for 12 and 50. A missing column counts as 0.
+pub fn major_minor_amount(major: Option<&str>, minor: Option<&str>) -> Result<String, String> {
+    Ok(format!(
+        "{}.{}",
+        major.unwrap_or("0").trim(),
+        minor_digits(minor.unwrap_or("0"))?
+    ))
+}
+
+/// The fraction digits of an `amount_minor` column holding ten-thousandths,
+/// e.g. `0050` for 50.
+fn minor_digits(minor: &str) -> Result<String, String> {
+    let minor = minor.trim();
+    match minor.parse::<u16>() {
+        Ok(units) if (units as i64) < UNIT && minor.chars().all(|c| c.is_ascii_digit()) => {
+            Ok(format!("{:0width$}", units, width = SCALE as usize))
+        }
+        _ => Err(format!("Invalid amount_minor: {:?}", minor)),
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Account {
-    pub available: f64,
-    pub held: f64,
-    pub total: f64,
+    pub available: Money,
+    pub held: Money,
+    pub total: Money,
     pub locked: bool,
+    /// Cumulative amount deposited, checked against `Config::deposit_limit`.
+    #[serde(default)]
+    pub deposited: Money,
+    /// Cumulative amount withdrawn.
+    #[serde(default)]
+    pub withdrawn: Money,
+    /// Deposits and withdrawals applied to the account.
+    #[serde(default)]
+    pub deposit_count: u64,
+    #[serde(default)]
+    pub withdrawal_count: u64,
+    /// The opening balance was seeded (e.g. from a snapshot) rather than
+    /// deposited. Seeded accounts are exempt from `Config::deposit_limit`.
+    #[serde(default)]
+    pub seeded: bool,
+    /// Transactions of the client currently under dispute.
+    #[serde(default)]
+    pub open_disputes: u32,
+    /// Deposits and withdrawals making up the current balance, only tracked
+    /// with `Config::track_provenance`. Charged back transactions are
+    /// removed.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tx_ids: BTreeSet<u32>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TXKind {
+    #[default]
+    Deposit,
+    Withdrawal,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct TXState {
+    #[serde(default)]
+    pub kind: TXKind,
     pub client: u16,
-    pub amount: f64,
+    pub amount: Money,
     pub disputed: bool,
+    /// Part of `amount` held by an open partial dispute. The whole amount
+    /// is held when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disputed_amount: Option<Money>,
+    /// Position in the engine's stream of the dispute currently open. Only
+    /// meaningful within one run, so it isn't saved in snapshots.
+    #[serde(skip)]
+    pub disputed_at: Option<u64>,
+    pub destination: Option<String>,
+}
+
+impl TXState {
+    /// Amount held while the transaction is disputed.
+    pub fn held_amount(&self) -> Money {
+        self.disputed_amount.unwrap_or(self.amount)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Deposit {
     pub client: u16,
     pub tx: u32,
-    pub amount: f64,
+    pub amount: Money,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Withdrawal {
     pub client: u16,
     pub tx: u32,
-    pub amount: f64,
+    pub amount: Money,
+    pub destination: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Dispute {
     pub client: u16,
     pub tx: u32,
+    /// Disputes only this part of the parent transaction's amount.
+    pub amount: Option<Money>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -131,7 +456,7 @@ pub struct Chargeback {
     pub tx: u32,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum TX {
     Deposit(Deposit),
     Withdrawal(Withdrawal),
@@ -140,7 +465,7 @@ pub enum TX {
     Chargeback(Chargeback),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum TXType {
     Deposit,
     Withdrawal,
@@ -150,6 +475,14 @@ pub enum TXType {
 }
 
 impl TXType {
+    pub const ALL: [TXType; 5] = [
+        TXType::Deposit,
+        TXType::Withdrawal,
+        TXType::Dispute,
+        TXType::Resolve,
+        TXType::Chargeback,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             TXType::Deposit => "deposit",
@@ -161,28 +494,135 @@ impl TXType {
     }
 }
 
+/// Parses a type name such as ` Deposit`, ignoring case and surrounding
+/// whitespace.
+impl FromStr for TXType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        TXType::ALL
+            .into_iter()
+            .find(|typ| typ.as_str() == name)
+            .ok_or_else(|| "Invalid transaction type".to_string())
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum TXBuildError {
     InvalidTransaction,
+    /// A deposit or withdrawal without an amount.
+    MissingAmount,
+    /// The amount is positive but below the smallest amount of the
+    /// configured precision.
+    AmountBelowPrecision(Money),
+    /// The amount and the configured minimum it is below.
+    AmountBelowMinimum(Money, Money),
+}
+
+impl TXBuildError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TXBuildError::InvalidTransaction => "invalid_transaction",
+            TXBuildError::MissingAmount => "missing_amount",
+            TXBuildError::AmountBelowPrecision(_) => "amount_below_precision",
+            TXBuildError::AmountBelowMinimum(..) => "amount_below_minimum",
+        }
+    }
 }
 
 impl fmt::Display for TXBuildError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             TXBuildError::InvalidTransaction => write!(f, "ValidationError: Invalid Transaction"),
+            TXBuildError::MissingAmount => write!(f, "ValidationError: Missing amount"),
+            TXBuildError::AmountBelowPrecision(amount) => write!(
+                f,
+                "ValidationError: Amount {} is below the configured precision",
+                amount
+            ),
+            TXBuildError::AmountBelowMinimum(amount, minimum) => write!(
+                f,
+                "ValidationError: Amount {} is below the configured minimum of {}",
+                amount, minimum
+            ),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl std::error::Error for TXBuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TXError {
     AccountLocked(TX),
     AccountNotFound(TX),
     ClientsDontMatch(u16, TX),
-    NotEnoughFunds(f64, f64, TX),
+    DepositLimitExceeded(TX),
+    NotEnoughFunds(Money, Money, TX),
     ParentTXAlreadyDisputed(TX),
     ParentTXNotDisputed(TX),
     ParentTXNotFound(TX),
+    TotalBelowFloor(TX),
+    TypeDisabled(TX),
+    DisputesFrozen(TX),
+    TooManyOpenDisputes(TX),
+    DuplicateTransaction(TX),
+    /// A balance would overflow or exceed `Config::max_balance`.
+    BalanceOverflow(TX),
+    /// A partial dispute for more than the parent transaction's amount.
+    DisputeAmountTooLarge(Money, TX),
+    /// A chargeback whose transaction wasn't disputed earlier in the
+    /// stream, see `Config::validate_chargeback_order`.
+    ChargebackOutOfOrder(TX),
+}
+
+impl TXError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TXError::AccountLocked(_) => "account_locked",
+            TXError::AccountNotFound(_) => "account_not_found",
+            TXError::ClientsDontMatch(_, _) => "clients_dont_match",
+            TXError::DepositLimitExceeded(_) => "deposit_limit_exceeded",
+            TXError::NotEnoughFunds(_, _, _) => "not_enough_funds",
+            TXError::ParentTXAlreadyDisputed(_) => "parent_tx_already_disputed",
+            TXError::ParentTXNotDisputed(_) => "parent_tx_not_disputed",
+            TXError::ParentTXNotFound(_) => "parent_tx_not_found",
+            TXError::TotalBelowFloor(_) => "total_below_floor",
+            TXError::TypeDisabled(_) => "type_disabled",
+            TXError::DisputesFrozen(_) => "disputes_frozen",
+            TXError::TooManyOpenDisputes(_) => "too_many_open_disputes",
+            TXError::DuplicateTransaction(_) => "duplicate_transaction",
+            TXError::BalanceOverflow(_) => "balance_overflow",
+            TXError::DisputeAmountTooLarge(..) => "dispute_amount_too_large",
+            TXError::ChargebackOutOfOrder(_) => "chargeback_out_of_order",
+        }
+    }
+
+    /// The transaction that failed.
+    pub fn transaction(&self) -> &TX {
+        match self {
+            TXError::AccountLocked(tx)
+            | TXError::AccountNotFound(tx)
+            | TXError::ClientsDontMatch(_, tx)
+            | TXError::DepositLimitExceeded(tx)
+            | TXError::NotEnoughFunds(_, _, tx)
+            | TXError::ParentTXAlreadyDisputed(tx)
+            | TXError::ParentTXNotDisputed(tx)
+            | TXError::ParentTXNotFound(tx)
+            | TXError::TotalBelowFloor(tx)
+            | TXError::TypeDisabled(tx)
+            | TXError::DisputesFrozen(tx)
+            | TXError::TooManyOpenDisputes(tx)
+            | TXError::DuplicateTransaction(tx)
+            | TXError::BalanceOverflow(tx)
+            | TXError::DisputeAmountTooLarge(_, tx)
+            | TXError::ChargebackOutOfOrder(tx) => tx,
+        }
+    }
 }
 
 impl fmt::Display for TXError {
@@ -204,9 +644,14 @@ impl fmt::Display for TXError {
                 "TransactionError: Clients don't match. Need {:?} have {:?}",
                 client, tx
             ),
+            TXError::DepositLimitExceeded(tx) => write!(
+                f,
+                "TransactionError: Deposit would exceed the client's deposit limit: {:?}",
+                tx
+            ),
             TXError::NotEnoughFunds(available, needed, tx) => write!(
                 f,
-                "TransactionError: Not enough funds. Have {:?} need {:?}. {:?}",
+                "TransactionError: Not enough funds. Have {} need {}. {:?}",
                 available, needed, tx
             ),
             TXError::ParentTXAlreadyDisputed(tx) => write!(
@@ -224,56 +669,534 @@ impl fmt::Display for TXError {
                 "TransactionError: Parent transaction not found: {:?}",
                 tx
             ),
+            TXError::TotalBelowFloor(tx) => write!(
+                f,
+                "TransactionError: Total would go below the configured floor: {:?}",
+                tx
+            ),
+            TXError::TypeDisabled(tx) => write!(
+                f,
+                "TransactionError: Transaction type is disabled: {:?}",
+                tx
+            ),
+            TXError::DisputesFrozen(tx) => write!(
+                f,
+                "TransactionError: Disputes are frozen for client: {:?}",
+                tx
+            ),
+            TXError::TooManyOpenDisputes(tx) => write!(
+                f,
+                "TransactionError: Client has too many open disputes: {:?}",
+                tx
+            ),
+            TXError::DuplicateTransaction(tx) => {
+                write!(f, "TransactionError: Transaction id already used: {:?}", tx)
+            }
+            TXError::BalanceOverflow(tx) => write!(
+                f,
+                "TransactionError: Balance would exceed the largest allowed amount: {:?}",
+                tx
+            ),
+            TXError::DisputeAmountTooLarge(parent_amount, tx) => write!(
+                f,
+                "TransactionError: Disputed amount is above the parent transaction's {}: {:?}",
+                parent_amount, tx
+            ),
+            TXError::ChargebackOutOfOrder(tx) => write!(
+                f,
+                "TransactionError: Chargeback without an earlier dispute in the stream: {:?}",
+                tx
+            ),
         }
     }
 }
 
+impl std::error::Error for TXError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
 impl TX {
     pub fn from_transaction(transaction: Transaction) -> Result<Self, TXBuildError> {
-        match transaction.typ.as_str() {
-            s if s == TXType::Deposit.as_str() => Ok(TX::Deposit(Deposit {
+        match transaction.typ {
+            TXType::Deposit => Ok(TX::Deposit(Deposit {
                 client: transaction.client,
                 tx: transaction.tx,
-                amount: transaction.amount.unwrap(),
+                amount: transaction.amount.ok_or(TXBuildError::MissingAmount)?,
             })),
-            s if s == TXType::Withdrawal.as_str() => Ok(TX::Withdrawal(Withdrawal {
+            TXType::Withdrawal => Ok(TX::Withdrawal(Withdrawal {
                 client: transaction.client,
                 tx: transaction.tx,
-                amount: transaction.amount.unwrap(),
+                amount: transaction.amount.ok_or(TXBuildError::MissingAmount)?,
+                destination: transaction.destination,
             })),
-            s if s == TXType::Dispute.as_str() => Ok(TX::Dispute(Dispute {
+            TXType::Dispute => Ok(TX::Dispute(Dispute {
                 client: transaction.client,
                 tx: transaction.tx,
+                amount: transaction.amount,
             })),
-            s if s == TXType::Resolve.as_str() => Ok(TX::Resolve(Resolve {
+            TXType::Resolve => Ok(TX::Resolve(Resolve {
                 client: transaction.client,
                 tx: transaction.tx,
             })),
-            s if s == TXType::Chargeback.as_str() => Ok(TX::Chargeback(Chargeback {
+            TXType::Chargeback => Ok(TX::Chargeback(Chargeback {
                 client: transaction.client,
                 tx: transaction.tx,
             })),
-            _ => Err(TXBuildError::InvalidTransaction),
+        }
+    }
+
+    /// Truncates the amount of a deposit, withdrawal or partial dispute to
+    /// `precision`, rejecting amounts that truncate to zero.
+    pub fn with_precision(mut self, precision: Precision) -> Result<Self, TXBuildError> {
+        if let TX::Deposit(Deposit { amount, .. })
+        | TX::Withdrawal(Withdrawal { amount, .. })
+        | TX::Dispute(Dispute {
+            amount: Some(amount),
+            ..
+        }) = &mut self
+        {
+            if *amount < precision.min_amount() {
+                return Err(TXBuildError::AmountBelowPrecision(*amount));
+            }
+            *amount = precision.apply(*amount);
+        }
+        Ok(self)
+    }
+
+    /// Rejects a deposit or withdrawal below `minimum`.
+    pub fn with_min_amount(self, minimum: Money) -> Result<Self, TXBuildError> {
+        match &self {
+            TX::Deposit(Deposit { amount, .. }) | TX::Withdrawal(Withdrawal { amount, .. })
+                if *amount < minimum =>
+            {
+                Err(TXBuildError::AmountBelowMinimum(*amount, minimum))
+            }
+            _ => Ok(self),
+        }
+    }
+
+    pub fn client(&self) -> u16 {
+        match self {
+            TX::Deposit(op) => op.client,
+            TX::Withdrawal(op) => op.client,
+            TX::Dispute(op) => op.client,
+            TX::Resolve(op) => op.client,
+            TX::Chargeback(op) => op.client,
+        }
+    }
+
+    pub fn tx(&self) -> u32 {
+        match self {
+            TX::Deposit(op) => op.tx,
+            TX::Withdrawal(op) => op.tx,
+            TX::Dispute(op) => op.tx,
+            TX::Resolve(op) => op.tx,
+            TX::Chargeback(op) => op.tx,
+        }
+    }
+
+    pub fn is_dispute_flow(&self) -> bool {
+        matches!(self, TX::Dispute(_) | TX::Resolve(_) | TX::Chargeback(_))
+    }
+
+    pub fn typ(&self) -> TXType {
+        match self {
+            TX::Deposit(_) => TXType::Deposit,
+            TX::Withdrawal(_) => TXType::Withdrawal,
+            TX::Dispute(_) => TXType::Dispute,
+            TX::Resolve(_) => TXType::Resolve,
+            TX::Chargeback(_) => TXType::Chargeback,
         }
     }
 
     pub fn name(&self) -> &'static str {
+        self.typ().as_str()
+    }
+}
+
+impl fmt::Display for TX {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            TX::Deposit(_) => TXType::Deposit.as_str(),
-            TX::Withdrawal(_) => TXType::Withdrawal.as_str(),
-            TX::Dispute(_) => TXType::Dispute.as_str(),
-            TX::Resolve(_) => TXType::Resolve.as_str(),
-            TX::Chargeback(_) => TXType::Chargeback.as_str(),
+            TX::Deposit(op) => write!(
+                f,
+                "{} client={} tx={} amount={}",
+                self.name(),
+                op.client,
+                op.tx,
+                op.amount
+            ),
+            TX::Withdrawal(op) => {
+                write!(
+                    f,
+                    "{} client={} tx={} amount={}",
+                    self.name(),
+                    op.client,
+                    op.tx,
+                    op.amount
+                )?;
+                match &op.destination {
+                    Some(destination) => write!(f, " destination={}", destination),
+                    None => Ok(()),
+                }
+            }
+            TX::Dispute(Dispute {
+                client,
+                tx,
+                amount: Some(amount),
+            }) => write!(
+                f,
+                "{} client={} tx={} amount={}",
+                self.name(),
+                client,
+                tx,
+                amount
+            ),
+            TX::Dispute(Dispute { client, tx, .. })
+            | TX::Resolve(Resolve { client, tx })
+            | TX::Chargeback(Chargeback { client, tx }) => {
+                write!(f, "{} client={} tx={}", self.name(), client, tx)
+            }
+        }
+    }
+}
+
+pub const SCALE: u32 = 4;
+
+const UNIT: i64 = 10i64.pow(SCALE);
+
+/// A fixed point amount with `SCALE` decimals, stored as a count of
+/// ten-thousandths of a unit.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_raw(ten_thousandths: i64) -> Self {
+        Money(ten_thousandths)
+    }
+
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / UNIT as f64
+    }
+
+    pub fn abs(self) -> Self {
+        Money(self.0.abs())
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, rhs: Money) -> Option<Money> {
+        self.0.checked_add(rhs.0).map(Money)
+    }
+
+    pub fn checked_sub(self, rhs: Money) -> Option<Money> {
+        self.0.checked_sub(rhs.0).map(Money)
+    }
+
+    /// Drops the decimals beyond `decimals`, rounding toward zero.
+    pub fn truncate_to(self, decimals: u32) -> Self {
+        let factor = 10i64.pow(SCALE - decimals.min(SCALE));
+        Money(self.0 / factor * factor)
+    }
+}
+
+/// Decimals amounts are processed and written with, for `--decimals`. At
+/// most `SCALE`, larger values are clamped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Precision(u32);
+
+impl Precision {
+    pub fn new(decimals: u32) -> Self {
+        Precision(decimals.min(SCALE))
+    }
+
+    pub fn decimals(self) -> u32 {
+        self.0
+    }
+
+    /// The smallest positive amount, e.g. 0.01 with two decimals.
+    pub fn min_amount(self) -> Money {
+        Money(10i64.pow(SCALE - self.0))
+    }
+
+    pub fn apply(self, amount: Money) -> Money {
+        amount.truncate_to(self.0)
+    }
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision(SCALE)
+    }
+}
+
+/// Precision discarded by truncating input amounts, in units of
+/// 10^-`TruncationLoss::SCALE`. Digits beyond those aren't counted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TruncationLoss(u128);
+
+impl TruncationLoss {
+    pub const SCALE: u32 = 18;
+
+    /// The part of the decimal amount `s` below `precision`, e.g. 0.00005
+    /// for `2500.12345` with four decimals. Invalid amounts lose nothing.
+    pub fn of(s: &str, precision: Precision) -> Self {
+        let digits = s.trim().trim_start_matches(['-', '+']);
+        let Some((_, frac)) = digits.split_once('.') else {
+            return TruncationLoss::default();
+        };
+        if !frac.chars().all(|c| c.is_ascii_digit()) {
+            return TruncationLoss::default();
+        }
+        let dropped: String = frac
+            .chars()
+            .skip(precision.decimals() as usize)
+            .chain(std::iter::repeat('0'))
+            .take((Self::SCALE - precision.decimals()) as usize)
+            .collect();
+        TruncationLoss(dropped.parse().unwrap_or_default())
+    }
+}
+
+impl AddAssign for TruncationLoss {
+    fn add_assign(&mut self, rhs: TruncationLoss) {
+        self.0 = self.0.saturating_add(rhs.0);
+    }
+}
+
+impl fmt::Display for TruncationLoss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = 10u128.pow(Self::SCALE);
+        let frac = format!("{:0width$}", self.0 % unit, width = Self::SCALE as usize);
+        let frac = frac.trim_end_matches('0');
+        let frac = if frac.is_empty() { "0" } else { frac };
+        write!(f, "{}.{}", self.0 / unit, frac)
+    }
+}
+
+/// Rounds to the nearest ten-thousandth. Meant for amounts written as float
+/// literals, input is parsed from its decimal string instead.
+impl From<f64> for Money {
+    fn from(f: f64) -> Self {
+        Money((f * UNIT as f64).round() as i64)
+    }
+}
+
+/// Parses a decimal such as `-12.5`, truncating decimals beyond `SCALE`.
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid amount: {:?}", s);
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if int.is_empty() && frac.is_empty()
+            || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let mut units: i64 = 0;
+        for c in int.chars() {
+            units = units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add(c as i64 - '0' as i64))
+                .ok_or_else(invalid)?;
+        }
+        let mut fraction = 0;
+        for (i, c) in frac
+            .chars()
+            .chain(std::iter::repeat('0'))
+            .take(SCALE as usize)
+            .enumerate()
+        {
+            fraction += (c as i64 - '0' as i64) * 10i64.pow(SCALE - 1 - i as u32);
+        }
+        let raw = units
+            .checked_mul(UNIT)
+            .and_then(|raw| raw.checked_add(fraction))
+            .ok_or_else(invalid)?;
+        Ok(Money(if negative { -raw } else { raw }))
+    }
+}
+
+/// Writes the amount with its significant decimals, and at least one.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let abs = self.0.unsigned_abs();
+        let unit = UNIT as u64;
+        let frac = format!("{:0width$}", abs % unit, width = SCALE as usize);
+        let frac = frac.trim_end_matches('0');
+        write!(
+            f,
+            "{}{}.{}",
+            if self.0 < 0 { "-" } else { "" },
+            abs / unit,
+            if frac.is_empty() { "0" } else { frac }
+        )
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+/// Reads decimal strings, and plain numbers from snapshots written before
+/// amounts were fixed point.
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MoneyVisitor;
+
+        impl Visitor<'_> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a decimal amount")
+            }
+
+            fn visit_str<E: Error>(self, v: &str) -> Result<Money, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: Error>(self, v: i64) -> Result<Money, E> {
+                v.checked_mul(UNIT)
+                    .map(Money)
+                    .ok_or_else(|| E::custom("amount out of range"))
+            }
+
+            fn visit_u64<E: Error>(self, v: u64) -> Result<Money, E> {
+                i64::try_from(v)
+                    .map_err(|_| E::custom("amount out of range"))
+                    .and_then(|v| self.visit_i64(v))
+            }
+
+            fn visit_f64<E: Error>(self, v: f64) -> Result<Money, E> {
+                Ok(Money::from(v))
+            }
+        }
+
+        d.deserialize_any(MoneyVisitor)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        self.0 += rhs.0;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        self.0 -= rhs.0;
+    }
+}
+
+/// Key of the transaction store. `client` is only set when transaction ids
+/// are unique per client rather than globally, see
+/// `Config::per_client_tx_ids`. Serialized as `tx` or `client:tx`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TxKey {
+    pub client: Option<u16>,
+    pub tx: u32,
+}
+
+impl From<u32> for TxKey {
+    fn from(tx: u32) -> Self {
+        TxKey { client: None, tx }
+    }
+}
+
+impl fmt::Display for TxKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.client {
+            Some(client) => write!(f, "{}:{}", client, self.tx),
+            None => write!(f, "{}", self.tx),
+        }
+    }
+}
+
+impl FromStr for TxKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |_| format!("Invalid transaction key: {}", s);
+        match s.split_once(':') {
+            Some((client, tx)) => Ok(TxKey {
+                client: Some(client.trim().parse().map_err(invalid)?),
+                tx: tx.trim().parse().map_err(invalid)?,
+            }),
+            None => Ok(TxKey::from(s.trim().parse::<u32>().map_err(invalid)?)),
         }
     }
 }
 
-pub fn truncate(f: f64) -> f64 {
-    (f * 10000.0).trunc() / 10000.0
+impl Serialize for TxKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TxKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
 }
 
-pub type Accounts = HashMap<u16, Account>;
-pub type Transactions = HashMap<u32, TXState>;
+pub type Accounts = BTreeMap<u16, Account>;
+pub type Transactions = HashMap<TxKey, TXState>;
 
 #[cfg(test)]
 mod tests {
@@ -301,10 +1224,12 @@ chargeback,1,1
         assert_eq!(
             transaction,
             Transaction {
-                typ: "deposit".to_string(),
+                typ: TXType::Deposit,
                 client: 1,
                 tx: 1,
-                amount: Some(2500.1234)
+                amount: Some(Money::from(2500.1234)),
+                destination: None,
+                currency: None,
             }
         );
 
@@ -312,10 +1237,12 @@ chargeback,1,1
         assert_eq!(
             transaction,
             Transaction {
-                typ: "withdrawal".to_string(),
+                typ: TXType::Withdrawal,
                 client: 1,
                 tx: 1,
-                amount: Some(1.0)
+                amount: Some(Money::from(1.0)),
+                destination: None,
+                currency: None,
             }
         );
 
@@ -323,10 +1250,12 @@ chargeback,1,1
         assert_eq!(
             transaction,
             Transaction {
-                typ: "dispute".to_string(),
+                typ: TXType::Dispute,
                 client: 1,
                 tx: 1,
-                amount: None
+                amount: None,
+                destination: None,
+                currency: None,
             }
         );
 
@@ -334,10 +1263,12 @@ chargeback,1,1
         assert_eq!(
             transaction,
             Transaction {
-                typ: "dispute".to_string(),
+                typ: TXType::Dispute,
                 client: 1,
                 tx: 1,
-                amount: None
+                amount: Some(Money::from(1.0)),
+                destination: None,
+                currency: None,
             }
         );
 
@@ -345,10 +1276,12 @@ chargeback,1,1
         assert_eq!(
             transaction,
             Transaction {
-                typ: "resolve".to_string(),
+                typ: TXType::Resolve,
                 client: 1,
                 tx: 1,
-                amount: None
+                amount: None,
+                destination: None,
+                currency: None,
             }
         );
 
@@ -356,10 +1289,12 @@ chargeback,1,1
         assert_eq!(
             transaction,
             Transaction {
-                typ: "chargeback".to_string(),
+                typ: TXType::Chargeback,
                 client: 1,
                 tx: 1,
-                amount: None
+                amount: None,
+                destination: None,
+                currency: None,
             }
         );
 
@@ -367,10 +1302,571 @@ chargeback,1,1
     }
 
     #[test]
-    fn test_truncate() {
-        assert_eq!(truncate(0.0001), 0.0001);
-        assert_eq!(truncate(0.00001), 0.0000);
-        assert_eq!(truncate(5.37895), 5.3789);
+    fn test_transaction_deserialize_destination() -> Result<(), Box<dyn Error>> {
+        let csv_data = "\
+type,client,tx,amount,destination
+withdrawal,1,2,1.0, ACME-001 
+deposit,1,1,1.0,ACME-001
+";
+
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv_data.as_bytes());
+        let mut transactions = reader.deserialize();
+
+        let transaction: Transaction = transactions.next().unwrap()?;
+        assert_eq!(transaction.destination, Some("ACME-001".to_string()));
+        assert_eq!(
+            TX::from_transaction(transaction)?.to_string(),
+            "withdrawal client=1 tx=2 amount=1.0 destination=ACME-001"
+        );
+
+        let transaction: Transaction = transactions.next().unwrap()?;
+        assert_eq!(transaction.destination, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_deserialize_spaced_header() -> Result<(), Box<dyn Error>> {
+        let csv_data = "\
+ type , Client ,tx , amount 
+withdrawal,2,7,3.5
+";
+
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv_data.as_bytes());
+        let transaction: Transaction = reader.deserialize().next().unwrap()?;
+        assert_eq!(
+            transaction,
+            Transaction {
+                typ: TXType::Withdrawal,
+                client: 2,
+                tx: 7,
+                amount: Some(Money::from(3.5)),
+                destination: None,
+                currency: None,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_deserialize_quoted_type() -> Result<(), Box<dyn Error>> {
+        let csv_data = "\
+type,client,tx,amount
+\"Deposit\",1,1,1.0
+'dispute',1,1,
+";
+
+        let mut reader = csv::ReaderBuilder::new()
+            .quoting(false)
+            .flexible(true)
+            .from_reader(csv_data.as_bytes());
+        let mut types = Vec::new();
+        for transaction in reader.deserialize::<Transaction>() {
+            types.push(TX::from_transaction(transaction?)?.typ());
+        }
+        assert_eq!(types, vec![TXType::Deposit, TXType::Dispute]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_amount() {
+        assert_eq!(validate_amount(" 1.5 "), Ok(Money::from(1.5)));
+        assert_eq!(validate_amount("0.0001"), Ok(Money::from_raw(1)));
+        assert_eq!(
+            validate_amount("-1.0"),
+            Err(AmountError::Negative("-1.0".to_string()))
+        );
+        assert_eq!(
+            validate_amount("-0.00001"),
+            Err(AmountError::Negative("-0.00001".to_string()))
+        );
+        assert_eq!(
+            validate_amount("0.0"),
+            Err(AmountError::Zero("0.0".to_string()))
+        );
+        assert_eq!(
+            validate_amount("-0"),
+            Err(AmountError::Zero("-0".to_string()))
+        );
+        assert_eq!(
+            validate_amount("0.00001"),
+            Err(AmountError::TooSmall("0.00001".to_string()))
+        );
+        assert_eq!(
+            validate_amount("abc"),
+            Err(AmountError::Invalid("abc".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_transaction_deserialize_invalid_amounts() {
+        let csv_data = "\
+type,client,tx,amount
+deposit,1,1,-1.0
+deposit,1,2,0.0
+deposit,1,3,0.00001
+withdrawal,1,4,0
+";
+        let mut reader = csv::ReaderBuilder::new().from_reader(csv_data.as_bytes());
+        let errors: Vec<String> = reader
+            .deserialize::<Transaction>()
+            .map(|transaction| transaction.unwrap_err().to_string())
+            .collect();
+        assert!(
+            errors[0].ends_with("Invalid amount \"-1.0\": negative"),
+            "{}",
+            errors[0]
+        );
+        assert!(
+            errors[1].ends_with("Invalid amount \"0.0\": zero"),
+            "{}",
+            errors[1]
+        );
+        assert!(
+            errors[2].ends_with("Invalid amount \"0.00001\": below the smallest amount of 0.0001"),
+            "{}",
+            errors[2]
+        );
+        assert!(
+            errors[3].ends_with("Invalid amount \"0\": zero"),
+            "{}",
+            errors[3]
+        );
+    }
+
+    #[test]
+    fn test_transaction_deserialize_major_minor() {
+        let csv_data = "\
+type,client,tx,amount_major,amount_minor
+deposit,1,1,5,2345
+deposit,1,2,0,50
+withdrawal,1,3,7,
+dispute,1,1,,
+deposit,1,4,5,10000
+deposit,1,5,0,0
+";
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(csv_data.as_bytes());
+        let amounts: Vec<Result<Option<Money>, _>> = reader
+            .deserialize::<Transaction>()
+            .map(|transaction| transaction.map(|transaction| transaction.amount))
+            .collect();
+        assert_eq!(amounts[0].as_ref().unwrap(), &Some(Money::from_raw(52_345)));
+        assert_eq!(amounts[1].as_ref().unwrap(), &Some(Money::from_raw(50)));
+        assert_eq!(amounts[2].as_ref().unwrap(), &Some(Money::from(7.0)));
+        assert_eq!(amounts[3].as_ref().unwrap(), &None);
+        assert!(amounts[4].is_err());
+        assert!(amounts[5].is_err());
+
+        let mut reader = csv::ReaderBuilder::new()
+            .from_reader("type,client,tx,amount,amount_major\ndeposit,1,1,1.0,1\n".as_bytes());
+        assert!(reader.deserialize::<Transaction>().next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_transaction_deserialize_column_orders() -> Result<(), Box<dyn Error>> {
+        let cases = [
+            "type,client,tx,amount\nwithdrawal,3,17,2.5\n",
+            "amount,tx,client,type\n2.5,17,3,withdrawal\n",
+            "client,type,amount,tx\n3,withdrawal,2.5,17\n",
+            "tx,amount,type,client\n17,2.5,withdrawal,3\n",
+            "somerandomfield,amount,client,tx,type\nrandominfo,2.5,3,17,withdrawal\n",
+            "type,somerandomfield,client,tx,amount\nwithdrawal,randominfo,3,17,2.5\n",
+        ];
+
+        for csv_data in cases {
+            let mut reader = csv::ReaderBuilder::new()
+                .flexible(true)
+                .from_reader(csv_data.as_bytes());
+            let transaction: Transaction = reader.deserialize().next().unwrap()?;
+            assert_eq!(
+                transaction,
+                Transaction {
+                    typ: TXType::Withdrawal,
+                    client: 3,
+                    tx: 17,
+                    amount: Some(Money::from(2.5)),
+                    destination: None,
+                    currency: None,
+                },
+                "{}",
+                csv_data
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_from_json_value() -> Result<(), Box<dyn Error>> {
+        let value = serde_json::json!({
+            "type": "deposit",
+            "client": "1",
+            "tx": 2,
+            "amount": "1.23456",
+        });
+        assert_eq!(
+            Transaction::from_json_value(&value)?,
+            Transaction {
+                typ: TXType::Deposit,
+                client: 1,
+                tx: 2,
+                amount: Some(Money::from(1.2345)),
+                destination: None,
+                currency: None,
+            }
+        );
+
+        let value = serde_json::json!({
+            "Type": "Withdrawal",
+            "client": 3,
+            "tx": "17",
+            "amount": 2.5,
+            "destination": "ACME-001",
+            "somerandomfield": null,
+        });
+        assert_eq!(
+            Transaction::from_json_value(&value)?,
+            Transaction {
+                typ: TXType::Withdrawal,
+                client: 3,
+                tx: 17,
+                amount: Some(Money::from(2.5)),
+                destination: Some("ACME-001".to_string()),
+                currency: None,
+            }
+        );
+
+        let value = serde_json::json!({ "type": "dispute", "client": 1, "tx": 2, "amount": null });
+        assert_eq!(Transaction::from_json_value(&value)?.amount, None);
+
+        for value in [
+            serde_json::json!({ "type": "deposit", "client": 1, "tx": 2, "amount": -1.0 }),
+            serde_json::json!({ "type": "deposit", "client": 70000, "tx": 2, "amount": 1.0 }),
+            serde_json::json!({ "type": "deposit", "client": 1, "amount": 1.0 }),
+            serde_json::json!(["deposit", 1, 2, 1.0]),
+        ] {
+            assert!(Transaction::from_json_value(&value).is_err(), "{}", value);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_deserialize_schema_versions() -> Result<(), Box<dyn Error>> {
+        let csv_data = "\
+type,client,tx,amount,currency
+deposit,1,1,2.5,eur
+withdrawal,1,2,1.0,
+dispute,1,1,,
+";
+        let reader = || {
+            csv::ReaderBuilder::new()
+                .flexible(true)
+                .from_reader(csv_data.as_bytes())
+        };
+
+        let v1: Vec<Transaction> = reader().deserialize().collect::<Result<_, _>>()?;
+        assert_eq!(v1.len(), 3);
+        assert!(v1.iter().all(|tx| tx.currency.is_none()));
+
+        let v2: Vec<Result<TransactionV2, _>> = reader().deserialize().collect();
+        assert_eq!(
+            v2[0].as_ref().unwrap().0,
+            Transaction {
+                typ: TXType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(Money::from(2.5)),
+                destination: None,
+                currency: Some("EUR".to_string()),
+            }
+        );
+        assert!(v2[1].is_err());
+        assert_eq!(v2[2].as_ref().unwrap().0.currency, None);
+
+        let mut reader =
+            csv::Reader::from_reader("type,client,tx,amount\ndeposit,1,1,2.5\n".as_bytes());
+        assert!(reader
+            .deserialize::<TransactionV2>()
+            .next()
+            .unwrap()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_deserialize_direction() {
+        let csv_data = "\
+type,direction,client,tx,amount
+,credit,1,1,2.5
+, Debit ,1,2,1.0
+dispute,,1,1,
+deposit,,1,3,1.0
+withdrawal,debit,1,4,1.0
+,sideways,1,5,1.0
+";
+        let v3: Vec<Result<TransactionV3, _>> = csv::Reader::from_reader(csv_data.as_bytes())
+            .deserialize()
+            .collect();
+        let typ = |i: usize| v3[i].as_ref().map(|tx| tx.0.typ).ok();
+        assert_eq!(typ(0), Some(TXType::Deposit));
+        assert_eq!(v3[0].as_ref().unwrap().0.amount, Some(Money::from(2.5)));
+        assert_eq!(typ(1), Some(TXType::Withdrawal));
+        assert_eq!(typ(2), Some(TXType::Dispute));
+        assert!(v3[3].is_err());
+        assert!(v3[4].is_err());
+        assert!(v3[5].is_err());
+
+        // Other schemas ignore the column.
+        let v1: Vec<Result<Transaction, _>> = csv::Reader::from_reader(csv_data.as_bytes())
+            .deserialize()
+            .collect();
+        assert!(v1[0].is_err());
+        assert_eq!(v1[3].as_ref().unwrap().typ, TXType::Deposit);
+    }
+
+    #[test]
+    fn test_transaction_deserialize_bom_crlf() -> Result<(), Box<dyn Error>> {
+        let csv_data =
+            "\u{feff}type,client,tx,amount\r\nDeposit ,1,1,2.5\r\nwithdrawal,1,2,1.0\r\n";
+        let transactions: Vec<Transaction> = csv::Reader::from_reader(csv_data.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].typ, TXType::Deposit);
+        assert_eq!(transactions[0].amount, Some(Money::from(2.5)));
+        assert_eq!(transactions[1].amount, Some(Money::from(1.0)));
+
+        // Records read with a `\n` terminator keep the `\r`, and headers
+        // taken verbatim keep the byte order mark.
+        let headers = csv::StringRecord::from(vec!["\u{feff}type", "client", "tx", "amount\r"]);
+        let record = csv::StringRecord::from(vec!["deposit\r", "1", "1", "2.5\r"]);
+        let transaction: Transaction = record.deserialize(Some(&headers))?;
+        assert_eq!(transaction.typ, TXType::Deposit);
+        assert_eq!(transaction.amount, Some(Money::from(2.5)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tx_type_from_str() {
+        for typ in TXType::ALL {
+            assert_eq!(typ.as_str().parse(), Ok(typ));
+        }
+        assert_eq!(" Chargeback ".parse(), Ok(TXType::Chargeback));
+        assert_eq!("DEPOSIT\r".parse(), Ok(TXType::Deposit));
+        assert!("transfer".parse::<TXType>().is_err());
+        assert!("".parse::<TXType>().is_err());
+    }
+
+    #[test]
+    fn test_truncation_loss() {
+        let precision = Precision::default();
+        let mut loss = TruncationLoss::default();
+        for amount in ["2500.12345", "1.00009", "3.5", "7", "0.000000001"] {
+            loss += TruncationLoss::of(amount, precision);
+        }
+        assert_eq!(loss.to_string(), "0.000140001");
+        assert_eq!(
+            TruncationLoss::of("12.3456", Precision::new(2)).to_string(),
+            "0.0056"
+        );
+        assert_eq!(
+            TruncationLoss::of("1.2.3", precision),
+            TruncationLoss::default()
+        );
+        assert_eq!(TruncationLoss::default().to_string(), "0.0");
+    }
+
+    #[test]
+    fn test_money_parse() {
+        let parse = |s: &str| s.parse::<Money>();
+        assert_eq!(parse("0.0001"), Ok(Money::from_raw(1)));
+        assert_eq!(parse("0.00001"), Ok(Money::ZERO));
+        assert_eq!(parse("5.37895"), Ok(Money::from_raw(53789)));
+        assert_eq!(parse(" 1.0001 "), Ok(Money::from_raw(10001)));
+        assert_eq!(parse("-2"), Ok(Money::from_raw(-20000)));
+        assert_eq!(parse(".5"), Ok(Money::from_raw(5000)));
+        assert_eq!(parse("3."), Ok(Money::from_raw(30000)));
+        for invalid in [
+            "",
+            ".",
+            "-",
+            "1e3",
+            "NaN",
+            "inf",
+            "1.2.3",
+            "99999999999999999",
+        ] {
+            assert!(parse(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_money_display() {
+        assert_eq!(Money::from_raw(10001).to_string(), "1.0001");
+        assert_eq!(Money::from_raw(15000).to_string(), "1.5");
+        assert_eq!(Money::ZERO.to_string(), "0.0");
+        assert_eq!(Money::from_raw(-75000).to_string(), "-7.5");
+        assert_eq!(Money::from_raw(-1).to_string(), "-0.0001");
+        assert_eq!(Money::from_raw(53789).truncate_to(2).to_string(), "5.37");
+        assert_eq!(Money::from_raw(-53789).truncate_to(0).to_string(), "-5.0");
+    }
+
+    #[test]
+    fn test_error_transaction() {
+        let tx = TX::Dispute(Dispute {
+            client: 1,
+            tx: 2,
+            amount: None,
+        });
+        let errors = [
+            TXError::AccountLocked(tx.clone()),
+            TXError::AccountNotFound(tx.clone()),
+            TXError::ClientsDontMatch(3, tx.clone()),
+            TXError::DepositLimitExceeded(tx.clone()),
+            TXError::NotEnoughFunds(Money::ZERO, Money::from(1.0), tx.clone()),
+            TXError::ParentTXAlreadyDisputed(tx.clone()),
+            TXError::ParentTXNotDisputed(tx.clone()),
+            TXError::ParentTXNotFound(tx.clone()),
+            TXError::TotalBelowFloor(tx.clone()),
+            TXError::TypeDisabled(tx.clone()),
+            TXError::DisputesFrozen(tx.clone()),
+            TXError::TooManyOpenDisputes(tx.clone()),
+            TXError::DuplicateTransaction(tx.clone()),
+        ];
+        for err in &errors {
+            assert_eq!(err.transaction(), &tx, "{}", err.kind());
+        }
+    }
+
+    #[test]
+    fn test_errors_box_into_dyn_error() {
+        let errors: Vec<Box<dyn Error>> = vec![
+            Box::new(TXBuildError::InvalidTransaction),
+            Box::new(TXError::DisputesFrozen(TX::Dispute(Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }))),
+        ];
+        for error in errors {
+            assert!(error.source().is_none());
+            assert!(error.to_string().contains("Error: "));
+        }
+    }
+
+    #[test]
+    fn test_precision() {
+        let precision = Precision::new(2);
+        assert_eq!(precision.min_amount(), Money::from_raw(100));
+        assert_eq!(Precision::new(12), Precision::default());
+        assert_eq!(Precision::default().min_amount(), Money::from_raw(1));
+
+        let deposit = |amount| {
+            TX::Deposit(Deposit {
+                client: 1,
+                tx: 1,
+                amount,
+            })
+        };
+        assert_eq!(
+            deposit(Money::from_raw(12_345)).with_precision(precision),
+            Ok(deposit(Money::from_raw(12_300)))
+        );
+        assert_eq!(
+            deposit(Money::from_raw(99)).with_precision(precision),
+            Err(TXBuildError::AmountBelowPrecision(Money::from_raw(99)))
+        );
+        let dispute = TX::Dispute(Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        });
+        assert_eq!(dispute.clone().with_precision(precision), Ok(dispute));
+    }
+
+    #[test]
+    fn test_with_min_amount() {
+        let minimum = Money::from(0.01);
+        let withdrawal = |amount: Money| {
+            TX::Withdrawal(Withdrawal {
+                client: 1,
+                tx: 1,
+                amount,
+                destination: None,
+            })
+        };
+        assert_eq!(
+            withdrawal(Money::from(0.01)).with_min_amount(minimum),
+            Ok(withdrawal(Money::from(0.01)))
+        );
+        let error = withdrawal(Money::from(0.0099))
+            .with_min_amount(minimum)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            TXBuildError::AmountBelowMinimum(Money::from(0.0099), minimum)
+        );
+        assert_eq!(
+            error.to_string(),
+            "ValidationError: Amount 0.0099 is below the configured minimum of 0.01"
+        );
+        let dispute = TX::Dispute(Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        });
+        assert_eq!(dispute.clone().with_min_amount(minimum), Ok(dispute));
+    }
+
+    #[test]
+    fn test_from_transaction_missing_amount() {
+        for typ in [TXType::Deposit, TXType::Withdrawal] {
+            let transaction = Transaction {
+                typ,
+                client: 1,
+                tx: 1,
+                amount: None,
+                destination: None,
+                currency: None,
+            };
+            assert_eq!(
+                TX::from_transaction(transaction),
+                Err(TXBuildError::MissingAmount)
+            );
+        }
+    }
+
+    #[test]
+    fn test_assert_balance_eq() {
+        assert_balance_eq!(Money::from_raw(15000), 1.5);
+        assert_balance_eq!(Money::from_raw(15001), 1.5, Money::from_raw(1));
+        let result =
+            std::panic::catch_unwind(|| assert_balance_eq!(Money::from_raw(15002), 1.5, 0.0001));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_money_exact_arithmetic() {
+        let mut total = Money::ZERO;
+        for _ in 0..10 {
+            total += "0.1".parse().unwrap();
+        }
+        assert_eq!(total, Money::from_raw(10000));
+        assert_eq!(
+            Money::from(0.1) + Money::from(0.2) - Money::from(0.3),
+            Money::ZERO
+        );
     }
 
     #[test]
@@ -379,7 +1875,7 @@ chargeback,1,1
             TX::Deposit(Deposit {
                 client: 1,
                 tx: 1,
-                amount: 0.0001
+                amount: Money::from(0.0001)
             })
             .name(),
             "deposit"
@@ -388,12 +1884,21 @@ chargeback,1,1
             TX::Withdrawal(Withdrawal {
                 client: 1,
                 tx: 1,
-                amount: 0.0001
+                amount: Money::from(0.0001),
+                destination: None,
             })
             .name(),
             "withdrawal"
         );
-        assert_eq!(TX::Dispute(Dispute { client: 1, tx: 1 }).name(), "dispute");
+        assert_eq!(
+            TX::Dispute(Dispute {
+                client: 1,
+                tx: 1,
+                amount: None
+            })
+            .name(),
+            "dispute"
+        );
         assert_eq!(TX::Resolve(Resolve { client: 1, tx: 1 }).name(), "resolve");
         assert_eq!(
             TX::Chargeback(Chargeback { client: 1, tx: 1 }).name(),
@@ -0,0 +1,321 @@
+use std::io::{self, Write};
+use std::process::{Command, Output, Stdio};
+
+use tempfile::NamedTempFile;
+
+fn run(input: &str, args: &[&str]) -> Output {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(input.as_bytes()).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_transaction_processor"))
+        .arg(file.path())
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn account_row(stdout: &[u8], client: &str) -> Option<String> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .find(|line| line.split(',').next() == Some(client))
+        .map(str::to_string)
+}
+
+#[test]
+fn test_bad_client_does_not_affect_others() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,abc
+deposit,2,2,10.0
+withdrawal,1,3,-5.0
+dispute,1,99,
+deposit,2,4,5.5
+chargeback,1,2,
+bogus,1,5,1.0
+withdrawal,2,6,3.0
+resolve,1,2,
+deposit,1,,1.0
+dispute,2,4,
+withdrawal,1,7,100.0
+";
+    let output = run(input, &[]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        account_row(&output.stdout, "2").as_deref(),
+        Some("2,7.0,5.5,12.5,false")
+    );
+    assert_eq!(account_row(&output.stdout, "1"), None);
+    assert!(!output.stderr.is_empty());
+}
+
+fn run_stdin(input: &str, args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transaction_processor"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The binary may exit before reading stdin, e.g. when rejecting its
+    // arguments, so a broken pipe isn't a failure.
+    match child.stdin.take().unwrap().write_all(input.as_bytes()) {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        written => written.unwrap(),
+    }
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_stdin_input() {
+    let input = "type,client,tx,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,1.0\n";
+    for args in [&["-"][..], &[]] {
+        let output = run_stdin(input, args);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n"
+        );
+    }
+
+    let output = run_stdin(input, &["-", "--check-references"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_missing_input_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_processor"))
+        .arg("does/not/exist.csv")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("could not open input file 'does/not/exist.csv': "),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn test_flush_every_ends_with_every_account() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+dispute,1,1,
+";
+    let output = run(input, &["--flush-every", "2"]);
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "client,available,held,total,locked");
+    assert_eq!(lines.len(), 5);
+    assert_eq!(
+        lines[3..],
+        vec!["1,0.0,1.0,1.0,false", "2,2.0,0.0,2.0,false"]
+    );
+}
+
+#[test]
+fn test_reject_on_dangling() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,5.0
+dispute,1,1,
+deposit,2,2,3.0
+chargeback,2,9,
+";
+    let output = run(input, &["--reject-on-dangling"]);
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Rejecting input: 1 dangling references"));
+
+    let output = run(input, &["--check-references"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        account_row(&output.stdout, "1").as_deref(),
+        Some("1,0.0,5.0,5.0,false")
+    );
+}
+
+#[test]
+fn test_stats_columns() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,2,2.5
+withdrawal,1,3,1.0
+withdrawal,1,4,100.0
+dispute,1,2,
+chargeback,1,2,
+deposit,2,5,1.0
+";
+    let output = run(input, &["--stats"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "\
+client,available,held,total,locked,deposit_count,withdrawal_count,total_deposited
+1,4.0,0.0,4.0,true,2,1,7.5
+2,1.0,0.0,1.0,false,1,0,1.0
+"
+    );
+
+    let output = run(input, &[]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().next(),
+        Some("client,available,held,total,locked")
+    );
+}
+
+#[test]
+fn test_multiple_input_files() {
+    let mut first = NamedTempFile::new().unwrap();
+    first
+        .write_all(b"type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\n")
+        .unwrap();
+    let mut second = NamedTempFile::new().unwrap();
+    second
+        .write_all(b"type,client,tx,amount\ndispute,1,1,\nwithdrawal,2,3,0.5\n")
+        .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_processor"))
+        .arg(first.path())
+        .arg("does/not/exist.csv")
+        .arg(second.path())
+        .arg("--check-references")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "\
+client,available,held,total,locked
+1,0.0,5.0,5.0,false
+2,0.5,0.0,0.5,false
+"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("could not open input file 'does/not/exist.csv': "),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.lines().count(), 1);
+}
+
+#[test]
+fn test_error_exit_code_and_strict() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,9.0
+deposit,2,3,1.0
+";
+    let output = run(input, &["--error-exit-code", "3"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "\
+client,available,held,total,locked
+1,5.0,0.0,5.0,false
+2,1.0,0.0,1.0,false
+"
+    );
+
+    let output = run(input, &["--strict"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Aborting after the first failed row"),
+        "{}",
+        stderr
+    );
+
+    let output = run("type,client,tx,amount\ndeposit,1,1,5.0\n", &["--strict"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_error_report_on_every_exit_path() {
+    let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,x,2,1.0\n";
+    for args in [&["--strict"][..], &["--validate-only"], &[]] {
+        let report = NamedTempFile::new().unwrap();
+        let path = report.path().to_str().unwrap();
+        let output = run(input, &[args, &["--error-report", path]].concat());
+
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(report.as_array().unwrap().len(), 1, "{:?}", args);
+    }
+}
+
+#[test]
+fn test_missing_input_file_honours_error_options() {
+    let report = NamedTempFile::new().unwrap();
+    let path = report.path().to_str().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_transaction_processor"))
+        .args(["does/not/exist.csv", "--error-exit-code", "3"])
+        .args(["--error-report", path])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(report[0]["kind"], "open");
+    assert!(report[0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("could not open input file 'does/not/exist.csv': "));
+}
+
+#[test]
+fn test_manifest_records_decimals() {
+    let manifest = NamedTempFile::new().unwrap();
+    let path = manifest.path().to_str().unwrap();
+    let output = run(
+        "type,client,tx,amount\ndeposit,1,1,1.2345\n",
+        &["--decimals", "2", "--manifest", path],
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        account_row(&output.stdout, "1").as_deref(),
+        Some("1,1.23,0.0,1.23,false")
+    );
+    let manifest: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(manifest["config"]["scale"], 2);
+}
+
+#[test]
+fn test_dense_clients_match_default() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,3,2,2.0
+dispute,1,1,
+withdrawal,3,3,1.0
+deposit,700,4,1.0
+";
+    let expected = run(input, &[]);
+    for range in ["4", "1000"] {
+        let output = run(input, &["--dense-clients", range]);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, expected.stdout, "{}", range);
+    }
+}